@@ -1,4 +1,5 @@
 use ahenk::cli::{commands, config::Config, errors::CliResult, output};
+use ahenk::HybridLogicalClock;
 use clap::{Parser, Subcommand};
 use std::process;
 
@@ -121,9 +122,12 @@ enum Commands {
 
     /// View operation log
     Oplog {
-        /// Show entries since timestamp
+        #[command(subcommand)]
+        command: Option<OplogCommands>,
+
+        /// Show entries since HLC (RFC 3339 time, `<time>#<counter>` or raw value)
         #[arg(long)]
-        since: Option<i64>,
+        since: Option<HybridLogicalClock>,
 
         /// Filter by device ID
         #[arg(long)]
@@ -134,6 +138,17 @@ enum Commands {
         limit: usize,
     },
 
+    /// Compact the oplog below the point every peer has acknowledged
+    Gc,
+
+    /// Inspect remote operations held back by merge
+    #[command(subcommand)]
+    Quarantine(QuarantineCommands),
+
+    /// Create or restore snapshot checkpoints
+    #[command(subcommand)]
+    Snapshot(SnapshotCommands),
+
     /// Show system information
     Info,
 
@@ -181,6 +196,57 @@ enum PeerCommands {
     },
 }
 
+#[derive(Subcommand)]
+enum QuarantineCommands {
+    /// List quarantined operations
+    List,
+
+    /// Accept a quarantined operation into the oplog
+    Release {
+        /// Operation ID to release
+        op_id: String,
+    },
+
+    /// Permanently drop a quarantined operation
+    Discard {
+        /// Operation ID to discard
+        op_id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum OplogCommands {
+    /// Show a table's rows as they were at a point in time
+    At {
+        /// Point in time (RFC 3339 time, `<time>#<counter>` or raw HLC value)
+        time: String,
+
+        /// Table to reconstruct
+        #[arg(long)]
+        table: String,
+
+        /// Only show this entity
+        #[arg(long)]
+        id: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum SnapshotCommands {
+    /// Take a checkpoint of the local state
+    Create {
+        /// Also write the snapshot to this file
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+
+    /// Bootstrap this device from a snapshot file
+    Restore {
+        /// Snapshot file to restore
+        path: String,
+    },
+}
+
 #[derive(Subcommand)]
 enum DeviceCommands {
     /// List user devices
@@ -323,10 +389,31 @@ async fn main() {
         } => commands::logs::view(follow, lines, level.as_deref(), &config).await,
         Commands::Query { sql } => commands::utils::query(&sql, cli.json, &config).await,
         Commands::Oplog {
+            command: Some(OplogCommands::At { time, table, id }),
+            ..
+        } => commands::utils::oplog_at(&time, &table, id.as_deref(), cli.json, &config).await,
+        Commands::Oplog {
+            command: None,
             since,
             device,
             limit,
         } => commands::utils::oplog(since, device.as_deref(), limit, cli.json, &config).await,
+        Commands::Gc => commands::utils::gc(cli.json, &config).await,
+        Commands::Quarantine(quarantine_cmd) => match quarantine_cmd {
+            QuarantineCommands::List => commands::quarantine::list(cli.json, &config).await,
+            QuarantineCommands::Release { op_id } => {
+                commands::quarantine::release(&op_id, &config).await
+            }
+            QuarantineCommands::Discard { op_id } => {
+                commands::quarantine::discard(&op_id, &config).await
+            }
+        },
+        Commands::Snapshot(snapshot_cmd) => match snapshot_cmd {
+            SnapshotCommands::Create { output } => {
+                commands::snapshot::create(output.as_deref(), cli.json, &config).await
+            }
+            SnapshotCommands::Restore { path } => commands::snapshot::restore(&path, &config).await,
+        },
         Commands::Info => commands::utils::info(cli.json).await,
         Commands::Doctor => commands::utils::doctor(&config).await,
         Commands::Export { path } => commands::utils::export(&path, &config).await,
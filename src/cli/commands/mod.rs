@@ -4,5 +4,7 @@ pub mod device;
 pub mod init;
 pub mod logs;
 pub mod peer;
+pub mod quarantine;
+pub mod snapshot;
 pub mod sync;
 pub mod utils;
@@ -0,0 +1,90 @@
+use crate::cli::config::Config;
+use crate::cli::errors::{CliError, CliResult};
+use crate::cli::output;
+use crate::crdt::{discard_quarantined, list_quarantined, release_quarantined, MergeRegistry};
+use crate::db::operations::initialize_database;
+use crate::HybridLogicalClock;
+
+pub async fn list(json: bool, config: &Config) -> CliResult<()> {
+    let db_path = config.db_path();
+    let conn = initialize_database(&db_path).map_err(|e| CliError::DatabaseError(e.to_string()))?;
+
+    let ops = list_quarantined(&conn).map_err(|e| CliError::DatabaseError(e.to_string()))?;
+
+    if json {
+        let ops_json: Vec<_> = ops
+            .iter()
+            .map(|q| {
+                serde_json::json!({
+                    "id": q.entry.id.to_string(),
+                    "device_id": q.entry.device_id.to_string(),
+                    "timestamp": q.entry.timestamp,
+                    "hlc": HybridLogicalClock::from_timestamp(q.entry.timestamp).to_string(),
+                    "table": q.entry.table,
+                    "op_type": q.entry.op_type,
+                    "data": q.entry.data,
+                    "reason": q.reason,
+                    "quarantined_at": q.quarantined_at,
+                })
+            })
+            .collect();
+        output::json(&serde_json::json!(ops_json));
+    } else {
+        if ops.is_empty() {
+            output::info("No quarantined operations");
+            return Ok(());
+        }
+
+        let mut table = output::create_table(vec!["ID", "Device", "HLC", "Table", "Op", "Reason"]);
+
+        for q in ops {
+            table.add_row(prettytable::Row::new(vec![
+                prettytable::Cell::new(&q.entry.id.to_string()),
+                prettytable::Cell::new(&q.entry.device_id.to_string()[..8]),
+                prettytable::Cell::new(
+                    &HybridLogicalClock::from_timestamp(q.entry.timestamp).to_string(),
+                ),
+                prettytable::Cell::new(&q.entry.table),
+                prettytable::Cell::new(&q.entry.op_type),
+                prettytable::Cell::new(&q.reason),
+            ]));
+        }
+
+        table.printstd();
+    }
+
+    Ok(())
+}
+
+pub async fn release(op_id: &str, config: &Config) -> CliResult<()> {
+    let op_uuid = uuid::Uuid::parse_str(op_id)
+        .map_err(|_| CliError::ValidationError("Invalid operation ID".to_string()))?;
+
+    let db_path = config.db_path();
+    let mut conn =
+        initialize_database(&db_path).map_err(|e| CliError::DatabaseError(e.to_string()))?;
+
+    let entry = release_quarantined(&mut conn, op_uuid, &MergeRegistry::default())
+        .map_err(|e| CliError::DatabaseError(e.to_string()))?;
+
+    output::success(&format!(
+        "Released operation {} ({} on {}) into the oplog",
+        entry.id, entry.op_type, entry.table
+    ));
+
+    Ok(())
+}
+
+pub async fn discard(op_id: &str, config: &Config) -> CliResult<()> {
+    let op_uuid = uuid::Uuid::parse_str(op_id)
+        .map_err(|_| CliError::ValidationError("Invalid operation ID".to_string()))?;
+
+    let db_path = config.db_path();
+    let conn = initialize_database(&db_path).map_err(|e| CliError::DatabaseError(e.to_string()))?;
+
+    discard_quarantined(&conn, op_uuid).map_err(|e| CliError::DatabaseError(e.to_string()))?;
+
+    output::success(&format!("Discarded quarantined operation {}", op_id));
+
+    Ok(())
+}
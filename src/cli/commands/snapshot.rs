@@ -0,0 +1,77 @@
+use crate::cli::config::Config;
+use crate::cli::errors::{CliError, CliResult};
+use crate::cli::output;
+use crate::crdt::{checkpoint, restore_snapshot, DeviceClock, MergeRegistry, Snapshot};
+use crate::db::operations::initialize_database;
+use std::fs;
+
+pub async fn create(path: Option<&str>, json: bool, config: &Config) -> CliResult<()> {
+    let db_path = config.db_path();
+    let conn = initialize_database(&db_path).map_err(|e| CliError::DatabaseError(e.to_string()))?;
+
+    let snapshot = checkpoint(&conn, &MergeRegistry::default())
+        .map_err(|e| CliError::DatabaseError(e.to_string()))?;
+
+    if let Some(path) = path {
+        let bytes = snapshot
+            .to_bytes()
+            .map_err(|e| CliError::DatabaseError(e.to_string()))?;
+        fs::write(path, bytes)?;
+    }
+
+    if json {
+        output::json(&serde_json::json!({
+            "id": snapshot.id.to_string(),
+            "created_at": snapshot.created_at.to_rfc3339(),
+            "version_vector": snapshot.version_vector,
+            "entries": snapshot.entries.len(),
+            "compacted": snapshot.compacted.len(),
+            "path": path,
+        }));
+    } else {
+        output::success(&format!(
+            "Created snapshot {} ({} operations, {} compacted)",
+            snapshot.id,
+            snapshot.entries.len(),
+            snapshot.compacted.len()
+        ));
+        if let Some(path) = path {
+            output::info(&format!("Snapshot written to {}", path));
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn restore(path: &str, config: &Config) -> CliResult<()> {
+    let device_config = config.device.as_ref().ok_or_else(|| {
+        CliError::ConfigError("Device not configured. Run 'ahenk-cli init' first.".to_string())
+    })?;
+    let device_id = uuid::Uuid::parse_str(&device_config.id)
+        .map_err(|_| CliError::ConfigError("Invalid device ID".to_string()))?;
+
+    let bytes = fs::read(path)?;
+    let snapshot =
+        Snapshot::from_bytes(&bytes).map_err(|e| CliError::ValidationError(e.to_string()))?;
+
+    let db_path = config.db_path();
+    let mut conn =
+        initialize_database(&db_path).map_err(|e| CliError::DatabaseError(e.to_string()))?;
+
+    output::step(&format!("Restoring snapshot {} from {}", snapshot.id, path));
+
+    restore_snapshot(
+        &mut conn,
+        &DeviceClock::new(device_id),
+        &MergeRegistry::default(),
+        &snapshot,
+    )
+    .map_err(|e| CliError::DatabaseError(e.to_string()))?;
+
+    output::success(&format!(
+        "Restored {} operations; only later operations will be synced",
+        snapshot.entries.len()
+    ));
+
+    Ok(())
+}
@@ -1,6 +1,7 @@
 use crate::cli::config::Config;
 use crate::cli::errors::{CliError, CliResult};
 use crate::cli::output;
+use crate::crdt::{compact_oplog, table_at, HybridLogicalClock, MergeRegistry};
 use crate::db::operations::initialize_database;
 use rusqlite::params;
 use std::fs;
@@ -69,7 +70,7 @@ pub async fn query(sql: &str, json: bool, config: &Config) -> CliResult<()> {
 }
 
 pub async fn oplog(
-    since: Option<i64>,
+    since: Option<HybridLogicalClock>,
     device: Option<&str>,
     limit: usize,
     json: bool,
@@ -82,8 +83,8 @@ pub async fn oplog(
         "SELECT id, device_id, timestamp, table_name, op_type, data FROM oplog".to_string();
     let mut conditions = Vec::new();
 
-    if let Some(ts) = since {
-        conditions.push(format!("timestamp > {}", ts));
+    if let Some(hlc) = since {
+        conditions.push(format!("timestamp > {}", hlc.to_timestamp()));
     }
 
     if let Some(dev) = device {
@@ -127,6 +128,7 @@ pub async fn oplog(
                     "id": id,
                     "device_id": device_id,
                     "timestamp": timestamp,
+                    "hlc": HybridLogicalClock::from_timestamp(*timestamp).to_string(),
                     "table": table,
                     "op_type": op_type,
                     "data": data,
@@ -140,11 +142,11 @@ pub async fn oplog(
             return Ok(());
         }
 
-        let mut table = output::create_table(vec!["Timestamp", "Table", "Op Type", "Device ID"]);
+        let mut table = output::create_table(vec!["HLC", "Table", "Op Type", "Device ID"]);
 
         for (_, device_id, timestamp, table_name, op_type, _) in results {
             table.add_row(prettytable::Row::new(vec![
-                prettytable::Cell::new(&timestamp.to_string()),
+                prettytable::Cell::new(&HybridLogicalClock::from_timestamp(timestamp).to_string()),
                 prettytable::Cell::new(&table_name),
                 prettytable::Cell::new(&op_type),
                 prettytable::Cell::new(&device_id[..8]), // Show first 8 chars
@@ -157,6 +159,93 @@ pub async fn oplog(
     Ok(())
 }
 
+pub async fn oplog_at(
+    time: &str,
+    table: &str,
+    id: Option<&str>,
+    json: bool,
+    config: &Config,
+) -> CliResult<()> {
+    let mut at: HybridLogicalClock = time
+        .parse()
+        .map_err(|e: crate::crdt::ParseHlcError| CliError::ValidationError(e.to_string()))?;
+    // A plain time includes every operation in that millisecond
+    if !time.contains('#') && !time.trim().bytes().all(|b| b.is_ascii_digit()) {
+        at = HybridLogicalClock::from_parts(at.physical_time(), u16::MAX);
+    }
+
+    let db_path = config.db_path();
+    let conn = initialize_database(&db_path).map_err(|e| CliError::DatabaseError(e.to_string()))?;
+
+    let mut rows = table_at(&conn, &MergeRegistry::default(), table, at)
+        .map_err(|e| CliError::DatabaseError(e.to_string()))?;
+    if let Some(id) = id {
+        rows.retain(|key, _| key == id);
+    }
+
+    if json {
+        output::json(&serde_json::json!(rows));
+    } else {
+        if rows.is_empty() {
+            output::info(&format!("No rows in '{}' at {}", table, at));
+            return Ok(());
+        }
+
+        let mut out = output::create_table(vec!["ID", "Row"]);
+
+        for (key, row) in rows {
+            out.add_row(prettytable::Row::new(vec![
+                prettytable::Cell::new(&key),
+                prettytable::Cell::new(&row.to_string()),
+            ]));
+        }
+
+        out.printstd();
+    }
+
+    Ok(())
+}
+
+pub async fn gc(json: bool, config: &Config) -> CliResult<()> {
+    let device_config = config.device.as_ref().ok_or_else(|| {
+        CliError::ConfigError("Device not configured. Run 'ahenk-cli init' first.".to_string())
+    })?;
+    let device_id = uuid::Uuid::parse_str(&device_config.id)
+        .map_err(|_| CliError::ConfigError("Invalid device ID".to_string()))?;
+
+    let db_path = config.db_path();
+    let mut conn =
+        initialize_database(&db_path).map_err(|e| CliError::DatabaseError(e.to_string()))?;
+
+    let stats = compact_oplog(&mut conn, device_id, &MergeRegistry::default())
+        .map_err(|e| CliError::DatabaseError(e.to_string()))?;
+
+    if json {
+        output::json(&serde_json::json!({
+            "stable_point": stats.stable_point.map(|hlc| hlc.to_string()),
+            "superseded_removed": stats.superseded_removed,
+            "tombstones_removed": stats.tombstones_removed,
+        }));
+    } else {
+        match stats.stable_point {
+            Some(stable_point) => {
+                output::info(&format!("Stable point: {}", stable_point));
+                output::success(&format!(
+                    "Removed {} superseded operations and {} tombstones",
+                    stats.superseded_removed, stats.tombstones_removed
+                ));
+            }
+            None => {
+                output::warning(
+                    "No stable point: every known peer must acknowledge the oplog first",
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
 pub async fn info(json: bool) -> CliResult<()> {
     let version = env!("CARGO_PKG_VERSION");
     let system = sysinfo::System::new_all();
@@ -0,0 +1,384 @@
+//! Range-fingerprint anti-entropy over the oplog.
+//!
+//! Version-vector sync only repairs holes in a device's operations when they
+//! depend on their predecessors (see [`causal`](super::causal)). Operations
+//! from peers that do not chain them can be lost while later ones advance the
+//! requester's version vector past them, and are then never requested again.
+//! Anti-entropy compares the oplogs themselves instead.
+//!
+//! Operations are bucketed by HLC timestamp into half-open ranges
+//! `[start, end)`. A range is summarized by a [`Fingerprint`]: the number of
+//! operations in it and an order-independent hash of their IDs. Two peers
+//! exchange fingerprints ([`reconcile`]):
+//!
+//! - equal fingerprints: the range is in sync and is dropped
+//! - different fingerprints over many operations: the range is split at
+//!   quantiles of the local timestamps and the sub-range fingerprints are
+//!   sent back, so the search only descends into divergent ranges
+//! - different fingerprints over few operations: the operation IDs are sent
+//!   back, and each side sends the operations the other lacks
+//!
+//! Operations at or below the `floor` (the highest stable point of either
+//! peer) are skipped, since compaction may have removed them on one side
+//! only and re-sending them would resurrect compacted history.
+
+use crate::db::operations::row_to_oplog_entry;
+use crate::error::Result;
+use crate::models::OplogEntry;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// Number of sub-ranges a divergent range is split into
+pub const BRANCH_FACTOR: usize = 16;
+
+/// Largest range, in operations, whose IDs are exchanged instead of split
+pub const LEAF_SIZE: u64 = 32;
+
+/// Summary of the operations in a timestamp range
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// Number of operations in the range
+    pub count: u64,
+    /// XOR of the hashed operation IDs
+    pub hash: u64,
+}
+
+impl Fingerprint {
+    /// Add an operation ID to the fingerprint
+    pub fn insert(&mut self, id: Uuid) {
+        let (hi, lo) = id.as_u64_pair();
+        self.count += 1;
+        self.hash ^= mix(hi ^ mix(lo));
+    }
+}
+
+/// What one peer knows about a timestamp range
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum RangeContent {
+    /// Fingerprint of the range
+    Fingerprint(Fingerprint),
+    /// Every operation ID in the range
+    Ids(Vec<Uuid>),
+}
+
+/// A half-open HLC timestamp range `[start, end)` and its content
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RangeSummary {
+    /// First timestamp in the range
+    pub start: i64,
+    /// First timestamp after the range
+    pub end: i64,
+    /// Fingerprint or IDs of the operations in the range
+    pub content: RangeContent,
+}
+
+/// Reply to a set of range summaries
+#[derive(Debug, Clone, Default)]
+pub struct Reconciliation {
+    /// Ranges that still differ, to send back to the peer
+    pub ranges: Vec<RangeSummary>,
+    /// Operations the peer is known to be missing
+    pub entries: Vec<OplogEntry>,
+}
+
+impl Reconciliation {
+    /// Whether the peers are in sync over every range that was compared
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty() && self.entries.is_empty()
+    }
+}
+
+/// Fingerprint of the operations in `[start, end)` above `floor`
+pub fn range_fingerprint(
+    conn: &Connection,
+    floor: i64,
+    start: i64,
+    end: i64,
+) -> Result<Fingerprint> {
+    let mut fingerprint = Fingerprint::default();
+    for id in range_ids(conn, floor, start, end)? {
+        fingerprint.insert(id);
+    }
+    Ok(fingerprint)
+}
+
+/// Summary of the whole oplog above `floor`, to start a reconciliation
+pub fn root_range(conn: &Connection, floor: i64) -> Result<RangeSummary> {
+    let start = floor.saturating_add(1);
+    Ok(RangeSummary {
+        start,
+        end: i64::MAX,
+        content: RangeContent::Fingerprint(range_fingerprint(conn, floor, start, i64::MAX)?),
+    })
+}
+
+/// Compare a peer's range summaries with the local oplog.
+///
+/// Only operations above `floor` are considered. Remote IDs that are in
+/// the local quarantine or causal delivery buffer count as known, so a held
+/// back operation is not requested over and over.
+pub fn reconcile(conn: &Connection, floor: i64, ranges: &[RangeSummary]) -> Result<Reconciliation> {
+    let mut reply = Reconciliation::default();
+
+    for range in ranges {
+        let start = range.start.max(floor.saturating_add(1));
+        if start >= range.end {
+            continue;
+        }
+
+        match &range.content {
+            RangeContent::Fingerprint(remote) => {
+                let local_ids = range_ids(conn, floor, start, range.end)?;
+                let mut local = Fingerprint::default();
+                for id in &local_ids {
+                    local.insert(*id);
+                }
+
+                if local == *remote {
+                    continue;
+                }
+                if remote.count == 0 {
+                    reply.entries.extend(load_entries(conn, &local_ids)?);
+                    continue;
+                }
+
+                let splits = if local.count > LEAF_SIZE {
+                    split_points(conn, floor, start, range.end)?
+                } else {
+                    Vec::new()
+                };
+                if splits.is_empty() {
+                    reply.ranges.push(RangeSummary {
+                        start,
+                        end: range.end,
+                        content: RangeContent::Ids(local_ids),
+                    });
+                    continue;
+                }
+
+                let bounds: Vec<i64> = std::iter::once(start)
+                    .chain(splits)
+                    .chain(std::iter::once(range.end))
+                    .collect();
+                for pair in bounds.windows(2) {
+                    reply.ranges.push(RangeSummary {
+                        start: pair[0],
+                        end: pair[1],
+                        content: RangeContent::Fingerprint(range_fingerprint(
+                            conn, floor, pair[0], pair[1],
+                        )?),
+                    });
+                }
+            }
+            RangeContent::Ids(remote_ids) => {
+                let local_ids = range_ids(conn, floor, start, range.end)?;
+                let remote: HashSet<&Uuid> = remote_ids.iter().collect();
+                let local: HashSet<&Uuid> = local_ids.iter().collect();
+
+                let theirs_missing: Vec<Uuid> = local_ids
+                    .iter()
+                    .filter(|id| !remote.contains(id))
+                    .copied()
+                    .collect();
+                reply.entries.extend(load_entries(conn, &theirs_missing)?);
+
+                let mut ours_missing = false;
+                for id in remote_ids {
+                    if !local.contains(id) && !is_held_back(conn, *id)? {
+                        ours_missing = true;
+                        break;
+                    }
+                }
+                if ours_missing {
+                    reply.ranges.push(RangeSummary {
+                        start,
+                        end: range.end,
+                        content: RangeContent::Ids(local_ids),
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(reply)
+}
+
+/// IDs of the operations in `[start, end)` above `floor`
+fn range_ids(conn: &Connection, floor: i64, start: i64, end: i64) -> Result<Vec<Uuid>> {
+    let mut stmt = conn.prepare(
+        "SELECT id FROM oplog WHERE timestamp > ?1 AND timestamp >= ?2 AND timestamp < ?3",
+    )?;
+    let rows = stmt.query_map(params![floor, start, end], |row| row.get::<_, String>(0))?;
+
+    let mut ids = Vec::new();
+    for row in rows {
+        // Malformed IDs cannot be exchanged; they are left out of every summary
+        if let Ok(id) = Uuid::parse_str(&row?) {
+            ids.push(id);
+        }
+    }
+    Ok(ids)
+}
+
+/// Timestamps splitting `[start, end)` into up to [`BRANCH_FACTOR`] ranges
+/// of roughly equal local size
+fn split_points(conn: &Connection, floor: i64, start: i64, end: i64) -> Result<Vec<i64>> {
+    let mut stmt = conn.prepare(
+        "SELECT timestamp FROM oplog
+         WHERE timestamp > ?1 AND timestamp >= ?2 AND timestamp < ?3
+         ORDER BY timestamp",
+    )?;
+    let timestamps = stmt
+        .query_map(params![floor, start, end], |row| row.get::<_, i64>(0))?
+        .collect::<rusqlite::Result<Vec<i64>>>()?;
+
+    let mut points: Vec<i64> = Vec::new();
+    for k in 1..BRANCH_FACTOR {
+        let point = timestamps[k * timestamps.len() / BRANCH_FACTOR];
+        if point > start && points.last().is_none_or(|&last| point > last) {
+            points.push(point);
+        }
+    }
+    Ok(points)
+}
+
+fn load_entries(conn: &Connection, ids: &[Uuid]) -> Result<Vec<OplogEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, device_id, timestamp, table_name, op_type, data, deps, schema_version FROM oplog WHERE id = ?1",
+    )?;
+    let mut entries = Vec::new();
+    for id in ids {
+        if let Some(entry) = stmt
+            .query_row(params![id.to_string()], row_to_oplog_entry)
+            .optional()?
+        {
+            entries.push(entry);
+        }
+    }
+    entries.sort_by_key(|e| e.timestamp);
+    Ok(entries)
+}
+
+/// Whether an operation was received but is held back from the oplog
+fn is_held_back(conn: &Connection, id: Uuid) -> Result<bool> {
+    let found = conn
+        .query_row(
+            "SELECT 1 FROM oplog_quarantine WHERE id = ?1",
+            params![id.to_string()],
+            |_| Ok(()),
+        )
+        .optional()?;
+    Ok(found.is_some() || super::causal::is_pending(conn, id)?)
+}
+
+/// SplitMix64 finalizer, spreading every ID bit across the hash
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{merge, DeviceClock, HybridLogicalClock};
+    use crate::db::operations::{get_oplog_entries_since, initialize_database};
+
+    fn op(millis: i64) -> OplogEntry {
+        OplogEntry {
+            id: Uuid::new_v4(),
+            device_id: Uuid::new_v4(),
+            timestamp: HybridLogicalClock::from_timestamp(millis << 16).to_timestamp(),
+            table: "todos".to_string(),
+            op_type: "create".to_string(),
+            data: serde_json::json!({"id": millis}),
+            deps: Vec::new(),
+            schema_version: 1,
+        }
+    }
+
+    fn ids(conn: &Connection) -> HashSet<Uuid> {
+        get_oplog_entries_since(conn, 0)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.id)
+            .collect()
+    }
+
+    /// Exchange summaries until neither side has anything left to send
+    fn run(a: &mut Connection, b: &mut Connection) -> usize {
+        let clock = DeviceClock::new(Uuid::new_v4());
+        let mut ranges = vec![root_range(a, 0).unwrap()];
+        let (mut from, mut to) = (a, b);
+        let mut rounds = 0;
+        while !ranges.is_empty() {
+            let reply = reconcile(to, 0, &ranges).unwrap();
+            merge(from, &clock, &reply.entries).unwrap();
+            ranges = reply.ranges;
+            std::mem::swap(&mut from, &mut to);
+            rounds += 1;
+        }
+        rounds
+    }
+
+    #[test]
+    fn test_fingerprint_is_order_independent() {
+        let ops: Vec<OplogEntry> = (1..=50).map(op).collect();
+        let mut reversed = ops.clone();
+        reversed.reverse();
+
+        let mut a = Fingerprint::default();
+        let mut b = Fingerprint::default();
+        ops.iter().for_each(|o| a.insert(o.id));
+        reversed.iter().for_each(|o| b.insert(o.id));
+        assert_eq!(a, b);
+        assert_eq!(a.count, 50);
+
+        let mut c = Fingerprint::default();
+        ops[1..].iter().for_each(|o| c.insert(o.id));
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_reconcile_finds_divergent_ops_in_large_oplog() {
+        let mut a = initialize_database(":memory:").unwrap();
+        let mut b = initialize_database(":memory:").unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+
+        let shared: Vec<OplogEntry> = (1..=2000).map(op).collect();
+        merge(&mut a, &clock, &shared).unwrap();
+        merge(&mut b, &clock, &shared).unwrap();
+
+        let only_a = [op(10), op(1500)];
+        let only_b = [op(700), op(3000)];
+        merge(&mut a, &clock, &only_a).unwrap();
+        merge(&mut b, &clock, &only_b).unwrap();
+
+        let rounds = run(&mut a, &mut b);
+        assert_eq!(ids(&a), ids(&b));
+        assert_eq!(ids(&a).len(), 2004);
+        assert!(rounds <= 8, "took {} rounds", rounds);
+
+        // Converged replicas agree at the root
+        let root = root_range(&a, 0).unwrap();
+        assert!(reconcile(&b, 0, &[root]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_reconcile_skips_ops_below_floor() {
+        let a = initialize_database(":memory:").unwrap();
+        let mut b = initialize_database(":memory:").unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+
+        let old = op(5);
+        let new = op(50);
+        merge(&mut b, &clock, &[old, new.clone()]).unwrap();
+
+        let floor = HybridLogicalClock::from_timestamp(10 << 16).to_timestamp();
+        let reply = reconcile(&b, floor, &[root_range(&a, floor).unwrap()]).unwrap();
+        let sent: Vec<Uuid> = reply.entries.iter().map(|e| e.id).collect();
+        assert_eq!(sent, vec![new.id]);
+    }
+}
@@ -0,0 +1,474 @@
+//! Automatic change capture for app tables.
+//!
+//! [`track_table`] installs `INSERT`, `UPDATE` and `DELETE` triggers on an
+//! app table that record every row change in the oplog, so writes sync even
+//! when the app never calls [`build_oplog_entry`](crate::build_oplog_entry):
+//!
+//! ```text
+//! INSERT INTO todos ...      -> create {"id": .., <every column>}
+//! UPDATE todos SET ...       -> update {"id": .., <every column>}
+//! DELETE FROM todos ...      -> delete {"id": ..}
+//! ```
+//!
+//! Captured operations belong to the device the table was tracked for and
+//! are stamped from its persistent [`DeviceClock`] row, so they interleave
+//! correctly with operations issued through the clock. The physical time
+//! comes from the clock's [`Clock`](super::Clock) source through an SQL
+//! function registered on the connection; every other connection writing to
+//! a tracked table needs it too ([`register_capture_clock`]). Like
+//! [`build_oplog_entry`](crate::build_oplog_entry), captured writes depend on
+//! the device's previous operation and on the latest write from every other
+//! device to the entity, which the `oplog_heads` table keeps per entity.
+//!
+//! Rows written by resolvers while applying operations (merges, local applies
+//! with a registry, undo, snapshot restores) are not captured again. Because
+//! the triggers run inside SQLite, captured operations are not announced to
+//! [change subscribers](super::observer) and do not update [`FieldLww`]'s
+//! field clocks; use the oplog-driven resolvers for tracked tables.
+//!
+//! Triggers list the table's columns when they are installed; call
+//! [`track_table`] again after changing the table's schema.
+//!
+//! [`FieldLww`]: super::FieldLww
+
+use super::resolver::quote_identifier;
+use super::{DeviceClock, HybridLogicalClock, MergeRegistry};
+use crate::error::{AhenkError, Result};
+use crate::models::is_valid_table_name;
+use chrono::Utc;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::{params, Connection};
+
+/// Trigger suffixes installed by [`track_table`]
+const TRIGGERS: [&str; 4] = ["insert", "update", "rekey", "delete"];
+
+/// SQL function capture triggers read physical time from
+const CLOCK_FUNCTION: &str = "ahenk_capture_time";
+
+/// Let capture triggers on this connection read physical time from `clock`.
+///
+/// [`track_table`] registers the clock on the connection it is given; call
+/// this on every other connection that writes to tracked tables, or their
+/// writes fail.
+pub fn register_capture_clock(conn: &Connection, clock: &DeviceClock) -> Result<()> {
+    let clock = clock.clone();
+    conn.create_scalar_function(CLOCK_FUNCTION, 0, FunctionFlags::SQLITE_UTF8, move |_| {
+        Ok(HybridLogicalClock::new(clock.wall_time(), 0).to_timestamp())
+    })?;
+    Ok(())
+}
+
+/// Capture writes to `table` into the oplog as operations of `clock`'s device.
+///
+/// Rows are keyed by the table's resolver key field in `registry` (`"id"`
+/// without one) and captured at its current schema version. Re-tracking a
+/// table reinstalls its triggers. Also registers `clock` on `conn` (see
+/// [`register_capture_clock`]).
+pub fn track_table(
+    conn: &Connection,
+    clock: &DeviceClock,
+    registry: &MergeRegistry,
+    table: &str,
+) -> Result<()> {
+    if !is_valid_table_name(table) {
+        return Err(AhenkError::Validation(format!(
+            "Cannot track table '{}': not a plain SQL identifier",
+            table
+        )));
+    }
+    let key_field = registry.key_field(table);
+    let columns = table_columns(conn, table)?;
+    if columns.is_empty() {
+        return Err(AhenkError::NotFound(format!("Table '{}'", table)));
+    }
+    if !columns.iter().any(|c| c == key_field) {
+        return Err(AhenkError::Validation(format!(
+            "Table '{}' has no '{}' column",
+            table, key_field
+        )));
+    }
+
+    let device_id = clock.device_id().to_string();
+    let capture = Capture {
+        table,
+        key_field,
+        device_id: &device_id,
+        schema_version: registry.schema_version(table),
+    };
+    let row = |prefix: &str| {
+        let fields: Vec<String> = columns
+            .iter()
+            .map(|c| format!("{}, {}.{}", quote_literal(c), prefix, quote_identifier(c)))
+            .collect();
+        format!("json_object({})", fields.join(", "))
+    };
+    let key_only = |prefix: &str| {
+        format!(
+            "json_object({}, {}.{})",
+            quote_literal(key_field),
+            prefix,
+            quote_identifier(key_field)
+        )
+    };
+    let key = quote_identifier(key_field);
+
+    register_capture_clock(conn, clock)?;
+    let tx = conn.unchecked_transaction()?;
+    drop_triggers(&tx, table)?;
+    tx.execute_batch(&capture.trigger("insert", "INSERT", None, &[("create", "NEW", row("NEW"))]))?;
+    tx.execute_batch(&capture.trigger(
+        "update",
+        "UPDATE",
+        Some(format!("OLD.{key} IS NEW.{key}")),
+        &[("update", "NEW", row("NEW"))],
+    ))?;
+    // A changed key moves the row to another entity
+    tx.execute_batch(&capture.trigger(
+        "rekey",
+        "UPDATE",
+        Some(format!("OLD.{key} IS NOT NEW.{key}")),
+        &[
+            ("delete", "OLD", key_only("OLD")),
+            ("create", "NEW", row("NEW")),
+        ],
+    ))?;
+    tx.execute_batch(&capture.trigger(
+        "delete",
+        "DELETE",
+        None,
+        &[("delete", "OLD", key_only("OLD"))],
+    ))?;
+    tx.execute(
+        "INSERT OR REPLACE INTO tracked_tables (table_name, key_field, device_id, registered_at)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![table, key_field, device_id, Utc::now().to_rfc3339()],
+    )?;
+    // The oplog trigger keeps heads from here on; start from the history
+    tx.execute(
+        "DELETE FROM oplog_heads WHERE table_name = ?1",
+        params![table],
+    )?;
+    tx.execute(
+        "INSERT INTO oplog_heads (table_name, entity_key, device_id, op_id, timestamp)
+         SELECT table_name, CAST(json_extract(data, '$.' || ?2) AS TEXT), device_id, id,
+                timestamp
+         FROM oplog
+         WHERE table_name = ?1 AND op_type IN ('create', 'update', 'delete')
+           AND json_extract(data, '$.' || ?2) IS NOT NULL
+         ON CONFLICT (table_name, entity_key, device_id)
+         DO UPDATE SET op_id = excluded.op_id, timestamp = excluded.timestamp
+         WHERE (excluded.timestamp, excluded.op_id) > (oplog_heads.timestamp, oplog_heads.op_id)",
+        params![table, key_field],
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Stop capturing writes to `table`
+pub fn untrack_table(conn: &Connection, table: &str) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    drop_triggers(&tx, table)?;
+    let removed = tx.execute(
+        "DELETE FROM tracked_tables WHERE table_name = ?1",
+        params![table],
+    )?;
+    if removed == 0 {
+        return Err(AhenkError::NotFound(format!("Tracked table '{}'", table)));
+    }
+    tx.execute(
+        "DELETE FROM oplog_heads WHERE table_name = ?1",
+        params![table],
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Tables with capture triggers installed
+pub fn tracked_tables(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT table_name FROM tracked_tables ORDER BY table_name")?;
+    let tables = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    Ok(tables)
+}
+
+/// Run `f` with capture triggers disabled on this connection.
+///
+/// The flag is only changed inside the caller's transaction, so concurrent
+/// writers on other connections are still captured.
+pub(crate) fn without_capture<T>(conn: &Connection, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    conn.execute(
+        "UPDATE change_capture_state SET suppressed = suppressed + 1",
+        [],
+    )?;
+    let result = f();
+    conn.execute(
+        "UPDATE change_capture_state SET suppressed = suppressed - 1",
+        [],
+    )?;
+    result
+}
+
+struct Capture<'a> {
+    table: &'a str,
+    key_field: &'a str,
+    device_id: &'a str,
+    schema_version: u32,
+}
+
+impl Capture<'_> {
+    /// `CREATE TRIGGER` recording one oplog entry per `(op_type, row, data)`
+    fn trigger(
+        &self,
+        name: &str,
+        event: &str,
+        condition: Option<String>,
+        ops: &[(&str, &str, String)],
+    ) -> String {
+        let device = quote_literal(self.device_id);
+        let mut when = "(SELECT suppressed FROM change_capture_state) = 0".to_string();
+        if let Some(condition) = condition {
+            when = format!("{} AND {}", when, condition);
+        }
+
+        let mut body = String::new();
+        for (op_type, row, data) in ops {
+            body.push_str(&format!(
+                "INSERT OR IGNORE INTO device_clock (device_id, timestamp) VALUES ({device}, 0);
+                 UPDATE device_clock SET timestamp = MAX(timestamp + 1, {now}())
+                 WHERE device_id = {device};
+                 INSERT INTO oplog
+                     (id, device_id, timestamp, table_name, op_type, data, deps, schema_version,
+                      entity_id)
+                 VALUES ({id}, {device},
+                     (SELECT timestamp FROM device_clock WHERE device_id = {device}),
+                     {table}, {op_type}, {data}, {deps}, {schema_version},
+                     CAST({row}.{key} AS TEXT));\n",
+                id = NEW_UUID,
+                now = CLOCK_FUNCTION,
+                table = quote_literal(self.table),
+                op_type = quote_literal(op_type),
+                deps = self.deps(row),
+                schema_version = self.schema_version,
+                key = quote_identifier(self.key_field),
+            ));
+        }
+
+        format!(
+            "CREATE TRIGGER {name} AFTER {event} ON {table}\nWHEN {when}\nBEGIN\n{body}END;",
+            name = quote_identifier(&trigger_name(self.table, name)),
+            table = quote_identifier(self.table),
+        )
+    }
+
+    /// JSON array of the device's latest operation and the latest row op on
+    /// the entity from every other device, or `NULL` if there are none
+    fn deps(&self, row: &str) -> String {
+        format!(
+            "(SELECT NULLIF(json_group_array(dep.id), '[]') FROM (
+                SELECT * FROM (SELECT id FROM oplog WHERE device_id = {device}
+                               ORDER BY timestamp DESC, id DESC LIMIT 1)
+                UNION ALL {heads}) AS dep)",
+            device = quote_literal(self.device_id),
+            heads = self.heads(row),
+        )
+    }
+
+    /// Query for the latest row op on the entity from every other device
+    fn heads(&self, row: &str) -> String {
+        format!(
+            "SELECT op_id FROM oplog_heads
+              WHERE table_name = {table} AND entity_key = CAST({row}.{key} AS TEXT)
+                AND device_id != {device}",
+            table = quote_literal(self.table),
+            key = quote_identifier(self.key_field),
+            device = quote_literal(self.device_id),
+        )
+    }
+}
+
+/// Random (version 4) UUID in its hyphenated text form
+const NEW_UUID: &str = "lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4'
+    || substr(hex(randomblob(2)), 2) || '-'
+    || substr('89ab', 1 + abs(random()) % 4, 1) || substr(hex(randomblob(2)), 2) || '-'
+    || hex(randomblob(6)))";
+
+fn trigger_name(table: &str, suffix: &str) -> String {
+    format!("ahenk_capture_{}_{}", table, suffix)
+}
+
+fn drop_triggers(conn: &Connection, table: &str) -> Result<()> {
+    for suffix in TRIGGERS {
+        conn.execute_batch(&format!(
+            "DROP TRIGGER IF EXISTS {}",
+            quote_identifier(&trigger_name(table, suffix))
+        ))?;
+    }
+    Ok(())
+}
+
+fn table_columns(conn: &Connection, table: &str) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?1) ORDER BY cid")?;
+    let columns = stmt
+        .query_map(params![table], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    Ok(columns)
+}
+
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{merge_with, Clock, LastWriteWins, ManualClock};
+    use crate::db::operations::{get_oplog_entries_since, initialize_database};
+    use chrono::TimeZone;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    fn setup() -> (Connection, DeviceClock, MergeRegistry) {
+        let conn = initialize_database(":memory:").unwrap();
+        conn.execute(
+            "CREATE TABLE todos (id TEXT PRIMARY KEY, title TEXT, done INTEGER)",
+            [],
+        )
+        .unwrap();
+        let registry = MergeRegistry::new().with_resolver("todos", LastWriteWins::new());
+        (conn, DeviceClock::new(Uuid::new_v4()), registry)
+    }
+
+    #[test]
+    fn test_writes_are_captured() {
+        let (conn, clock, registry) = setup();
+        let before = clock.tick(&conn).unwrap();
+        track_table(&conn, &clock, &registry, "todos").unwrap();
+        assert_eq!(tracked_tables(&conn).unwrap(), vec!["todos"]);
+
+        conn.execute_batch(
+            "INSERT INTO todos VALUES ('t1', 'draft', 0);
+             UPDATE todos SET title = 'final' WHERE id = 't1';
+             UPDATE todos SET id = 't2' WHERE id = 't1';
+             DELETE FROM todos;",
+        )
+        .unwrap();
+
+        let ops = get_oplog_entries_since(&conn, 0).unwrap();
+        let summary: Vec<(&str, serde_json::Value)> = ops
+            .iter()
+            .map(|op| (op.op_type.as_str(), op.data.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "create",
+                    serde_json::json!({"id": "t1", "title": "draft", "done": 0})
+                ),
+                (
+                    "update",
+                    serde_json::json!({"id": "t1", "title": "final", "done": 0})
+                ),
+                ("delete", serde_json::json!({"id": "t1"})),
+                (
+                    "create",
+                    serde_json::json!({"id": "t2", "title": "final", "done": 0})
+                ),
+                ("delete", serde_json::json!({"id": "t2"})),
+            ]
+        );
+        assert!(ops.iter().all(|op| op.device_id == clock.device_id()
+            && op.timestamp > before.to_timestamp()
+            && op.validate().is_ok()));
+        // Each captured op depends on the one captured before it
+        assert!(ops
+            .windows(2)
+            .all(|pair| pair[1].deps.contains(&pair[0].id)));
+        // The device clock carries on after captured writes
+        let last = HybridLogicalClock::from_timestamp(ops.last().unwrap().timestamp);
+        assert!(clock.tick(&conn).unwrap() > last);
+
+        untrack_table(&conn, "todos").unwrap();
+        conn.execute("INSERT INTO todos VALUES ('t3', 'quiet', 0)", [])
+            .unwrap();
+        assert_eq!(get_oplog_entries_since(&conn, 0).unwrap().len(), ops.len());
+    }
+
+    #[test]
+    fn test_merged_writes_are_not_recaptured() {
+        let (mut phone, phone_clock, registry) = setup();
+        let (mut laptop, laptop_clock, _) = setup();
+        track_table(&phone, &phone_clock, &registry, "todos").unwrap();
+        track_table(&laptop, &laptop_clock, &registry, "todos").unwrap();
+
+        laptop
+            .execute("INSERT INTO todos VALUES ('t1', 'from laptop', 0)", [])
+            .unwrap();
+        let remote = get_oplog_entries_since(&laptop, 0).unwrap();
+        merge_with(&mut phone, &phone_clock, &remote, &registry).unwrap();
+
+        let title: String = phone
+            .query_row("SELECT title FROM todos WHERE id = 't1'", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(title, "from laptop");
+        assert_eq!(get_oplog_entries_since(&phone, 0).unwrap().len(), 1);
+
+        // A local edit is captured and depends on the laptop's write
+        phone
+            .execute("UPDATE todos SET done = 1 WHERE id = 't1'", [])
+            .unwrap();
+        let ops = get_oplog_entries_since(&phone, 0).unwrap();
+        assert_eq!(ops.len(), 2);
+        assert_eq!(ops[1].device_id, phone_clock.device_id());
+        assert_eq!(ops[1].deps, vec![remote[0].id]);
+
+        merge_with(&mut laptop, &laptop_clock, &ops, &registry).unwrap();
+        let done: bool = laptop
+            .query_row("SELECT done FROM todos WHERE id = 't1'", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert!(done);
+        assert_eq!(get_oplog_entries_since(&laptop, 0).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_captured_writes_read_the_device_clock() {
+        let (conn, _, registry) = setup();
+        let time = ManualClock::new(Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap());
+        let clock = DeviceClock::with_clock(Uuid::new_v4(), Arc::new(time.clone()));
+        track_table(&conn, &clock, &registry, "todos").unwrap();
+
+        conn.execute("INSERT INTO todos VALUES ('t1', 'draft', 0)", [])
+            .unwrap();
+        let op = get_oplog_entries_since(&conn, 0).unwrap().pop().unwrap();
+        assert_eq!(
+            HybridLogicalClock::from_timestamp(op.timestamp),
+            HybridLogicalClock::new(time.now(), 0)
+        );
+    }
+
+    #[test]
+    fn test_tracking_starts_from_existing_history() {
+        let (mut phone, phone_clock, registry) = setup();
+        let (laptop, laptop_clock, _) = setup();
+        track_table(&laptop, &laptop_clock, &registry, "todos").unwrap();
+        laptop
+            .execute("INSERT INTO todos VALUES ('t1', 'from laptop', 0)", [])
+            .unwrap();
+        let remote = get_oplog_entries_since(&laptop, 0).unwrap();
+
+        // Merged before the phone tracks the table
+        merge_with(&mut phone, &phone_clock, &remote, &registry).unwrap();
+        track_table(&phone, &phone_clock, &registry, "todos").unwrap();
+        phone
+            .execute("UPDATE todos SET done = 1 WHERE id = 't1'", [])
+            .unwrap();
+
+        let ops = get_oplog_entries_since(&phone, 0).unwrap();
+        assert_eq!(ops[1].deps, vec![remote[0].id]);
+    }
+}
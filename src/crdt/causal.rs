@@ -0,0 +1,210 @@
+//! Causal delivery of remote operations.
+//!
+//! An operation may name the operations it depends on
+//! ([`OplogEntry::deps`]). [`merge`](super::merge) only applies it once every
+//! dependency is in the local oplog; until then it waits in the persistent
+//! `oplog_pending` buffer, and is applied by the merge that delivers its last
+//! missing dependency.
+//!
+//! Operations removed by compaction were delivered before they were removed,
+//! so their IDs are kept in `oplog_compacted` and still satisfy dependencies.
+//!
+//! Every local operation also depends on the operation its device recorded
+//! before it ([`device_head`]). A device's operations are therefore recorded
+//! everywhere in the order it recorded them, and a
+//! [`VersionVector`](super::VersionVector) entry always covers a contiguous
+//! prefix of the device's operations: an operation that is lost or
+//! reordered in transit holds back the later ones instead of being skipped
+//! by delta sync.
+
+use crate::db::operations::{deps_column, row_to_oplog_entry};
+use crate::error::{AhenkError, Result};
+use crate::models::OplogEntry;
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use uuid::Uuid;
+
+const SELECT_PENDING: &str =
+    "SELECT id, device_id, timestamp, table_name, op_type, data, deps, schema_version FROM oplog_pending";
+
+/// Whether every dependency has been delivered
+pub(crate) fn deps_satisfied(conn: &Connection, deps: &[Uuid]) -> Result<bool> {
+    let mut stmt = conn.prepare(
+        "SELECT EXISTS (SELECT 1 FROM oplog WHERE id = ?1)
+             OR EXISTS (SELECT 1 FROM oplog_compacted WHERE id = ?1)",
+    )?;
+    for dep in deps {
+        if !stmt.query_row(params![dep.to_string()], |row| row.get::<_, bool>(0))? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Latest operation recorded by `device_id`, which the device's next
+/// operation depends on
+pub(crate) fn device_head(conn: &Connection, device_id: Uuid) -> Result<Option<Uuid>> {
+    let head: Option<String> = conn
+        .query_row(
+            "SELECT id FROM oplog WHERE device_id = ?1 ORDER BY timestamp DESC, id DESC LIMIT 1",
+            params![device_id.to_string()],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(head.and_then(|id| Uuid::parse_str(&id).ok()))
+}
+
+/// Hold an operation back until its dependencies arrive
+pub(crate) fn buffer_op(conn: &Connection, op: &OplogEntry) -> Result<()> {
+    let data =
+        serde_json::to_string(&op.data).map_err(|e| AhenkError::Serialization(e.to_string()))?;
+    let deps = deps_column(&op.deps).map_err(|e| AhenkError::Serialization(e.to_string()))?;
+
+    conn.execute(
+        "INSERT OR IGNORE INTO oplog_pending (id, device_id, timestamp, table_name, op_type, data, deps, schema_version, received_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            op.id.to_string(),
+            op.device_id.to_string(),
+            op.timestamp,
+            op.table,
+            op.op_type,
+            data,
+            deps,
+            op.schema_version,
+            Utc::now().to_rfc3339(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Remove and return the buffered operations whose dependencies have all
+/// been delivered, in HLC order
+pub(crate) fn take_ready(conn: &Connection) -> Result<Vec<OplogEntry>> {
+    let mut stmt = conn.prepare(&format!(
+        "{} p WHERE NOT EXISTS (
+            SELECT 1 FROM json_each(p.deps) d
+            WHERE d.value NOT IN (SELECT id FROM oplog)
+              AND d.value NOT IN (SELECT id FROM oplog_compacted)
+         ) ORDER BY timestamp, device_id, id",
+        SELECT_PENDING
+    ))?;
+    let ready = stmt
+        .query_map([], row_to_oplog_entry)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut delete = conn.prepare("DELETE FROM oplog_pending WHERE id = ?1")?;
+    for op in &ready {
+        delete.execute(params![op.id.to_string()])?;
+    }
+    Ok(ready)
+}
+
+/// Whether an operation is waiting in the buffer
+pub(crate) fn is_pending(conn: &Connection, op_id: Uuid) -> Result<bool> {
+    let mut stmt = conn.prepare("SELECT 1 FROM oplog_pending WHERE id = ?1")?;
+    Ok(stmt.exists(params![op_id.to_string()])?)
+}
+
+/// List the operations waiting for dependencies, oldest first
+pub fn list_pending(conn: &Connection) -> Result<Vec<OplogEntry>> {
+    let mut stmt = conn.prepare(&format!(
+        "{} ORDER BY timestamp, device_id, id",
+        SELECT_PENDING
+    ))?;
+    let rows = stmt.query_map([], row_to_oplog_entry)?;
+
+    let mut ops = Vec::new();
+    for row in rows {
+        ops.push(row?);
+    }
+    Ok(ops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{merge, merge_with, DeviceClock, LastWriteWins, MergeRegistry};
+    use crate::db::operations::{get_oplog_entries_since, initialize_database};
+    use crate::logic::build_oplog_entry;
+
+    #[test]
+    fn test_update_waits_for_its_create() {
+        let mut origin = initialize_database(":memory:").unwrap();
+        let origin_clock = DeviceClock::new(Uuid::new_v4());
+        let create = build_oplog_entry(
+            &origin,
+            &origin_clock,
+            "todos",
+            "create",
+            &serde_json::json!({"id": "t1", "title": "draft"}),
+        )
+        .unwrap();
+        crate::crdt::local_apply(&mut origin, &create).unwrap();
+        let update = build_oplog_entry(
+            &origin,
+            &origin_clock,
+            "todos",
+            "update",
+            &serde_json::json!({"id": "t1", "title": "final"}),
+        )
+        .unwrap()
+        .with_deps([create.id]);
+
+        let mut conn = initialize_database(":memory:").unwrap();
+        conn.execute("CREATE TABLE todos (id TEXT PRIMARY KEY, title TEXT)", [])
+            .unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+        let registry = MergeRegistry::new().with_resolver("todos", LastWriteWins::new());
+
+        merge_with(&mut conn, &clock, std::slice::from_ref(&update), &registry).unwrap();
+        assert!(get_oplog_entries_since(&conn, 0).unwrap().is_empty());
+        let pending = list_pending(&conn).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].deps, vec![create.id]);
+
+        merge_with(&mut conn, &clock, &[create], &registry).unwrap();
+        assert!(list_pending(&conn).unwrap().is_empty());
+        let ids: Vec<Uuid> = get_oplog_entries_since(&conn, 0)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.id)
+            .collect();
+        assert_eq!(ids.len(), 2);
+        assert_eq!(ids[1], update.id);
+
+        let title: Option<String> = conn
+            .query_row("SELECT title FROM todos WHERE id = 't1'", [], |row| {
+                row.get(0)
+            })
+            .optional()
+            .unwrap();
+        assert_eq!(title.as_deref(), Some("final"));
+    }
+
+    #[test]
+    fn test_dependency_chain_is_released_in_one_merge() {
+        let mut conn = initialize_database(":memory:").unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+        let op = |title: &str| {
+            build_oplog_entry(
+                &conn,
+                &clock,
+                "todos",
+                "update",
+                &serde_json::json!({"id": "t1", "title": title}),
+            )
+            .unwrap()
+        };
+        let a = op("a");
+        let b = op("b").with_deps([a.id]);
+        let c = op("c").with_deps([b.id]);
+
+        merge(&mut conn, &clock, &[c, b]).unwrap();
+        assert_eq!(list_pending(&conn).unwrap().len(), 2);
+
+        merge(&mut conn, &clock, &[a]).unwrap();
+        assert!(list_pending(&conn).unwrap().is_empty());
+        assert_eq!(get_oplog_entries_since(&conn, 0).unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,85 @@
+//! Wall-clock sources for the HLC.
+//!
+//! Everything that needs physical time ([`HybridLogicalClock`], the
+//! [`DeviceClock`] service and the sync layer) reads it through the [`Clock`]
+//! trait instead of calling `Utc::now()` directly. Production code uses
+//! [`SystemClock`]; tests use [`ManualClock`] to drive time explicitly.
+//!
+//! [`HybridLogicalClock`]: super::HybridLogicalClock
+//! [`DeviceClock`]: super::DeviceClock
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
+/// Source of physical (wall-clock) time
+pub trait Clock: Send + Sync {
+    /// Current wall-clock time
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// Clock backed by the operating system's real-time clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Manually driven clock for deterministic tests.
+///
+/// Time only moves when [`set`](Self::set) or [`advance`](Self::advance) is
+/// called. Clones share the same time, so a test can keep one handle while a
+/// [`DeviceClock`](super::DeviceClock) owns another.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    millis: Arc<AtomicI64>,
+}
+
+impl ManualClock {
+    /// Create a manual clock starting at the given time
+    pub fn new(start: DateTime<Utc>) -> Self {
+        Self {
+            millis: Arc::new(AtomicI64::new(start.timestamp_millis())),
+        }
+    }
+
+    /// Jump to an absolute time (may move backwards to simulate skew)
+    pub fn set(&self, time: DateTime<Utc>) {
+        self.millis.store(time.timestamp_millis(), Ordering::SeqCst);
+    }
+
+    /// Move time forward (or backward, for a negative duration)
+    pub fn advance(&self, by: Duration) {
+        self.millis
+            .fetch_add(by.num_milliseconds(), Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc.timestamp_millis_opt(self.millis.load(Ordering::SeqCst))
+            .single()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock_is_shared_between_clones() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let clock = ManualClock::new(start);
+        let handle = clock.clone();
+
+        handle.advance(Duration::seconds(90));
+        assert_eq!(clock.now(), start + Duration::seconds(90));
+
+        handle.set(start - Duration::hours(1));
+        assert_eq!(clock.now(), start - Duration::hours(1));
+    }
+}
@@ -0,0 +1,628 @@
+//! Causally-stable oplog compaction.
+//!
+//! Every peer acknowledges the highest HLC up to which it holds the whole
+//! local oplog ([`acknowledged_prefix`], [`record_peer_ack`]), along with its
+//! own latest operation at the time. The stable point is the minimum over
+//! every other device, whether a known peer or only seen through its
+//! operations, of its acknowledgement and of how much of its log the local
+//! replica holds: every device has seen every operation at or below it, and
+//! no operation at or below it is still on its way here, so history there is
+//! only needed for the current state.
+//!
+//! [`compact_oplog`] then removes, below the stable point:
+//! - creates/updates superseded by a later delete of the same entity, or by a
+//!   later write that covers every field they wrote
+//! - tombstones (deletes), which every peer has now seen. The deleted entity
+//!   and the HLC of its latest delete are remembered in `oplog_tombstones`,
+//!   so [`DeleteWins`](super::DeleteWins) still ignores later writes to it
+//!   and [`LastWriteWins`](super::LastWriteWins) still ignores older ones
+//!
+//! Writes followed by a [patch](super::patch) are kept, since the patch was
+//! applied to the state they produced.
+//!
+//! Only `create`, `update` and `delete` operations are compacted, and entities
+//! are identified by the primary-key field of the table's resolver (see
+//! [`MergeRegistry::key_field`]); operations without one are never removed.
+//! Operations above the stable point are always kept. The IDs of removed
+//! operations are kept in `oplog_compacted`, so later operations depending on
+//! them are still delivered and redelivered ones are not applied again.
+
+use super::history::key_string;
+use super::patch::is_patch_op;
+use super::{HybridLogicalClock, MergeRegistry, VersionVector};
+use crate::error::Result;
+use chrono::Utc;
+use rusqlite::{params, Connection};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+/// Outcome of a compaction run
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// Stable point used, or `None` if no peer has acknowledged anything
+    pub stable_point: Option<HybridLogicalClock>,
+    /// Superseded creates/updates removed
+    pub superseded_removed: usize,
+    /// Tombstones removed
+    pub tombstones_removed: usize,
+}
+
+/// Record that a peer device has received the local oplog up to `timestamp`.
+///
+/// `written` is the HLC of the peer's own latest operation when it sent the
+/// acknowledgement (its own entry in its version vector, `0` if none). The
+/// peer's later operations are stamped above `timestamp`, so once the local
+/// replica holds its log up to `written` the acknowledgement is stable.
+///
+/// Watermarks only move forward; an older acknowledgement is ignored.
+pub fn record_peer_ack(
+    conn: &Connection,
+    device_id: Uuid,
+    timestamp: HybridLogicalClock,
+    written: i64,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO peer_acks (device_id, timestamp, written, acked_at) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(device_id) DO UPDATE SET
+            timestamp = excluded.timestamp, written = excluded.written,
+            acked_at = excluded.acked_at
+         WHERE excluded.timestamp > peer_acks.timestamp",
+        params![
+            device_id.to_string(),
+            timestamp.to_timestamp(),
+            written,
+            Utc::now().to_rfc3339()
+        ],
+    )?;
+    Ok(())
+}
+
+/// Highest HLC up to which a peer with version vector `remote` holds the
+/// whole local oplog, or `None` if the local oplog is empty.
+///
+/// The vector only says how far the peer got in each device's log, so the
+/// acknowledgement stops just below the oldest local operation it does not
+/// cover, whatever the peer holds above it.
+pub fn acknowledged_prefix(
+    conn: &Connection,
+    remote: &VersionVector,
+) -> Result<Option<HybridLogicalClock>> {
+    let remote: serde_json::Map<String, serde_json::Value> = remote
+        .iter()
+        .map(|(device_id, timestamp)| (device_id.to_string(), timestamp.into()))
+        .collect();
+    let (missing, last): (Option<i64>, Option<i64>) = conn.query_row(
+        "SELECT (SELECT MIN(timestamp) FROM oplog
+                 WHERE timestamp > COALESCE(json_extract(?1, '$.\"' || device_id || '\"'), 0)),
+                (SELECT MAX(timestamp) FROM oplog)",
+        params![serde_json::Value::Object(remote).to_string()],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+
+    Ok(match missing {
+        Some(missing) => Some(HybridLogicalClock::from_timestamp(missing - 1)),
+        None => last.map(HybridLogicalClock::from_timestamp),
+    })
+}
+
+/// Stable point of the replica of device `device_id`: the highest HLC every
+/// other device has acknowledged and below which the local replica holds
+/// every other device's operations.
+///
+/// Other devices are the known peers and every device with operations in the
+/// local version vector. A device's acknowledgement counts in full once the
+/// local replica holds its log up to the operation it had last written when
+/// acknowledging (see [`record_peer_ack`]); until then the stable point stays
+/// at or below what the local replica holds of its log.
+///
+/// Returns `None` when there are no other devices, or when one of them has
+/// not acknowledged anything yet.
+pub fn stable_watermark(conn: &Connection, device_id: Uuid) -> Result<Option<HybridLogicalClock>> {
+    let (devices, acked, min): (i64, i64, Option<i64>) = conn.query_row(
+        "SELECT COUNT(*), COUNT(a.timestamp),
+                MIN(CASE WHEN COALESCE(v.timestamp, 0) >= a.written THEN a.timestamp
+                         ELSE MIN(a.timestamp, COALESCE(v.timestamp, 0)) END)
+         FROM (SELECT device_id FROM peers UNION SELECT device_id FROM version_vector) d
+         LEFT JOIN peer_acks a ON a.device_id = d.device_id
+         LEFT JOIN version_vector v ON v.device_id = d.device_id
+         WHERE d.device_id != ?1",
+        params![device_id.to_string()],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+
+    if devices == 0 || acked < devices {
+        return Ok(None);
+    }
+    Ok(min.map(HybridLogicalClock::from_timestamp))
+}
+
+/// Compact the oplog of device `device_id` below its current stable
+/// watermark.
+///
+/// Entities are identified by the key fields of the resolvers in `registry`.
+pub fn compact_oplog(
+    conn: &mut Connection,
+    device_id: Uuid,
+    registry: &MergeRegistry,
+) -> Result<CompactionStats> {
+    match stable_watermark(conn, device_id)? {
+        Some(stable_point) => compact_oplog_until(conn, registry, stable_point),
+        None => Ok(CompactionStats::default()),
+    }
+}
+
+/// Compact the oplog below an explicit stable point.
+///
+/// The caller is responsible for `stable_point` being acknowledged by every
+/// peer; prefer [`compact_oplog`].
+pub fn compact_oplog_until(
+    conn: &mut Connection,
+    registry: &MergeRegistry,
+    stable_point: HybridLogicalClock,
+) -> Result<CompactionStats> {
+    let tx = conn.transaction()?;
+    let (superseded, tombstones) = superseded_ops(&tx, registry, stable_point.to_timestamp())?;
+
+    let mut delete = tx.prepare("DELETE FROM oplog WHERE id = ?1")?;
+    let mut ungroup = tx.prepare("DELETE FROM oplog_groups WHERE op_id = ?1")?;
+    let mut remember = tx.prepare("INSERT OR IGNORE INTO oplog_compacted (id) VALUES (?1)")?;
+    // Keep the latest delete of each entity
+    let mut bury = tx.prepare(
+        "INSERT INTO oplog_tombstones (table_name, entity_id, timestamp, device_id, op_id)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT (table_name, entity_id) DO UPDATE SET
+            timestamp = excluded.timestamp, device_id = excluded.device_id,
+            op_id = excluded.op_id
+         WHERE (excluded.timestamp, excluded.device_id, excluded.op_id)
+            > (oplog_tombstones.timestamp, oplog_tombstones.device_id,
+               oplog_tombstones.op_id)",
+    )?;
+    let tombstone_ids = tombstones.iter().map(|t| &t.op_id);
+    for id in superseded.iter().chain(tombstone_ids) {
+        delete.execute(params![id])?;
+        ungroup.execute(params![id])?;
+        remember.execute(params![id])?;
+    }
+    for tombstone in &tombstones {
+        bury.execute(params![
+            tombstone.table,
+            tombstone.entity_id,
+            tombstone.timestamp,
+            tombstone.device_id,
+            tombstone.op_id
+        ])?;
+    }
+    drop(delete);
+    drop(ungroup);
+    drop(remember);
+    drop(bury);
+
+    tx.commit()?;
+    Ok(CompactionStats {
+        stable_point: Some(stable_point),
+        superseded_removed: superseded.len(),
+        tombstones_removed: tombstones.len(),
+    })
+}
+
+/// A delete found by [`superseded_ops`]
+pub(crate) struct Tombstone {
+    pub(crate) op_id: String,
+    pub(crate) table: String,
+    pub(crate) entity_id: String,
+    pub(crate) timestamp: i64,
+    pub(crate) device_id: String,
+}
+
+/// IDs of the row operations at or below `until` that later operations
+/// supersede, and the deletes (tombstones) among them
+pub(crate) fn superseded_ops(
+    conn: &Connection,
+    registry: &MergeRegistry,
+    until: i64,
+) -> Result<(Vec<String>, Vec<Tombstone>)> {
+    // Newest first, so each operation is checked against everything after it
+    let mut stmt = conn.prepare(
+        "SELECT id, table_name, op_type, data, timestamp, device_id FROM oplog
+         WHERE timestamp <= ?1
+           AND op_type IN ('create', 'update', 'delete', 'patch', 'merge-patch')
+         ORDER BY timestamp DESC, device_id DESC, id DESC",
+    )?;
+    let rows = stmt.query_map(params![until], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, String>(3)?,
+            row.get::<_, i64>(4)?,
+            row.get::<_, String>(5)?,
+        ))
+    })?;
+
+    #[derive(Default)]
+    struct EntityState {
+        deleted_after: bool,
+        covered: HashSet<String>,
+    }
+
+    let mut entities: HashMap<(String, String), EntityState> = HashMap::new();
+    let mut superseded = Vec::new();
+    let mut tombstones = Vec::new();
+
+    for row in rows {
+        let (id, table, op_type, data, timestamp, device_id) = row?;
+        let data = serde_json::from_str::<serde_json::Value>(&data).unwrap_or_default();
+        let Some(entity_id) = data.get(registry.key_field(&table)).and_then(key_string) else {
+            continue;
+        };
+        let state = entities
+            .entry((table.clone(), entity_id.clone()))
+            .or_default();
+
+        if op_type == "delete" {
+            state.deleted_after = true;
+            tombstones.push(Tombstone {
+                op_id: id,
+                table,
+                entity_id,
+                timestamp,
+                device_id,
+            });
+            continue;
+        }
+        if is_patch_op(&op_type) {
+            // A patch reads every field written before it
+            if state.deleted_after {
+                superseded.push(id);
+            } else {
+                state.covered.clear();
+            }
+            continue;
+        }
+
+        let fields: HashSet<String> = data
+            .as_object()
+            .map(|o| o.keys().cloned().collect())
+            .unwrap_or_default();
+
+        if state.deleted_after || (!fields.is_empty() && fields.is_subset(&state.covered)) {
+            superseded.push(id);
+        } else {
+            state.covered.extend(fields);
+        }
+    }
+
+    Ok((superseded, tombstones))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{merge, merge_with, DeleteWins, DeviceClock, LastWriteWins};
+    use crate::db::operations::{get_oplog_entries_since, initialize_database};
+    use crate::models::OplogEntry;
+    use chrono::{Duration, TimeZone};
+    use rusqlite::OptionalExtension;
+
+    fn op(minute: i64, op_type: &str, data: serde_json::Value) -> OplogEntry {
+        let time = Utc.with_ymd_and_hms(2024, 6, 1, 8, 0, 0).unwrap() + Duration::minutes(minute);
+        OplogEntry {
+            id: Uuid::new_v4(),
+            device_id: Uuid::new_v4(),
+            timestamp: HybridLogicalClock::new(time, 0).to_timestamp(),
+            table: "todos".to_string(),
+            op_type: op_type.to_string(),
+            data,
+            deps: Vec::new(),
+            schema_version: 1,
+        }
+    }
+
+    fn add_peer(conn: &Connection) -> Uuid {
+        add_peer_device(conn, Uuid::new_v4())
+    }
+
+    fn add_peer_device(conn: &Connection, device_id: Uuid) -> Uuid {
+        conn.execute(
+            "INSERT INTO peers (peer_id, user_id, device_id) VALUES (?1, ?2, ?3)",
+            params![
+                Uuid::new_v4().to_string(),
+                Uuid::new_v4().to_string(),
+                device_id.to_string()
+            ],
+        )
+        .unwrap();
+        device_id
+    }
+
+    /// Every device that wrote `ops` becomes a peer holding the local oplog up
+    /// to `timestamp`
+    fn acknowledge_writers(conn: &Connection, ops: &[OplogEntry], timestamp: i64) {
+        for op in ops {
+            add_peer_device(conn, op.device_id);
+            record_peer_ack(
+                conn,
+                op.device_id,
+                HybridLogicalClock::from_timestamp(timestamp),
+                op.timestamp,
+            )
+            .unwrap();
+        }
+    }
+
+    /// Record that the local replica holds `device_id`'s log up to `timestamp`
+    fn hold(conn: &Connection, device_id: Uuid, timestamp: i64) {
+        conn.execute(
+            "INSERT INTO version_vector (device_id, timestamp) VALUES (?1, ?2)
+             ON CONFLICT(device_id) DO UPDATE SET timestamp = excluded.timestamp",
+            params![device_id.to_string(), timestamp],
+        )
+        .unwrap();
+    }
+
+    fn remaining(conn: &Connection) -> Vec<Uuid> {
+        get_oplog_entries_since(conn, 0)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.id)
+            .collect()
+    }
+
+    #[test]
+    fn test_stable_watermark_is_minimum_peer_ack() {
+        let conn = initialize_database(":memory:").unwrap();
+        let local = Uuid::new_v4();
+        assert_eq!(stable_watermark(&conn, local).unwrap(), None);
+
+        let a = add_peer(&conn);
+        let b = add_peer(&conn);
+        record_peer_ack(&conn, a, HybridLogicalClock::from_timestamp(500), 0).unwrap();
+        assert_eq!(stable_watermark(&conn, local).unwrap(), None);
+
+        record_peer_ack(&conn, b, HybridLogicalClock::from_timestamp(300), 0).unwrap();
+        assert_eq!(
+            stable_watermark(&conn, local).unwrap(),
+            Some(HybridLogicalClock::from_timestamp(300))
+        );
+
+        // Acknowledgements never move backwards
+        record_peer_ack(&conn, b, HybridLogicalClock::from_timestamp(100), 0).unwrap();
+        record_peer_ack(&conn, a, HybridLogicalClock::from_timestamp(400), 0).unwrap();
+        assert_eq!(
+            stable_watermark(&conn, local).unwrap(),
+            Some(HybridLogicalClock::from_timestamp(300))
+        );
+    }
+
+    #[test]
+    fn test_stable_watermark_covers_what_we_hold_of_others() {
+        let conn = initialize_database(":memory:").unwrap();
+        let local = Uuid::new_v4();
+        let a = add_peer(&conn);
+        record_peer_ack(&conn, a, HybridLogicalClock::from_timestamp(500), 0).unwrap();
+
+        // Local operations never hold the stable point back
+        hold(&conn, local, 100);
+        assert_eq!(
+            stable_watermark(&conn, local).unwrap(),
+            Some(HybridLogicalClock::from_timestamp(500))
+        );
+
+        // A device only seen through its operations has to acknowledge too
+        let c = Uuid::new_v4();
+        hold(&conn, c, 200);
+        assert_eq!(stable_watermark(&conn, local).unwrap(), None);
+        record_peer_ack(&conn, c, HybridLogicalClock::from_timestamp(600), 200).unwrap();
+        assert_eq!(
+            stable_watermark(&conn, local).unwrap(),
+            Some(HybridLogicalClock::from_timestamp(500))
+        );
+
+        // Until its operation at 450 arrives, `a`'s log is only held up to 300
+        hold(&conn, a, 300);
+        record_peer_ack(&conn, a, HybridLogicalClock::from_timestamp(700), 450).unwrap();
+        assert_eq!(
+            stable_watermark(&conn, local).unwrap(),
+            Some(HybridLogicalClock::from_timestamp(300))
+        );
+        hold(&conn, a, 450);
+        assert_eq!(
+            stable_watermark(&conn, local).unwrap(),
+            Some(HybridLogicalClock::from_timestamp(600))
+        );
+    }
+
+    #[test]
+    fn test_acknowledged_prefix_stops_at_first_gap() {
+        let mut conn = initialize_database(":memory:").unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+        assert_eq!(
+            acknowledged_prefix(&conn, &VersionVector::new()).unwrap(),
+            None
+        );
+
+        let first = op(0, "create", serde_json::json!({"id": "t1"}));
+        let second = op(1, "create", serde_json::json!({"id": "t2"}));
+        let third = op(2, "create", serde_json::json!({"id": "t3"}));
+        merge(
+            &mut conn,
+            &clock,
+            &[first.clone(), second.clone(), third.clone()],
+        )
+        .unwrap();
+
+        // Holding the newest operation does not cover the older one it lacks
+        let mut remote = VersionVector::new();
+        remote.observe(first.device_id, first.timestamp);
+        remote.observe(third.device_id, third.timestamp);
+        assert_eq!(
+            acknowledged_prefix(&conn, &remote).unwrap(),
+            Some(HybridLogicalClock::from_timestamp(second.timestamp - 1))
+        );
+
+        remote.observe(second.device_id, second.timestamp);
+        assert_eq!(
+            acknowledged_prefix(&conn, &remote).unwrap(),
+            Some(HybridLogicalClock::from_timestamp(third.timestamp))
+        );
+    }
+
+    #[test]
+    fn test_compaction_drops_superseded_ops_below_stable_point() {
+        let mut conn = initialize_database(":memory:").unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+
+        let create = op(
+            0,
+            "create",
+            serde_json::json!({"id": "t1", "title": "a", "done": false}),
+        );
+        let rename = op(1, "update", serde_json::json!({"id": "t1", "title": "b"}));
+        let full = op(
+            2,
+            "update",
+            serde_json::json!({"id": "t1", "title": "c", "done": true}),
+        );
+        let partial = op(3, "update", serde_json::json!({"id": "t1", "done": false}));
+        let other = op(4, "create", serde_json::json!({"id": "t2", "title": "x"}));
+        let removed = op(5, "delete", serde_json::json!({"id": "t2"}));
+        let like = op(0, "increment", serde_json::json!({"id": "t1", "amount": 1}));
+        let unstable = op(
+            60,
+            "update",
+            serde_json::json!({"id": "t1", "title": "d", "done": true}),
+        );
+        let ops = [
+            create.clone(),
+            rename.clone(),
+            full.clone(),
+            partial.clone(),
+            other.clone(),
+            removed.clone(),
+            like.clone(),
+            unstable.clone(),
+        ];
+        merge(&mut conn, &clock, &ops).unwrap();
+        acknowledge_writers(&conn, &ops, removed.timestamp);
+
+        let stats = compact_oplog(&mut conn, clock.device_id(), &MergeRegistry::default()).unwrap();
+        assert_eq!(stats.superseded_removed, 3);
+        assert_eq!(stats.tombstones_removed, 1);
+
+        let left = remaining(&conn);
+        assert_eq!(left, vec![like.id, full.id, partial.id, unstable.id]);
+    }
+
+    #[test]
+    fn test_compacted_deletes_still_win() {
+        let mut conn = initialize_database(":memory:").unwrap();
+        conn.execute(
+            "CREATE TABLE contacts (contact_id TEXT PRIMARY KEY, name TEXT)",
+            [],
+        )
+        .unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+        let registry =
+            MergeRegistry::new().with_resolver("contacts", DeleteWins::with_key("contact_id"));
+        let contact = |minute, op_type: &str, data| OplogEntry {
+            table: "contacts".to_string(),
+            ..op(minute, op_type, data)
+        };
+
+        let create = contact(
+            0,
+            "create",
+            serde_json::json!({"contact_id": "c1", "name": "Ada"}),
+        );
+        let delete = contact(1, "delete", serde_json::json!({"contact_id": "c1"}));
+        merge_with(
+            &mut conn,
+            &clock,
+            &[create.clone(), delete.clone()],
+            &registry,
+        )
+        .unwrap();
+        acknowledge_writers(&conn, &[create.clone(), delete.clone()], delete.timestamp);
+
+        let stats = compact_oplog(&mut conn, clock.device_id(), &registry).unwrap();
+        assert_eq!(stats.superseded_removed, 1);
+        assert_eq!(stats.tombstones_removed, 1);
+        assert!(remaining(&conn).is_empty());
+
+        // A later write does not bring the contact back, and a redelivered
+        // compacted op is not recorded again
+        let recreate = contact(
+            2,
+            "create",
+            serde_json::json!({"contact_id": "c1", "name": "Ada"}),
+        );
+        merge_with(&mut conn, &clock, &[recreate.clone(), create], &registry).unwrap();
+        assert_eq!(remaining(&conn), vec![recreate.id]);
+        let rows: i64 = conn
+            .query_row("SELECT COUNT(*) FROM contacts", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 0);
+    }
+
+    #[test]
+    fn test_compaction_keeps_everything_without_acks() {
+        let mut conn = initialize_database(":memory:").unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+        let ops = [
+            op(0, "create", serde_json::json!({"id": "t1", "title": "a"})),
+            op(1, "delete", serde_json::json!({"id": "t1"})),
+        ];
+        merge(&mut conn, &clock, &ops).unwrap();
+        add_peer(&conn);
+
+        assert_eq!(
+            compact_oplog(&mut conn, clock.device_id(), &MergeRegistry::default()).unwrap(),
+            CompactionStats::default()
+        );
+        assert_eq!(remaining(&conn).len(), 2);
+    }
+
+    #[test]
+    fn test_compacted_deletes_outrank_older_writes() {
+        let mut conn = initialize_database(":memory:").unwrap();
+        conn.execute("CREATE TABLE todos (id TEXT PRIMARY KEY, title TEXT)", [])
+            .unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+        let registry = MergeRegistry::new().with_resolver("todos", LastWriteWins::new());
+        let title = |conn: &Connection| -> Option<String> {
+            conn.query_row("SELECT title FROM todos WHERE id = 't1'", [], |row| {
+                row.get(0)
+            })
+            .optional()
+            .unwrap()
+        };
+
+        let create = op(0, "create", serde_json::json!({"id": "t1", "title": "a"}));
+        let delete = op(2, "delete", serde_json::json!({"id": "t1"}));
+        merge_with(
+            &mut conn,
+            &clock,
+            &[create.clone(), delete.clone()],
+            &registry,
+        )
+        .unwrap();
+        let stats = compact_oplog_until(
+            &mut conn,
+            &registry,
+            HybridLogicalClock::from_timestamp(delete.timestamp),
+        )
+        .unwrap();
+        assert_eq!(stats.tombstones_removed, 1);
+        assert!(remaining(&conn).is_empty());
+
+        // A concurrent write older than the compacted delete stays deleted
+        let concurrent = op(1, "update", serde_json::json!({"id": "t1", "title": "b"}));
+        merge_with(&mut conn, &clock, &[concurrent], &registry).unwrap();
+        assert_eq!(title(&conn), None);
+
+        // A newer write brings the entity back
+        let recreate = op(3, "create", serde_json::json!({"id": "t1", "title": "c"}));
+        merge_with(&mut conn, &clock, &[recreate], &registry).unwrap();
+        assert_eq!(title(&conn), Some("c".to_string()));
+    }
+}
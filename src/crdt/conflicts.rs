@@ -0,0 +1,468 @@
+//! Conflict log for concurrent writes.
+//!
+//! Resolvers pick one winner when two devices write the same entity, so the
+//! other write silently disappears from the materialized row. For tables with
+//! a resolver, [`merge_with`] records such pairs in the `conflicts` table when
+//! neither write is in the causal past of the other:
+//! - writes from the same device are ordered by their HLC
+//! - a write is after every operation in its [`deps`](OplogEntry::deps), and
+//!   after every earlier write by the devices of those operations
+//!
+//! [`build_oplog_entry`](crate::build_oplog_entry) makes `create`, `update`
+//! and `delete` operations depend on the latest write from every other device
+//! to the same entity (keyed by `"id"`), so only truly concurrent writes are
+//! recorded.
+//!
+//! [`resolve_conflict`] settles a conflict by picking either write or a new
+//! value. It records a new operation depending on both writes, which every
+//! device applies like any other; devices that logged the same conflict mark
+//! it resolved when that operation arrives.
+//!
+//! [`merge_with`]: super::merge_with
+
+use super::history::key_string;
+use super::resolver::{is_row_op, MergeRegistry};
+use super::{insert_oplog_entry, new_local_op, DeviceClock};
+use crate::db::operations::row_to_oplog_entry;
+use crate::error::{AhenkError, Result};
+use crate::models::OplogEntry;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// Concurrent writes to one entity detected by merge
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Conflict {
+    /// Unique ID of the conflict
+    pub id: Uuid,
+    /// Table of the entity
+    pub table: String,
+    /// Primary key of the entity
+    pub entity_id: String,
+    /// Write that was already in the local oplog
+    pub local: OplogEntry,
+    /// Concurrent write recorded after it, usually received from a peer
+    pub remote: OplogEntry,
+    /// When the conflict was detected
+    pub detected_at: DateTime<Utc>,
+    /// When the conflict was resolved, if it was
+    pub resolved_at: Option<DateTime<Utc>>,
+    /// Operation that resolved the conflict
+    pub resolution_op_id: Option<Uuid>,
+}
+
+/// How to settle a conflict
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum ConflictResolution {
+    /// Keep the write that was already in the local oplog
+    KeepLocal,
+    /// Keep the write received from the peer
+    KeepRemote,
+    /// Write a new value for the entity
+    Value(serde_json::Value),
+}
+
+/// Latest `create`, `update` or `delete` from every other device to the
+/// entity `data` targets
+pub(crate) fn entity_heads(
+    conn: &Connection,
+    table: &str,
+    key_field: &str,
+    data: &serde_json::Value,
+    device_id: Uuid,
+) -> Result<Vec<Uuid>> {
+    let Some(key) = data.get(key_field).and_then(key_string) else {
+        return Ok(Vec::new());
+    };
+
+    let mut stmt = conn.prepare(
+        "SELECT device_id, id FROM oplog
+         WHERE table_name = ?1 AND op_type IN ('create', 'update', 'delete')
+           AND entity_id = ?2 AND device_id != ?3
+         ORDER BY timestamp, device_id, id",
+    )?;
+    let rows = stmt.query_map(params![table, key, device_id.to_string()], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+
+    // Later writes from a device replace its earlier ones
+    let mut heads = BTreeMap::new();
+    for row in rows {
+        let (device, id) = row?;
+        heads.insert(device, id);
+    }
+    Ok(heads
+        .into_values()
+        .filter_map(|id| Uuid::parse_str(&id).ok())
+        .collect())
+}
+
+/// Record a conflict if `op` is concurrent with the entity's latest write.
+///
+/// Called by merge and local writes before `op` is recorded in the oplog.
+pub(crate) fn detect(conn: &Connection, registry: &MergeRegistry, op: &OplogEntry) -> Result<()> {
+    if !is_row_op(&op.op_type) || registry.resolver(&op.table).is_none() {
+        return Ok(());
+    }
+    let key_field = registry.key_field(&op.table);
+    let Some(entity_id) = op.data.get(key_field).and_then(key_string) else {
+        return Ok(());
+    };
+
+    let latest = conn
+        .query_row(
+            "SELECT id, device_id, timestamp, table_name, op_type, data, deps, schema_version
+             FROM oplog
+             WHERE table_name = ?1 AND op_type IN ('create', 'update', 'delete')
+               AND entity_id = ?2
+             ORDER BY timestamp DESC, device_id DESC, id DESC LIMIT 1",
+            params![op.table, entity_id],
+            row_to_oplog_entry,
+        )
+        .optional()?;
+    let Some(local) = latest else {
+        return Ok(());
+    };
+
+    let both_deletes = local.op_type == "delete" && op.op_type == "delete";
+    if both_deletes
+        || local.data == op.data
+        || happened_before(conn, &local, op)?
+        || happened_before(conn, op, &local)?
+    {
+        return Ok(());
+    }
+
+    conn.execute(
+        "INSERT INTO conflicts (id, table_name, entity_id, local_op_id, local_op, remote_op_id, remote_op, detected_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            Uuid::new_v4().to_string(),
+            op.table,
+            entity_id,
+            local.id.to_string(),
+            to_json(&local)?,
+            op.id.to_string(),
+            to_json(op)?,
+            Utc::now().to_rfc3339(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Mark the open conflicts `op` settles, i.e. those whose two writes are
+/// both among its dependencies, as resolved by it
+pub(crate) fn settle(conn: &Connection, op: &OplogEntry) -> Result<()> {
+    if op.deps.len() < 2 {
+        return Ok(());
+    }
+    let deps =
+        serde_json::to_string(&op.deps).map_err(|e| AhenkError::Serialization(e.to_string()))?;
+    conn.execute(
+        "UPDATE conflicts SET resolved_at = ?1, resolution_op_id = ?2
+         WHERE resolved_at IS NULL
+           AND local_op_id IN (SELECT value FROM json_each(?3))
+           AND remote_op_id IN (SELECT value FROM json_each(?3))",
+        params![Utc::now().to_rfc3339(), op.id.to_string(), deps],
+    )?;
+    Ok(())
+}
+
+/// List unresolved conflicts, oldest first
+pub fn list_conflicts(conn: &Connection) -> Result<Vec<Conflict>> {
+    query_conflicts(conn, "WHERE resolved_at IS NULL", params![])
+}
+
+/// Look up a conflict, resolved or not
+pub fn get_conflict(conn: &Connection, conflict_id: Uuid) -> Result<Conflict> {
+    query_conflicts(conn, "WHERE id = ?1", params![conflict_id.to_string()])?
+        .pop()
+        .ok_or_else(|| AhenkError::NotFound(format!("Conflict {}", conflict_id)))
+}
+
+/// Settle a conflict, returning the operation that resolves it.
+///
+/// The operation depends on both conflicting writes and is recorded and
+/// resolved in one transaction; send it to peers like any other local
+/// operation. Picking a delete records a `delete`; anything else records an
+/// `update` with the chosen row.
+pub fn resolve_conflict(
+    conn: &mut Connection,
+    clock: &DeviceClock,
+    registry: &MergeRegistry,
+    conflict_id: Uuid,
+    resolution: ConflictResolution,
+) -> Result<OplogEntry> {
+    let tx = conn.transaction()?;
+    let conflict = get_conflict(&tx, conflict_id)?;
+    if conflict.resolved_at.is_some() {
+        return Err(AhenkError::Validation(format!(
+            "Conflict {} is already resolved",
+            conflict_id
+        )));
+    }
+
+    let key_field = registry.key_field(&conflict.table);
+    let (op_type, data) = match resolution {
+        ConflictResolution::KeepLocal => chosen_write(&conflict.local),
+        ConflictResolution::KeepRemote => chosen_write(&conflict.remote),
+        ConflictResolution::Value(mut value) => {
+            let object = value.as_object_mut().ok_or_else(|| {
+                AhenkError::Validation("Conflict resolution value must be a JSON object".into())
+            })?;
+            let key = conflict.local.data[key_field].clone();
+            object.insert(key_field.to_string(), key);
+            ("update", value)
+        }
+    };
+
+    let op = new_local_op(&tx, clock, &conflict.table, op_type, data)?
+        .with_deps([conflict.local.id, conflict.remote.id])
+        .with_schema_version(registry.schema_version(&conflict.table));
+    insert_oplog_entry(&tx, &op, key_field)?;
+    registry.resolve(&tx, &op)?;
+    settle(&tx, &op)?;
+
+    tx.commit()?;
+    registry.notify(std::slice::from_ref(&op));
+    Ok(op)
+}
+
+/// Whether `a` is in the causal past of `b`
+fn happened_before(conn: &Connection, a: &OplogEntry, b: &OplogEntry) -> Result<bool> {
+    if a.device_id == b.device_id {
+        return Ok(a.order_key() < b.order_key());
+    }
+    if b.deps.contains(&a.id) {
+        return Ok(true);
+    }
+
+    let deps =
+        serde_json::to_string(&b.deps).map_err(|e| AhenkError::Serialization(e.to_string()))?;
+    let covered = conn.query_row(
+        "SELECT EXISTS (
+             SELECT 1 FROM oplog WHERE id IN (SELECT value FROM json_each(?1))
+               AND device_id = ?2 AND (timestamp, id) >= (?3, ?4)
+         )",
+        params![deps, a.device_id.to_string(), a.timestamp, a.id.to_string()],
+        |row| row.get(0),
+    )?;
+    Ok(covered)
+}
+
+fn chosen_write(op: &OplogEntry) -> (&'static str, serde_json::Value) {
+    if op.op_type == "delete" {
+        ("delete", op.data.clone())
+    } else {
+        ("update", op.data.clone())
+    }
+}
+
+fn to_json(op: &OplogEntry) -> Result<String> {
+    serde_json::to_string(op).map_err(|e| AhenkError::Serialization(e.to_string()))
+}
+
+fn query_conflicts(
+    conn: &Connection,
+    filter: &str,
+    params: impl rusqlite::Params,
+) -> Result<Vec<Conflict>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, table_name, entity_id, local_op, remote_op, detected_at, resolved_at, resolution_op_id
+         FROM conflicts {} ORDER BY detected_at, id",
+        filter
+    ))?;
+    let rows = stmt.query_map(params, |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, String>(3)?,
+            row.get::<_, String>(4)?,
+            row.get::<_, String>(5)?,
+            row.get::<_, Option<String>>(6)?,
+            row.get::<_, Option<String>>(7)?,
+        ))
+    })?;
+
+    let parse_time = |s: &str| {
+        DateTime::parse_from_rfc3339(s)
+            .map(|t| t.with_timezone(&Utc))
+            .map_err(|e| AhenkError::Serialization(e.to_string()))
+    };
+    let parse_id =
+        |s: &str| Uuid::parse_str(s).map_err(|e| AhenkError::Serialization(e.to_string()));
+    let parse_op = |s: &str| {
+        serde_json::from_str::<OplogEntry>(s).map_err(|e| AhenkError::Serialization(e.to_string()))
+    };
+
+    let mut conflicts = Vec::new();
+    for row in rows {
+        let (id, table, entity_id, local, remote, detected_at, resolved_at, resolution_op_id) =
+            row?;
+        conflicts.push(Conflict {
+            id: parse_id(&id)?,
+            table,
+            entity_id,
+            local: parse_op(&local)?,
+            remote: parse_op(&remote)?,
+            detected_at: parse_time(&detected_at)?,
+            resolved_at: resolved_at.as_deref().map(parse_time).transpose()?,
+            resolution_op_id: resolution_op_id.as_deref().map(parse_id).transpose()?,
+        });
+    }
+    Ok(conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{local_apply_with, merge_with, LastWriteWins};
+    use crate::db::operations::{get_oplog_entries_since, initialize_database};
+    use crate::logic::build_oplog_entry;
+    use serde_json::json;
+
+    fn replica() -> (Connection, DeviceClock) {
+        let conn = initialize_database(":memory:").unwrap();
+        conn.execute("CREATE TABLE todos (id TEXT PRIMARY KEY, title TEXT)", [])
+            .unwrap();
+        (conn, DeviceClock::new(Uuid::new_v4()))
+    }
+
+    fn write(
+        conn: &mut Connection,
+        clock: &DeviceClock,
+        registry: &MergeRegistry,
+        op_type: &str,
+        title: &str,
+    ) -> OplogEntry {
+        let data = json!({"id": "t1", "title": title});
+        let op = build_oplog_entry(conn, clock, "todos", op_type, &data).unwrap();
+        local_apply_with(conn, &op, registry).unwrap();
+        op
+    }
+
+    fn title(conn: &Connection) -> String {
+        conn.query_row("SELECT title FROM todos WHERE id = 't1'", [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sequential_writes_are_not_conflicts() {
+        let registry = MergeRegistry::new().with_resolver("todos", LastWriteWins::new());
+        let (mut phone, phone_clock) = replica();
+        let (mut laptop, laptop_clock) = replica();
+
+        let create = write(&mut phone, &phone_clock, &registry, "create", "draft");
+        merge_with(
+            &mut laptop,
+            &laptop_clock,
+            std::slice::from_ref(&create),
+            &registry,
+        )
+        .unwrap();
+
+        // The laptop saw the create, so its update depends on it
+        let update = write(&mut laptop, &laptop_clock, &registry, "update", "final");
+        assert_eq!(update.deps, vec![create.id]);
+        merge_with(&mut phone, &phone_clock, &[update], &registry).unwrap();
+
+        assert!(list_conflicts(&phone).unwrap().is_empty());
+        assert_eq!(title(&phone), "final");
+    }
+
+    #[test]
+    fn test_concurrent_writes_are_logged_and_resolved() {
+        let registry = MergeRegistry::new().with_resolver("todos", LastWriteWins::new());
+        let (mut phone, phone_clock) = replica();
+        let (mut laptop, laptop_clock) = replica();
+
+        let create = write(&mut phone, &phone_clock, &registry, "create", "draft");
+        merge_with(&mut laptop, &laptop_clock, &[create], &registry).unwrap();
+
+        // Both devices edit while offline
+        let ours = write(&mut phone, &phone_clock, &registry, "update", "phone");
+        let theirs = write(&mut laptop, &laptop_clock, &registry, "update", "laptop");
+        merge_with(
+            &mut phone,
+            &phone_clock,
+            std::slice::from_ref(&theirs),
+            &registry,
+        )
+        .unwrap();
+        merge_with(
+            &mut laptop,
+            &laptop_clock,
+            std::slice::from_ref(&ours),
+            &registry,
+        )
+        .unwrap();
+
+        let conflicts = list_conflicts(&phone).unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].entity_id, "t1");
+        assert_eq!(conflicts[0].local.id, ours.id);
+        assert_eq!(conflicts[0].remote.data["title"], "laptop");
+        assert_eq!(list_conflicts(&laptop).unwrap().len(), 1);
+
+        // The loser of last-write-wins is picked on the phone
+        let loser = if ours.timestamp < theirs.timestamp {
+            ConflictResolution::KeepLocal
+        } else {
+            ConflictResolution::KeepRemote
+        };
+        let op = resolve_conflict(
+            &mut phone,
+            &phone_clock,
+            &registry,
+            conflicts[0].id,
+            loser.clone(),
+        )
+        .unwrap();
+        assert!(list_conflicts(&phone).unwrap().is_empty());
+        assert_eq!(
+            get_conflict(&phone, conflicts[0].id)
+                .unwrap()
+                .resolution_op_id,
+            Some(op.id)
+        );
+        assert!(matches!(
+            resolve_conflict(&mut phone, &phone_clock, &registry, conflicts[0].id, loser),
+            Err(AhenkError::Validation(_))
+        ));
+
+        // The laptop converges and closes its copy of the conflict
+        let ops = get_oplog_entries_since(&phone, 0).unwrap();
+        merge_with(&mut laptop, &laptop_clock, &ops, &registry).unwrap();
+        assert!(list_conflicts(&laptop).unwrap().is_empty());
+        assert_eq!(title(&laptop), title(&phone));
+        assert_ne!(title(&phone), "draft");
+    }
+
+    #[test]
+    fn test_resolve_with_new_value() {
+        let registry = MergeRegistry::new().with_resolver("todos", LastWriteWins::new());
+        let (mut phone, phone_clock) = replica();
+        let (mut laptop, laptop_clock) = replica();
+
+        write(&mut phone, &phone_clock, &registry, "create", "phone");
+        let theirs = write(&mut laptop, &laptop_clock, &registry, "create", "laptop");
+        merge_with(&mut phone, &phone_clock, &[theirs], &registry).unwrap();
+
+        let conflict = list_conflicts(&phone).unwrap().remove(0);
+        resolve_conflict(
+            &mut phone,
+            &phone_clock,
+            &registry,
+            conflict.id,
+            ConflictResolution::Value(json!({"title": "merged"})),
+        )
+        .unwrap();
+        assert_eq!(title(&phone), "merged");
+    }
+}
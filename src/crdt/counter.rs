@@ -0,0 +1,142 @@
+//! PN-Counter operations.
+//!
+//! A counter is identified by `(table, counter id)`. Every change is an
+//! `increment` operation carrying a signed amount:
+//!
+//! ```json
+//! {"id": "post-42-likes", "amount": 1}
+//! ```
+//!
+//! The oplog stores each operation exactly once, so the converged value is
+//! the sum of all increments, whatever order they arrived in. Increments made
+//! on two offline devices both count once the devices sync. Amounts must be
+//! non-zero integers; [`OplogEntry::validate`] rejects anything else.
+
+use super::{new_local_op, DeviceClock};
+use crate::error::{AhenkError, Result};
+use crate::models::OplogEntry;
+use rusqlite::{params, Connection};
+
+/// Operation type for counter increments (negative amounts decrement)
+pub const INCREMENT: &str = "increment";
+
+/// Build an operation adding `amount` to a counter.
+///
+/// Record it with [`local_apply`](super::local_apply) like any other
+/// operation.
+pub fn increment_op(
+    conn: &Connection,
+    clock: &DeviceClock,
+    table: &str,
+    counter_id: &str,
+    amount: i64,
+) -> Result<OplogEntry> {
+    if amount == 0 {
+        return Err(AhenkError::Validation(
+            "Counter increment must be non-zero".to_string(),
+        ));
+    }
+
+    new_local_op(
+        conn,
+        clock,
+        table,
+        INCREMENT,
+        serde_json::json!({"id": counter_id, "amount": amount}),
+    )
+}
+
+/// Converged value of a counter (0 if it was never incremented).
+///
+/// Fails with [`AhenkError::Validation`] if the sum does not fit in an `i64`.
+pub fn counter_value(conn: &Connection, table: &str, counter_id: &str) -> Result<i64> {
+    let mut stmt = conn.prepare(
+        "SELECT json_extract(data, '$.amount') FROM oplog
+         WHERE table_name = ?1 AND op_type = ?2 AND json_extract(data, '$.id') = ?3
+           AND json_type(data, '$.amount') = 'integer'",
+    )?;
+    // Sum in i128 so the result does not depend on the order amounts are read
+    let mut total: i128 = 0;
+    for amount in stmt.query_map(params![table, INCREMENT, counter_id], |row| {
+        row.get::<_, i64>(0)
+    })? {
+        total += i128::from(amount?);
+    }
+    i64::try_from(total)
+        .map_err(|_| AhenkError::Validation(format!("Counter '{}' overflows an i64", counter_id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{local_apply, merge};
+    use crate::db::operations::{get_oplog_entries_since, initialize_database};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_concurrent_increments_converge() {
+        let mut phone = initialize_database(":memory:").unwrap();
+        let mut laptop = initialize_database(":memory:").unwrap();
+        let phone_clock = DeviceClock::new(Uuid::new_v4());
+        let laptop_clock = DeviceClock::new(Uuid::new_v4());
+
+        // Both devices like the post while offline; the laptop also un-likes once
+        for amount in [1, 1] {
+            let op = increment_op(&phone, &phone_clock, "likes", "post-42", amount).unwrap();
+            local_apply(&mut phone, &op).unwrap();
+        }
+        for amount in [1, -1, 1] {
+            let op = increment_op(&laptop, &laptop_clock, "likes", "post-42", amount).unwrap();
+            local_apply(&mut laptop, &op).unwrap();
+        }
+        assert_eq!(counter_value(&phone, "likes", "post-42").unwrap(), 2);
+        assert_eq!(counter_value(&laptop, "likes", "post-42").unwrap(), 1);
+
+        let from_phone = get_oplog_entries_since(&phone, 0).unwrap();
+        let from_laptop = get_oplog_entries_since(&laptop, 0).unwrap();
+        merge(&mut phone, &phone_clock, &from_laptop).unwrap();
+        merge(&mut laptop, &laptop_clock, &from_phone).unwrap();
+        // Redelivery is idempotent
+        merge(&mut laptop, &laptop_clock, &from_phone).unwrap();
+
+        assert_eq!(counter_value(&phone, "likes", "post-42").unwrap(), 3);
+        assert_eq!(counter_value(&laptop, "likes", "post-42").unwrap(), 3);
+        assert_eq!(counter_value(&laptop, "likes", "post-7").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_zero_increment_is_rejected() {
+        let conn = initialize_database(":memory:").unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+        assert!(matches!(
+            increment_op(&conn, &clock, "likes", "post-42", 0),
+            Err(AhenkError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_remote_increments_are_rejected() {
+        let mut conn = initialize_database(":memory:").unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+        let remote = DeviceClock::new(Uuid::new_v4());
+
+        for amount in [serde_json::json!(0.5), serde_json::json!("x")] {
+            let mut op = increment_op(&conn, &remote, "likes", "post-42", 1).unwrap();
+            op.data["amount"] = amount;
+            assert!(matches!(
+                merge(&mut conn, &clock, &[op]),
+                Err(AhenkError::InvalidOperation { .. })
+            ));
+        }
+
+        // Sums that leave the i64 range are reported instead of wrapping
+        for _ in 0..2 {
+            let op = increment_op(&conn, &remote, "likes", "post-42", i64::MAX).unwrap();
+            merge(&mut conn, &clock, &[op]).unwrap();
+        }
+        assert!(matches!(
+            counter_value(&conn, "likes", "post-42"),
+            Err(AhenkError::Validation(_))
+        ));
+    }
+}
@@ -0,0 +1,257 @@
+//! Persistent, monotonic per-device clock service.
+//!
+//! [`DeviceClock`] stores the last HLC issued or observed by a device in the
+//! `device_clock` table. Every local operation calls [`DeviceClock::tick`] and
+//! every merged remote operation calls [`DeviceClock::observe`], so the
+//! timestamps a device hands out are strictly increasing, even across process
+//! restarts or when the wall clock is set backwards.
+//!
+//! A maximum drift bound can be configured with [`DeviceClock::with_max_drift`];
+//! remote timestamps further ahead of local wall-clock time are refused, so a
+//! single device with a wrong clock cannot drag every replica into the future.
+
+use super::clock::{Clock, SystemClock};
+use super::HybridLogicalClock;
+use crate::error::{AhenkError, Result};
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fmt;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// HLC service for a single device, backed by the database.
+///
+/// The struct itself holds no clock state; the database row is the source
+/// of truth, so any number of `DeviceClock` values for the same device can
+/// share one connection. Physical time is read from an injectable [`Clock`].
+#[derive(Clone)]
+pub struct DeviceClock {
+    device_id: Uuid,
+    source: Arc<dyn Clock>,
+    max_drift: Option<Duration>,
+}
+
+impl DeviceClock {
+    /// Create a clock service for the given device using the system clock
+    pub fn new(device_id: Uuid) -> Self {
+        Self::with_clock(device_id, Arc::new(SystemClock))
+    }
+
+    /// Create a clock service that reads physical time from `source`
+    pub fn with_clock(device_id: Uuid, source: Arc<dyn Clock>) -> Self {
+        Self {
+            device_id,
+            source,
+            max_drift: None,
+        }
+    }
+
+    /// Refuse remote timestamps more than `max_drift` ahead of local time
+    pub fn with_max_drift(mut self, max_drift: Duration) -> Self {
+        self.max_drift = Some(max_drift);
+        self
+    }
+
+    /// Configured maximum drift, if any
+    pub fn max_drift(&self) -> Option<Duration> {
+        self.max_drift
+    }
+
+    /// Device this clock issues timestamps for
+    pub fn device_id(&self) -> Uuid {
+        self.device_id
+    }
+
+    /// Wall-clock source used by this service
+    pub fn source(&self) -> &dyn Clock {
+        self.source.as_ref()
+    }
+
+    /// Current wall-clock time according to the clock source
+    pub fn wall_time(&self) -> DateTime<Utc> {
+        self.source.now()
+    }
+
+    /// Last HLC issued or observed by this device, if any
+    pub fn last(&self, conn: &Connection) -> Result<Option<HybridLogicalClock>> {
+        let timestamp: Option<i64> = conn
+            .query_row(
+                "SELECT timestamp FROM device_clock WHERE device_id = ?1",
+                params![self.device_id.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(timestamp.map(HybridLogicalClock::from_timestamp))
+    }
+
+    /// Issue a timestamp for a local operation.
+    ///
+    /// The result is strictly greater than every timestamp previously issued
+    /// or observed by this device.
+    pub fn tick(&self, conn: &Connection) -> Result<HybridLogicalClock> {
+        self.advance(conn, None)
+    }
+
+    /// Advance the clock past a timestamp received from a remote device.
+    ///
+    /// Fails with [`AhenkError::ClockDrift`] without touching the stored
+    /// clock if the remote timestamp exceeds the maximum drift bound.
+    pub fn observe(
+        &self,
+        conn: &Connection,
+        remote: HybridLogicalClock,
+    ) -> Result<HybridLogicalClock> {
+        self.check_drift(remote)?;
+        self.advance(conn, Some(remote))
+    }
+
+    /// Check a remote timestamp against the maximum drift bound
+    pub fn check_drift(&self, remote: HybridLogicalClock) -> Result<()> {
+        let Some(max_drift) = self.max_drift else {
+            return Ok(());
+        };
+
+        let ahead = remote.to_datetime() - self.wall_time();
+        if ahead > max_drift {
+            return Err(AhenkError::ClockDrift {
+                timestamp: remote.to_timestamp(),
+                ahead_ms: ahead.num_milliseconds(),
+                max_drift_ms: max_drift.num_milliseconds(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Read-modify-write of the stored clock inside a savepoint, so it works
+    /// both standalone and inside a caller's transaction.
+    fn advance(
+        &self,
+        conn: &Connection,
+        remote: Option<HybridLogicalClock>,
+    ) -> Result<HybridLogicalClock> {
+        conn.execute_batch("SAVEPOINT device_clock")?;
+
+        let result = self.last(conn).and_then(|last| {
+            let mut hlc = last.unwrap_or_default();
+            hlc.increment_with(remote, self.source.as_ref());
+            conn.execute(
+                "INSERT INTO device_clock (device_id, timestamp) VALUES (?1, ?2)
+                 ON CONFLICT(device_id) DO UPDATE SET timestamp = excluded.timestamp",
+                params![self.device_id.to_string(), hlc.to_timestamp()],
+            )?;
+            Ok(hlc)
+        });
+
+        match result {
+            Ok(hlc) => {
+                conn.execute_batch("RELEASE device_clock")?;
+                Ok(hlc)
+            }
+            Err(e) => {
+                conn.execute_batch("ROLLBACK TO device_clock; RELEASE device_clock")?;
+                Err(e)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for DeviceClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceClock")
+            .field("device_id", &self.device_id)
+            .field("max_drift", &self.max_drift)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::clock::ManualClock;
+    use crate::db::operations::initialize_database;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_tick_is_strictly_increasing() {
+        let conn = initialize_database(":memory:").unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+
+        let mut previous = clock.tick(&conn).unwrap();
+        for _ in 0..1000 {
+            let next = clock.tick(&conn).unwrap();
+            assert!(next > previous);
+            previous = next;
+        }
+        assert_eq!(clock.last(&conn).unwrap(), Some(previous));
+    }
+
+    #[test]
+    fn test_tick_survives_restart_and_clock_rollback() {
+        let conn = initialize_database(":memory:").unwrap();
+        let device_id = Uuid::new_v4();
+        let start = Utc.with_ymd_and_hms(2024, 6, 1, 8, 0, 0).unwrap();
+        let wall = ManualClock::new(start);
+
+        let before_restart = DeviceClock::with_clock(device_id, Arc::new(wall.clone()))
+            .tick(&conn)
+            .unwrap();
+
+        // The process restarts and the wall clock has been set an hour back
+        wall.set(start - Duration::hours(1));
+        let clock = DeviceClock::with_clock(device_id, Arc::new(wall.clone()));
+        let after_restart = clock.tick(&conn).unwrap();
+
+        assert!(after_restart > before_restart);
+        assert_eq!(after_restart, HybridLogicalClock::new(start, 1));
+    }
+
+    #[test]
+    fn test_observe_advances_past_remote() {
+        let conn = initialize_database(":memory:").unwrap();
+        let start = Utc.with_ymd_and_hms(2024, 6, 1, 8, 0, 0).unwrap();
+        let clock = DeviceClock::with_clock(Uuid::new_v4(), Arc::new(ManualClock::new(start)));
+        clock.tick(&conn).unwrap();
+
+        // Remote device's clock runs five minutes ahead
+        let remote = HybridLogicalClock::new(start + Duration::minutes(5), 9);
+        let observed = clock.observe(&conn, remote).unwrap();
+        assert_eq!(
+            observed,
+            HybridLogicalClock::new(start + Duration::minutes(5), 10)
+        );
+        assert!(clock.tick(&conn).unwrap() > observed);
+    }
+
+    #[test]
+    fn test_clocks_are_per_device() {
+        let conn = initialize_database(":memory:").unwrap();
+        let a = DeviceClock::new(Uuid::new_v4());
+        let b = DeviceClock::new(Uuid::new_v4());
+
+        a.tick(&conn).unwrap();
+        assert!(b.last(&conn).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_observe_rejects_excessive_drift() {
+        let conn = initialize_database(":memory:").unwrap();
+        let start = Utc.with_ymd_and_hms(2024, 6, 1, 8, 0, 0).unwrap();
+        let clock = DeviceClock::with_clock(Uuid::new_v4(), Arc::new(ManualClock::new(start)))
+            .with_max_drift(Duration::minutes(1));
+        let before = clock.tick(&conn).unwrap();
+
+        let within = HybridLogicalClock::new(start + Duration::seconds(30), 0);
+        assert!(clock.observe(&conn, within).is_ok());
+
+        let years_ahead = HybridLogicalClock::new(start + Duration::days(365 * 3), 0);
+        let err = clock.observe(&conn, years_ahead).unwrap_err();
+        assert!(matches!(err, AhenkError::ClockDrift { .. }));
+
+        // The stored clock was not dragged forward
+        let last = clock.last(&conn).unwrap().unwrap();
+        assert!(last > before);
+        assert!(last < years_ahead);
+    }
+}
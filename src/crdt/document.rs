@@ -0,0 +1,354 @@
+//! Field-level last-write-wins document materialization.
+//!
+//! [`FieldLww`] is a [`MergeResolver`] that merges entities field by field
+//! instead of replacing whole rows. Every field of every entity carries the
+//! [`OrderKey`](crate::OrderKey) of the operation that last wrote it
+//! (`document_fields`), and deletes leave a tombstone (`document_tombstones`). After each operation the
+//! entity's current state is written to the app table.
+//!
+//! Update operations should carry only the fields they change (plus the key):
+//! concurrent updates to different fields then both survive, while concurrent
+//! updates to the same field resolve last-write-wins.
+//!
+//! ```text
+//! phone:  update {"id": "t1", "completed": true}           @ 2000
+//! laptop: update {"id": "t1", "title": "Buy snacks"}        @ 2001
+//! merged: {"id": "t1", "title": "Buy snacks", "completed": true}
+//! ```
+//!
+//! A delete hides every field written before it. A later write resurrects the
+//! entity with only the fields written after the delete.
+//!
+//! Entities that have received [`patch`](super::patch) operations are instead
+//! rebuilt by replaying their writes and patches in HLC order, with writes
+//! still merged field by field.
+
+use super::history::{has_patches, replay_entity};
+use super::patch::is_patch_op;
+use super::resolver::{delete_row, entity_key, is_row_op, upsert_row, MergeResolver, Replay};
+use crate::error::{AhenkError, Result};
+use crate::models::OplogEntry;
+use rusqlite::types::Value;
+use rusqlite::{params, Connection, OptionalExtension};
+
+/// Field-level last-write-wins resolver
+#[derive(Debug, Clone)]
+pub struct FieldLww {
+    key: String,
+}
+
+impl FieldLww {
+    /// Materialize entities keyed by the `"id"` field
+    pub fn new() -> Self {
+        Self::with_key("id")
+    }
+
+    /// Materialize entities keyed by a custom primary-key field
+    pub fn with_key(key: impl Into<String>) -> Self {
+        Self { key: key.into() }
+    }
+
+    /// Current merged state of an entity, or `None` if it was deleted or
+    /// never written
+    pub fn document(
+        &self,
+        conn: &Connection,
+        table: &str,
+        entity_id: &str,
+    ) -> Result<Option<serde_json::Value>> {
+        if has_patches(conn, table, entity_id)? {
+            let fields = replay_entity(conn, table, entity_id, Replay::MergeFields)?;
+            return Ok(fields
+                .filter(|fields| !fields.is_empty())
+                .map(serde_json::Value::Object));
+        }
+
+        let tombstone: Option<(i64, String, String)> = conn
+            .query_row(
+                "SELECT timestamp, device_id, op_id FROM document_tombstones
+                 WHERE table_name = ?1 AND entity_id = ?2",
+                params![table, entity_id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT field, value, timestamp, device_id, op_id FROM document_fields
+             WHERE table_name = ?1 AND entity_id = ?2 ORDER BY field",
+        )?;
+        let rows = stmt.query_map(params![table, entity_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                (
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                ),
+            ))
+        })?;
+
+        let mut fields = serde_json::Map::new();
+        for row in rows {
+            let (field, value, written) = row?;
+            if tombstone
+                .as_ref()
+                .is_some_and(|deleted| &written <= deleted)
+            {
+                continue;
+            }
+            let value = serde_json::from_str(&value)
+                .map_err(|e| AhenkError::Serialization(e.to_string()))?;
+            fields.insert(field, value);
+        }
+
+        if fields.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::Value::Object(fields)))
+    }
+
+    /// Fold a delete into the entity's tombstone
+    fn record_delete(&self, conn: &Connection, op: &OplogEntry, entity_id: &str) -> Result<()> {
+        conn.execute(
+            "INSERT INTO document_tombstones (table_name, entity_id, timestamp, device_id, op_id)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(table_name, entity_id) DO UPDATE SET
+                timestamp = excluded.timestamp, device_id = excluded.device_id,
+                op_id = excluded.op_id
+             WHERE (excluded.timestamp, excluded.device_id, excluded.op_id)
+                > (document_tombstones.timestamp, document_tombstones.device_id,
+                   document_tombstones.op_id)",
+            params![
+                op.table,
+                entity_id,
+                op.timestamp,
+                op.device_id.to_string(),
+                op.id.to_string()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Fold each written field into its per-field clock
+    fn record_fields(&self, conn: &Connection, op: &OplogEntry, entity_id: &str) -> Result<()> {
+        let object = op.data.as_object().ok_or_else(|| {
+            AhenkError::Validation(format!("Operation {} data must be a JSON object", op.id))
+        })?;
+
+        let mut stmt = conn.prepare(
+            "INSERT INTO document_fields
+                (table_name, entity_id, field, value, timestamp, device_id, op_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT(table_name, entity_id, field) DO UPDATE SET
+                value = excluded.value, timestamp = excluded.timestamp,
+                device_id = excluded.device_id, op_id = excluded.op_id
+             WHERE (excluded.timestamp, excluded.device_id, excluded.op_id)
+                > (document_fields.timestamp, document_fields.device_id, document_fields.op_id)",
+        )?;
+        for (field, value) in object {
+            stmt.execute(params![
+                op.table,
+                entity_id,
+                field,
+                value.to_string(),
+                op.timestamp,
+                op.device_id.to_string(),
+                op.id.to_string(),
+            ])?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for FieldLww {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MergeResolver for FieldLww {
+    fn key_field(&self) -> &str {
+        &self.key
+    }
+
+    fn replay(&self) -> Replay {
+        Replay::MergeFields
+    }
+
+    fn resolve(&self, conn: &Connection, op: &OplogEntry) -> Result<()> {
+        if !is_row_op(&op.op_type) && !is_patch_op(&op.op_type) {
+            return Ok(());
+        }
+        let key = entity_key(op, &self.key)?;
+        let entity_id = match &key {
+            Value::Text(s) => s.clone(),
+            Value::Integer(i) => i.to_string(),
+            Value::Real(f) => f.to_string(),
+            _ => unreachable!("entity_key only returns text or numbers"),
+        };
+
+        if op.op_type == "delete" {
+            self.record_delete(conn, op, &entity_id)?;
+        } else if is_row_op(&op.op_type) {
+            self.record_fields(conn, op, &entity_id)?;
+        }
+
+        match self.document(conn, &op.table, &entity_id)? {
+            Some(document) => upsert_row(conn, &op.table, &document),
+            None => delete_row(conn, &op.table, &self.key, &key),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{
+        local_apply_with, merge_with, DeviceClock, HybridLogicalClock, MergeRegistry,
+    };
+    use crate::db::operations::initialize_database;
+    use chrono::{Duration, TimeZone, Utc};
+    use uuid::Uuid;
+
+    fn setup() -> (Connection, DeviceClock, MergeRegistry) {
+        let conn = initialize_database(":memory:").unwrap();
+        conn.execute(
+            "CREATE TABLE todos (id TEXT PRIMARY KEY, title TEXT, completed INTEGER)",
+            [],
+        )
+        .unwrap();
+        let registry = MergeRegistry::new().with_resolver("todos", FieldLww::new());
+        (conn, DeviceClock::new(Uuid::new_v4()), registry)
+    }
+
+    fn op(millis: i64, op_type: &str, data: serde_json::Value) -> OplogEntry {
+        let time =
+            Utc.with_ymd_and_hms(2024, 6, 1, 8, 0, 0).unwrap() + Duration::milliseconds(millis);
+        OplogEntry {
+            id: Uuid::new_v4(),
+            device_id: Uuid::new_v4(),
+            timestamp: HybridLogicalClock::new(time, 0).to_timestamp(),
+            table: "todos".to_string(),
+            op_type: op_type.to_string(),
+            data,
+            deps: Vec::new(),
+            schema_version: 1,
+        }
+    }
+
+    fn row(conn: &Connection) -> Option<(String, bool)> {
+        conn.query_row(
+            "SELECT title, completed FROM todos WHERE id = 't1'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_concurrent_edits_to_different_fields_both_survive() {
+        let create = op(
+            1000,
+            "create",
+            serde_json::json!({"id": "t1", "title": "Buy groceries", "completed": false}),
+        );
+        let complete = op(
+            2000,
+            "update",
+            serde_json::json!({"id": "t1", "completed": true}),
+        );
+        let rename = op(
+            2001,
+            "update",
+            serde_json::json!({"id": "t1", "title": "Buy groceries and snacks"}),
+        );
+
+        // Every delivery order converges to the same state
+        for order in [
+            [&create, &complete, &rename],
+            [&create, &rename, &complete],
+            [&rename, &complete, &create],
+        ] {
+            let (mut conn, clock, registry) = setup();
+            for op in order {
+                merge_with(&mut conn, &clock, std::slice::from_ref(op), &registry).unwrap();
+            }
+            assert_eq!(
+                row(&conn),
+                Some(("Buy groceries and snacks".to_string(), true))
+            );
+        }
+    }
+
+    #[test]
+    fn test_same_field_resolves_last_write_wins() {
+        let (mut conn, clock, registry) = setup();
+        let older = op(
+            1000,
+            "create",
+            serde_json::json!({"id": "t1", "title": "older", "completed": false}),
+        );
+        let newer = op(
+            2000,
+            "update",
+            serde_json::json!({"id": "t1", "title": "newer"}),
+        );
+
+        merge_with(&mut conn, &clock, &[newer, older], &registry).unwrap();
+        assert_eq!(row(&conn), Some(("newer".to_string(), false)));
+    }
+
+    #[test]
+    fn test_delete_hides_earlier_fields() {
+        let (mut conn, clock, registry) = setup();
+        let resolver = FieldLww::new();
+        let create = op(
+            1000,
+            "create",
+            serde_json::json!({"id": "t1", "title": "draft", "completed": false}),
+        );
+        let delete = op(2000, "delete", serde_json::json!({"id": "t1"}));
+        let stale = op(
+            1500,
+            "update",
+            serde_json::json!({"id": "t1", "title": "stale"}),
+        );
+
+        merge_with(&mut conn, &clock, &[create, delete, stale], &registry).unwrap();
+        assert_eq!(row(&conn), None);
+        assert!(resolver.document(&conn, "todos", "t1").unwrap().is_none());
+
+        // A write after the delete resurrects the entity with just that field
+        let revive = op(
+            3000,
+            "update",
+            serde_json::json!({"id": "t1", "completed": true}),
+        );
+        merge_with(&mut conn, &clock, &[revive], &registry).unwrap();
+        assert_eq!(
+            resolver.document(&conn, "todos", "t1").unwrap(),
+            Some(serde_json::json!({"id": "t1", "completed": true}))
+        );
+    }
+
+    #[test]
+    fn test_local_ops_participate_in_field_clocks() {
+        let (mut conn, clock, registry) = setup();
+        let local = op(
+            5000,
+            "create",
+            serde_json::json!({"id": "t1", "title": "local", "completed": false}),
+        );
+        let remote = op(
+            4000,
+            "update",
+            serde_json::json!({"id": "t1", "title": "remote", "completed": true}),
+        );
+
+        local_apply_with(&mut conn, &local, &registry).unwrap();
+        merge_with(&mut conn, &clock, &[remote], &registry).unwrap();
+        assert_eq!(row(&conn), Some(("local".to_string(), false)));
+    }
+}
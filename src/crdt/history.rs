@@ -0,0 +1,268 @@
+//! Point-in-time reconstruction of table state.
+//!
+//! [`table_at`] and [`entity_at`] replay the `create`, `update`, `delete`
+//! and [patch](super::patch) operations recorded up to a given HLC and return
+//! the rows as the table's resolver materialized them at that moment (see
+//! [`Replay`]). Without a resolver, writes are merged field by field: a write
+//! sets the fields it carries, a patch edits the entity as it stood, and a
+//! delete removes the entity until it is written again.
+//!
+//! Replay only sees operations still in the oplog. Below the stable point,
+//! [compaction](super::compaction) may have removed superseded writes, so
+//! history there can be incomplete.
+
+use super::patch::{apply_patch_op, is_patch_op};
+use super::resolver::{is_row_op, MergeRegistry, Replay};
+use super::HybridLogicalClock;
+use crate::error::{AhenkError, Result};
+use rusqlite::{params, Connection};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// Rows of a table at `at` (inclusive), keyed by entity ID.
+///
+/// Writes are replayed as the table's resolver in `registry` applies them,
+/// and entities are keyed by its primary-key field (`"id"` without one);
+/// numeric keys are rendered as strings.
+pub fn table_at(
+    conn: &Connection,
+    registry: &MergeRegistry,
+    table: &str,
+    at: HybridLogicalClock,
+) -> Result<BTreeMap<String, Value>> {
+    let key_field = registry.key_field(table);
+    let replay = registry.replay(table);
+    let mut stmt = conn.prepare(
+        "SELECT op_type, data FROM oplog
+         WHERE table_name = ?1 AND timestamp <= ?2
+           AND op_type IN ('create', 'update', 'delete', 'patch', 'merge-patch')
+         ORDER BY timestamp, device_id, id",
+    )?;
+    let rows = stmt.query_map(params![table, at.to_timestamp()], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+
+    let mut entities: BTreeMap<String, EntityReplay> = BTreeMap::new();
+    for row in rows {
+        let (op_type, data) = row?;
+        let data: Value =
+            serde_json::from_str(&data).map_err(|e| AhenkError::Serialization(e.to_string()))?;
+        let Some(key) = data.get(key_field).and_then(key_string) else {
+            continue;
+        };
+        entities
+            .entry(key)
+            .or_default()
+            .apply(replay, &op_type, &data);
+    }
+
+    Ok(entities
+        .into_iter()
+        .filter_map(|(key, entity)| Some((key, Value::Object(entity.state?))))
+        .collect())
+}
+
+/// State of one entity at `at` (inclusive), or `None` if it did not exist
+pub fn entity_at(
+    conn: &Connection,
+    registry: &MergeRegistry,
+    table: &str,
+    entity_id: &str,
+    at: HybridLogicalClock,
+) -> Result<Option<Value>> {
+    Ok(table_at(conn, registry, table, at)?.remove(entity_id))
+}
+
+/// Current state of one entity, replayed from every write and patch in the
+/// oplog as `replay` folds them
+pub(crate) fn replay_entity(
+    conn: &Connection,
+    table: &str,
+    entity_id: &str,
+    replay: Replay,
+) -> Result<Option<Map<String, Value>>> {
+    let mut stmt = conn.prepare(
+        "SELECT op_type, data FROM oplog
+         WHERE table_name = ?1 AND entity_id = ?2
+           AND op_type IN ('create', 'update', 'delete', 'patch', 'merge-patch')
+         ORDER BY timestamp, device_id, id",
+    )?;
+    let rows = stmt.query_map(params![table, entity_id], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+
+    let mut entity = EntityReplay::default();
+    for row in rows {
+        let (op_type, data) = row?;
+        let data: Value =
+            serde_json::from_str(&data).map_err(|e| AhenkError::Serialization(e.to_string()))?;
+        entity.apply(replay, &op_type, &data);
+    }
+    Ok(entity.state)
+}
+
+/// An entity being rebuilt from its operations in HLC order
+#[derive(Default)]
+pub(crate) struct EntityReplay {
+    pub(crate) state: Option<Map<String, Value>>,
+    /// Later operations no longer change the entity
+    settled: bool,
+}
+
+impl EntityReplay {
+    pub(crate) fn apply(&mut self, replay: Replay, op_type: &str, data: &Value) {
+        if self.settled {
+            return;
+        }
+        let write = matches!(op_type, "create" | "update");
+        match replay {
+            Replay::MergeFields => {}
+            Replay::ReplaceRow if write => self.state = None,
+            Replay::ReplaceRow => {}
+            Replay::DeleteWins if write => self.state = None,
+            Replay::DeleteWins => self.settled = op_type == "delete",
+            Replay::KeepFirst if is_row_op(op_type) => self.settled = true,
+            // Write-once records ignore patches
+            Replay::KeepFirst => return,
+        }
+        apply_row_op(&mut self.state, op_type, data);
+    }
+}
+
+/// Whether any `patch` or `merge-patch` operation targets an entity
+pub(crate) fn has_patches(conn: &Connection, table: &str, entity_id: &str) -> Result<bool> {
+    Ok(conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM oplog WHERE table_name = ?1 AND entity_id = ?2
+         AND op_type IN ('patch', 'merge-patch'))",
+        params![table, entity_id],
+        |row| row.get(0),
+    )?)
+}
+
+/// Fold a `create`, `update`, `delete` or patch payload into an entity's
+/// fields. Patches to an entity that does not exist are ignored.
+pub(crate) fn apply_row_op(state: &mut Option<Map<String, Value>>, op_type: &str, data: &Value) {
+    if is_patch_op(op_type) {
+        if let Some(fields) = state {
+            apply_patch_op(fields, op_type, data);
+        }
+        return;
+    }
+    if !is_row_op(op_type) {
+        return;
+    }
+    if op_type == "delete" {
+        *state = None;
+    } else if let Some(fields) = data.as_object() {
+        state.get_or_insert_with(Map::new).extend(fields.clone());
+    }
+}
+
+pub(crate) fn key_string(key: &Value) -> Option<String> {
+    match key {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{local_apply, DeleteWins, DeviceClock, KeepFirst, LastWriteWins};
+    use crate::db::operations::initialize_database;
+    use crate::logic::build_oplog_entry;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_replay_table_at_each_point() {
+        let mut conn = initialize_database(":memory:").unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+        let mut write = |op_type: &str, data: Value| {
+            let op = build_oplog_entry(&conn, &clock, "todos", op_type, &data).unwrap();
+            local_apply(&mut conn, &op).unwrap();
+            HybridLogicalClock::from_timestamp(op.timestamp)
+        };
+
+        let created = write(
+            "create",
+            json!({"id": "t1", "title": "draft", "done": false}),
+        );
+        write("create", json!({"id": "t2", "title": "other"}));
+        let renamed = write("update", json!({"id": "t1", "title": "final"}));
+        let deleted = write("delete", json!({"id": "t1"}));
+        write("update", json!({"id": "t1", "done": true}));
+        let registry = MergeRegistry::new();
+
+        assert_eq!(
+            entity_at(&conn, &registry, "todos", "t1", created).unwrap(),
+            Some(json!({"id": "t1", "title": "draft", "done": false}))
+        );
+        assert_eq!(
+            entity_at(&conn, &registry, "todos", "t1", renamed).unwrap(),
+            Some(json!({"id": "t1", "title": "final", "done": false}))
+        );
+        assert_eq!(
+            table_at(&conn, &registry, "todos", deleted)
+                .unwrap()
+                .into_keys()
+                .collect::<Vec<_>>(),
+            vec!["t2"]
+        );
+
+        // A write after a delete resurrects only the fields it carries
+        let latest = HybridLogicalClock::from_timestamp(i64::MAX);
+        assert_eq!(
+            entity_at(&conn, &registry, "todos", "t1", latest).unwrap(),
+            Some(json!({"id": "t1", "done": true}))
+        );
+        assert!(table_at(
+            &conn,
+            &registry,
+            "todos",
+            HybridLogicalClock::from_timestamp(0)
+        )
+        .unwrap()
+        .is_empty());
+    }
+
+    #[test]
+    fn test_replay_follows_the_resolver() {
+        let mut conn = initialize_database(":memory:").unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+        let mut write = |op_type: &str, data: Value| {
+            let op = build_oplog_entry(&conn, &clock, "todos", op_type, &data).unwrap();
+            local_apply(&mut conn, &op).unwrap();
+            HybridLogicalClock::from_timestamp(op.timestamp)
+        };
+
+        write(
+            "create",
+            json!({"id": "t1", "title": "draft", "done": false}),
+        );
+        let renamed = write("update", json!({"id": "t1", "title": "final"}));
+        write("delete", json!({"id": "t1"}));
+        let latest = write("create", json!({"id": "t1", "title": "again"}));
+
+        let at = |registry: MergeRegistry, at| entity_at(&conn, &registry, "todos", "t1", at);
+        let lww = || MergeRegistry::new().with_resolver("todos", LastWriteWins::new());
+        assert_eq!(
+            at(lww(), renamed).unwrap(),
+            Some(json!({"id": "t1", "title": "final"}))
+        );
+        assert_eq!(
+            at(lww(), latest).unwrap(),
+            Some(json!({"id": "t1", "title": "again"}))
+        );
+
+        // Deleted entities stay deleted, write-once entities keep their first write
+        let delete_wins = MergeRegistry::new().with_resolver("todos", DeleteWins::new());
+        assert_eq!(at(delete_wins, latest).unwrap(), None);
+        let keep_first = MergeRegistry::new().with_resolver("todos", KeepFirst::new());
+        assert_eq!(
+            at(keep_first, latest).unwrap(),
+            Some(json!({"id": "t1", "title": "draft", "done": false}))
+        );
+    }
+}
@@ -0,0 +1,390 @@
+//! Hybrid Logical Clock (HLC) timestamps.
+//!
+//! An HLC packs wall-clock time and a logical counter into a single `u64`
+//! that is stored in `oplog.timestamp`:
+//!
+//! ```text
+//! | 48 bits physical time (milliseconds since epoch) | 16 bits counter |
+//! ```
+//!
+//! 48 bits of milliseconds cover dates up to the year 10889, so present and
+//! future wall-clock times never overflow. The encoded value always fits in a
+//! non-negative `i64`, which keeps SQLite `INTEGER` ordering identical to HLC
+//! ordering.
+
+use super::clock::{Clock, SystemClock};
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// Number of bits used by the logical counter
+const COUNTER_BITS: u32 = 16;
+
+/// Largest physical time (milliseconds since epoch) an HLC can represent
+pub const MAX_PHYSICAL_TIME: u64 = (1 << 48) - 1;
+
+/// Hybrid Logical Clock for maintaining causal ordering of operations.
+///
+/// HLC combines physical time (system clock) with a logical counter to ensure:
+/// - Events on the same device are totally ordered
+/// - Events across devices can be causally ordered
+/// - Clock drift is bounded
+///
+/// Format: 48 bits physical time (milliseconds) + 16 bits counter
+///
+/// Clocks on different devices can produce equal timestamps; order
+/// operations by [`OplogEntry::order_key`](crate::OplogEntry::order_key) to
+/// break such ties.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct HybridLogicalClock {
+    timestamp: u64,
+}
+
+impl HybridLogicalClock {
+    /// Create a new HLC from physical time and counter.
+    ///
+    /// Times before the Unix epoch are clamped to zero and times past
+    /// [`MAX_PHYSICAL_TIME`] are clamped to the maximum.
+    pub fn new(physical_time: DateTime<Utc>, counter: u16) -> Self {
+        let millis = physical_time.timestamp_millis().max(0) as u64;
+        Self::from_parts(millis, counter)
+    }
+
+    /// Create a new HLC from milliseconds since epoch and counter
+    pub fn from_parts(physical_millis: u64, counter: u16) -> Self {
+        let physical = physical_millis.min(MAX_PHYSICAL_TIME);
+        Self {
+            timestamp: (physical << COUNTER_BITS) | counter as u64,
+        }
+    }
+
+    /// Create HLC from a raw timestamp value
+    pub fn from_timestamp(timestamp: i64) -> Self {
+        Self {
+            timestamp: timestamp as u64,
+        }
+    }
+
+    /// Convert HLC to raw timestamp value
+    pub fn to_timestamp(&self) -> i64 {
+        self.timestamp as i64
+    }
+
+    /// Extract physical time component (milliseconds since epoch)
+    pub fn physical_time(&self) -> u64 {
+        self.timestamp >> COUNTER_BITS
+    }
+
+    /// Extract logical counter component
+    pub fn counter(&self) -> u16 {
+        (self.timestamp & 0xFFFF) as u16
+    }
+
+    /// Physical time component as a UTC date-time
+    pub fn to_datetime(&self) -> DateTime<Utc> {
+        Utc.timestamp_millis_opt(self.physical_time() as i64)
+            .single()
+            .unwrap_or_default()
+    }
+
+    /// Create HLC with current physical time and zero counter
+    pub fn now() -> Self {
+        Self::now_with(&SystemClock)
+    }
+
+    /// Create HLC with the given clock's current time and zero counter
+    pub fn now_with(clock: &dyn Clock) -> Self {
+        Self::new(clock.now(), 0)
+    }
+
+    /// Increment HLC, optionally synchronizing with remote time
+    ///
+    /// This implements the HLC update rule:
+    /// - Advance to max of local and remote physical time
+    /// - Increment counter if physical times are equal
+    ///
+    /// If the counter would overflow, the physical component is advanced by
+    /// one millisecond instead, so the clock still moves strictly forward.
+    pub fn increment(&mut self, remote_time: Option<Self>) {
+        self.increment_with(remote_time, &SystemClock)
+    }
+
+    /// Same as [`increment`](Self::increment), reading physical time from
+    /// the given clock.
+    pub fn increment_with(&mut self, remote_time: Option<Self>, clock: &dyn Clock) {
+        let physical_now = clock.now().timestamp_millis().max(0) as u64;
+        let local_physical = self.physical_time();
+
+        let (physical, counter) = match remote_time {
+            // Local operation: increment based on current time
+            None => {
+                let new_physical = physical_now.max(local_physical);
+                if new_physical == local_physical {
+                    (new_physical, Some(self.counter()))
+                } else {
+                    (new_physical, None)
+                }
+            }
+            // Remote synchronization
+            Some(remote) => {
+                let remote_physical = remote.physical_time();
+                let new_physical = physical_now.max(local_physical).max(remote_physical);
+
+                if new_physical == local_physical && new_physical == remote_physical {
+                    (new_physical, Some(self.counter().max(remote.counter())))
+                } else if new_physical == local_physical {
+                    (new_physical, Some(self.counter()))
+                } else if new_physical == remote_physical {
+                    (new_physical, Some(remote.counter()))
+                } else {
+                    (new_physical, None)
+                }
+            }
+        };
+
+        *self = match counter {
+            // Time advanced, reset counter
+            None => Self::from_parts(physical, 0),
+            Some(counter) => match counter.checked_add(1) {
+                Some(next) => Self::from_parts(physical, next),
+                // Counter exhausted, borrow the next millisecond
+                None => Self::from_parts(physical + 1, 0),
+            },
+        };
+    }
+}
+
+impl PartialOrd for HybridLogicalClock {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for HybridLogicalClock {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.timestamp.cmp(&other.timestamp)
+    }
+}
+
+/// Formats the clock as `<RFC 3339 physical time>#<counter>`,
+/// e.g. `2024-10-22T09:15:03.412Z#3`.
+impl fmt::Display for HybridLogicalClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}#{}",
+            self.to_datetime()
+                .to_rfc3339_opts(SecondsFormat::Millis, true),
+            self.counter()
+        )
+    }
+}
+
+/// Error returned when parsing a [`HybridLogicalClock`] from a string fails
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseHlcError(String);
+
+impl fmt::Display for ParseHlcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid HLC '{}'", self.0)
+    }
+}
+
+impl std::error::Error for ParseHlcError {}
+
+/// Parses the [`Display`](fmt::Display) format.
+///
+/// Also accepts a plain RFC 3339 time (counter 0) and the raw integer
+/// encoding stored in `oplog.timestamp`.
+impl FromStr for HybridLogicalClock {
+    type Err = ParseHlcError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let err = || ParseHlcError(s.to_string());
+
+        if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+            let raw: i64 = s.parse().map_err(|_| err())?;
+            return Ok(Self::from_timestamp(raw));
+        }
+
+        let (time, counter) = match s.rsplit_once('#') {
+            Some((time, counter)) => (time, counter.parse::<u16>().map_err(|_| err())?),
+            None => (s, 0),
+        };
+
+        let physical = DateTime::parse_from_rfc3339(time).map_err(|_| err())?;
+        Ok(Self::new(physical.with_timezone(&Utc), counter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::clock::ManualClock;
+    use chrono::Duration;
+
+    #[test]
+    fn test_hlc_ordering() {
+        let hlc1 = HybridLogicalClock::new(Utc::now(), 0);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let hlc2 = HybridLogicalClock::now();
+
+        assert!(hlc1 < hlc2);
+    }
+
+    #[test]
+    fn test_hlc_increment() {
+        // Test 1: Increment always advances HLC (either time or counter)
+        let mut hlc = HybridLogicalClock::now();
+        let initial = hlc.to_timestamp();
+
+        hlc.increment(None);
+        assert!(
+            hlc.to_timestamp() > initial,
+            "HLC should advance after increment"
+        );
+
+        // Test 2: Multiple rapid increments advance HLC
+        for _ in 0..5 {
+            let before = hlc.to_timestamp();
+            hlc.increment(None);
+            assert!(
+                hlc.to_timestamp() > before,
+                "Each increment should advance HLC"
+            );
+        }
+
+        // Test 3: Increment with remote time synchronizes correctly
+        let mut hlc1 = HybridLogicalClock::now();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let hlc2 = HybridLogicalClock::now();
+
+        let before = hlc1.to_timestamp();
+        hlc1.increment(Some(hlc2));
+
+        // After syncing with a later time, HLC should advance
+        assert!(
+            hlc1.to_timestamp() >= before,
+            "Syncing with remote time should advance HLC"
+        );
+        assert!(
+            hlc1.to_timestamp() >= hlc2.to_timestamp(),
+            "After sync, local HLC should be >= remote"
+        );
+    }
+
+    #[test]
+    fn test_hlc_roundtrip() {
+        let hlc = HybridLogicalClock::now();
+        let timestamp = hlc.to_timestamp();
+        let hlc2 = HybridLogicalClock::from_timestamp(timestamp);
+
+        assert_eq!(hlc, hlc2);
+    }
+
+    #[test]
+    fn test_hlc_current_time_does_not_overflow() {
+        let now = Utc::now();
+        let hlc = HybridLogicalClock::new(now, 7);
+
+        assert!(hlc.to_timestamp() > 0);
+        assert_eq!(hlc.physical_time(), now.timestamp_millis() as u64);
+        assert_eq!(hlc.counter(), 7);
+
+        // Year 3000 still round-trips
+        let future = Utc.with_ymd_and_hms(3000, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!(HybridLogicalClock::new(future, 0).to_datetime(), future);
+    }
+
+    #[test]
+    fn test_hlc_counter_overflow_advances_physical_time() {
+        let clock = ManualClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap());
+        let physical = clock.now().timestamp_millis() as u64;
+        let mut hlc = HybridLogicalClock::from_parts(physical, u16::MAX);
+
+        hlc.increment_with(None, &clock);
+
+        assert_eq!(hlc.physical_time(), physical + 1);
+        assert_eq!(hlc.counter(), 0);
+    }
+
+    #[test]
+    fn test_hlc_increment_with_skewed_clock() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let clock = ManualClock::new(start);
+        let mut hlc = HybridLogicalClock::now_with(&clock);
+
+        // Wall clock does not move: counter advances
+        hlc.increment_with(None, &clock);
+        assert_eq!(hlc, HybridLogicalClock::new(start, 1));
+
+        // Wall clock jumps backwards: physical time is kept, counter advances
+        clock.set(start - Duration::minutes(10));
+        hlc.increment_with(None, &clock);
+        assert_eq!(hlc, HybridLogicalClock::new(start, 2));
+
+        // Wall clock catches up: counter resets
+        clock.set(start + Duration::seconds(1));
+        hlc.increment_with(None, &clock);
+        assert_eq!(
+            hlc,
+            HybridLogicalClock::new(start + Duration::seconds(1), 0)
+        );
+    }
+
+    #[test]
+    fn test_hlc_increment_with_remote_time() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let clock = ManualClock::new(start);
+        let mut hlc = HybridLogicalClock::new(start, 3);
+
+        // Remote ahead of local wall time: adopt remote physical time
+        let remote = HybridLogicalClock::new(start + Duration::seconds(5), 7);
+        hlc.increment_with(Some(remote), &clock);
+        assert_eq!(
+            hlc,
+            HybridLogicalClock::new(start + Duration::seconds(5), 8)
+        );
+
+        // Remote at the same physical time: max counter + 1
+        let remote = HybridLogicalClock::new(start + Duration::seconds(5), 20);
+        hlc.increment_with(Some(remote), &clock);
+        assert_eq!(
+            hlc,
+            HybridLogicalClock::new(start + Duration::seconds(5), 21)
+        );
+
+        // Remote behind: keep local time, bump local counter
+        let remote = HybridLogicalClock::new(start, 0);
+        hlc.increment_with(Some(remote), &clock);
+        assert_eq!(
+            hlc,
+            HybridLogicalClock::new(start + Duration::seconds(5), 22)
+        );
+    }
+
+    #[test]
+    fn test_hlc_display_from_str_roundtrip() {
+        let time = Utc.with_ymd_and_hms(2024, 10, 22, 9, 15, 3).unwrap();
+        let hlc = HybridLogicalClock::new(time, 3);
+
+        assert_eq!(hlc.to_string(), "2024-10-22T09:15:03.000Z#3");
+        assert_eq!(hlc.to_string().parse::<HybridLogicalClock>().unwrap(), hlc);
+        assert_eq!(
+            "2024-10-22T09:15:03Z"
+                .parse::<HybridLogicalClock>()
+                .unwrap(),
+            HybridLogicalClock::new(time, 0)
+        );
+        assert_eq!(
+            hlc.to_timestamp()
+                .to_string()
+                .parse::<HybridLogicalClock>()
+                .unwrap(),
+            hlc
+        );
+        assert!("yesterday".parse::<HybridLogicalClock>().is_err());
+    }
+}
@@ -0,0 +1,388 @@
+//! CRDT (Conflict-free Replicated Data Type) implementation.
+//!
+//! This module provides the foundation for distributed synchronization:
+//! - Hybrid Logical Clock for causal ordering
+//! - Injectable wall-clock sources
+//! - Persistent per-device clock service
+//! - Quarantine for remote operations with excessive clock drift
+//! - Causal delivery buffer for operations with unmet dependencies
+//! - Operation log management and causally-stable compaction
+//! - Pluggable per-table merge resolvers
+//! - Trigger-based change capture for app tables
+//! - Tracked transactions grouping app writes with their operations
+//! - Conflict log for concurrent writes with manual resolution
+//! - Change subscriptions for committed local and merged operations
+//! - Schema-versioned payloads with per-table upcasters
+//! - Field-level last-write-wins document materialization
+//! - PN-Counter and OR-Set operation types
+//! - RGA sequence CRDT for ordered lists and text
+//! - JSON Patch and JSON Merge Patch partial updates
+//! - Per-device version vectors for delta sync
+//! - Point-in-time reconstruction of table state
+//! - Snapshot checkpoints for bootstrapping new devices
+//! - Undo/redo through compensating operations
+//! - Range-fingerprint anti-entropy for oplog reconciliation
+//!
+//! Apps register a [`MergeResolver`] per table (or use one of the built-ins)
+//! to have merged operations applied to their tables, or implement their own
+//! merge logic using the HLC and oplog primitives provided here.
+
+pub mod anti_entropy;
+pub mod capture;
+pub mod causal;
+pub mod clock;
+pub mod compaction;
+pub mod conflicts;
+pub mod counter;
+pub mod device_clock;
+pub mod document;
+pub mod history;
+pub mod hlc;
+pub mod observer;
+pub mod orset;
+pub mod patch;
+pub mod quarantine;
+pub mod resolver;
+pub mod sequence;
+pub mod snapshot;
+pub mod tracked;
+pub mod undo;
+pub mod upcast;
+pub mod version_vector;
+
+pub use anti_entropy::{
+    range_fingerprint, reconcile, root_range, Fingerprint, RangeContent, RangeSummary,
+    Reconciliation,
+};
+pub use capture::{register_capture_clock, track_table, tracked_tables, untrack_table};
+pub use causal::list_pending;
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use compaction::{
+    acknowledged_prefix, compact_oplog, compact_oplog_until, record_peer_ack, stable_watermark,
+    CompactionStats,
+};
+pub use conflicts::{get_conflict, list_conflicts, resolve_conflict, Conflict, ConflictResolution};
+pub use counter::{counter_value, increment_op};
+pub use device_clock::DeviceClock;
+pub use document::FieldLww;
+pub use history::{entity_at, table_at};
+pub use hlc::{HybridLogicalClock, ParseHlcError};
+pub use observer::{SubscriptionId, TableFilter};
+pub use orset::{set_add_op, set_members, set_remove_op};
+pub use patch::{apply_merge_patch, apply_patch, merge_patch_op, patch_op};
+pub use quarantine::{discard_quarantined, list_quarantined, release_quarantined, QuarantinedOp};
+pub use resolver::{DeleteWins, KeepFirst, LastWriteWins, MergeRegistry, MergeResolver, Replay};
+pub use sequence::{
+    seq_delete_at, seq_delete_op, seq_insert_at, seq_insert_op, seq_insert_text, sequence_elements,
+    sequence_text, sequence_values, SequenceElement,
+};
+pub use snapshot::{
+    checkpoint, create_snapshot, latest_checkpoint, restore_snapshot, save_checkpoint, Snapshot,
+};
+pub use tracked::{group_operations, operation_group, with_tracked_tx, TrackedTx};
+pub use undo::{can_redo, can_undo, inverse_op, record_undo_group, redo, undo, undo_group};
+pub use upcast::{upcast_oplog, Upcaster};
+pub use version_vector::VersionVector;
+
+use crate::error::{AhenkError, Result};
+use crate::OplogEntry;
+use rusqlite::Connection;
+use uuid::Uuid;
+
+// ============================================================================
+// Operation Application
+// ============================================================================
+
+/// Apply a local operation and record it in the oplog.
+///
+/// This function records the operation in the oplog for later synchronization.
+/// Apps should implement their own table-specific logic before calling this.
+/// The app's write and the oplog insert commit separately; use
+/// [`with_tracked_tx`] to commit them atomically.
+///
+/// # Example
+/// ```rust,no_run
+/// use ahenk::{local_apply, build_oplog_entry, DeviceClock};
+/// # use rusqlite::Connection;
+/// # use uuid::Uuid;
+///
+/// # fn example(mut conn: Connection, user_id: Uuid, device_id: Uuid) -> Result<(), Box<dyn std::error::Error>> {
+/// let clock = DeviceClock::new(device_id);
+///
+/// // App creates a record in their table
+/// conn.execute(
+///     "INSERT INTO my_app_data (id, value) VALUES (?1, ?2)",
+///     rusqlite::params!["id1", "value1"],
+/// )?;
+///
+/// // Record operation in oplog for sync
+/// let entry = build_oplog_entry(
+///     &conn,
+///     &clock,
+///     "my_app_data",
+///     "create",
+///     &serde_json::json!({"id": "id1", "value": "value1"}),
+/// )?;
+/// local_apply(&mut conn, &entry)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn local_apply(conn: &mut Connection, op: &OplogEntry) -> Result<()> {
+    op.validate()?;
+
+    // Check if operation already exists (idempotency)
+    if !oplog_contains(conn, op)? {
+        // Record operation in oplog; without a resolver entities are keyed by `id`
+        insert_oplog_entry(conn, op, "id")?;
+    }
+
+    Ok(())
+}
+
+/// Apply a local operation with the resolver registered for its table.
+///
+/// Records the operation like [`local_apply`] and, in the same transaction,
+/// runs the table's resolver so that resolvers which keep their own state
+/// (such as [`FieldLww`]) see local writes as well as remote ones.
+/// Operations older than the table's newest schema version are upcast first.
+/// Subscribers on `registry` (see [`observer`]) are notified after commit.
+pub fn local_apply_with(
+    conn: &mut Connection,
+    op: &OplogEntry,
+    registry: &MergeRegistry,
+) -> Result<()> {
+    op.validate()?;
+    let tx = conn.transaction()?;
+    let recorded = apply_local(&tx, op, registry)?;
+    tx.commit()?;
+    registry.notify(recorded.as_slice());
+    Ok(())
+}
+
+/// Record a validated local operation and run its resolver, returning the
+/// recorded (upcast) entry unless it was already known.
+///
+/// Local operations get the same treatment as remote ones: upcasting,
+/// conflict detection and settling, then the table's resolver.
+pub(crate) fn apply_local(
+    conn: &Connection,
+    op: &OplogEntry,
+    registry: &MergeRegistry,
+) -> Result<Option<OplogEntry>> {
+    if oplog_contains(conn, op)? {
+        return Ok(None);
+    }
+    let op = registry.upcast(op)?;
+    conflicts::detect(conn, registry, &op)?;
+    insert_oplog_entry(conn, &op, registry.key_field(&op.table))?;
+    conflicts::settle(conn, &op)?;
+    registry.resolve(conn, &op)?;
+    Ok(Some(op))
+}
+
+/// Merge remote operations into the local database.
+///
+/// This function merges operations from remote peers, recording them in the oplog.
+/// Apps should implement their own conflict resolution logic and table updates,
+/// or use [`merge_with`] to have registered resolvers apply them.
+///
+/// The function:
+/// 1. Checks if each operation already exists (idempotency)
+/// 2. Records new operations in the oplog
+/// 3. Advances the local device clock past each new operation's timestamp
+/// 4. Apps must handle actual table updates based on their conflict resolution strategy
+///
+/// Operations whose timestamp is further ahead of local time than the clock's
+/// maximum drift are moved to the quarantine table instead (see
+/// [`list_quarantined`] and [`release_quarantined`]). Operations whose
+/// [`deps`](OplogEntry::deps) are not all in the oplog wait in the causal
+/// delivery buffer (see [`list_pending`]) and are recorded by the merge that
+/// delivers their last dependency.
+///
+/// If any operation has a malformed table name or op type (see
+/// [`OplogEntry::validate`]), the whole batch is rejected with
+/// [`AhenkError::InvalidOperation`] and nothing is recorded.
+///
+/// # Example
+/// ```rust,no_run
+/// use ahenk::{merge, DeviceClock, OplogEntry};
+/// # use rusqlite::Connection;
+/// # use uuid::Uuid;
+///
+/// # fn example(mut conn: Connection, device_id: Uuid, remote_ops: Vec<OplogEntry>) -> Result<(), Box<dyn std::error::Error>> {
+/// // Merge operations from remote peer
+/// let clock = DeviceClock::new(device_id);
+/// merge(&mut conn, &clock, &remote_ops)?;
+///
+/// // App should now apply operations to their tables with conflict resolution
+/// for op in remote_ops {
+///     // App-specific logic here based on op.table, op.op_type, and op.data
+///     // Use HLC timestamps for last-write-wins or custom conflict resolution
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn merge(conn: &mut Connection, clock: &DeviceClock, remote_ops: &[OplogEntry]) -> Result<()> {
+    merge_with(conn, clock, remote_ops, &MergeRegistry::default())
+}
+
+/// Merge remote operations and apply them with per-table resolvers.
+///
+/// Behaves like [`merge`], and additionally upcasts each new operation to its
+/// table's newest schema version (see [`upcast`]) and calls the resolver
+/// registered for the table right after recording it, and notifies the
+/// registry's subscribers (see [`observer`]) after commit. Writes concurrent with
+/// the entity's latest write are recorded in the conflict log (see
+/// [`list_conflicts`]). Everything happens in
+/// one transaction: if a resolver fails, neither the oplog nor any app table
+/// is changed.
+///
+/// # Example
+/// ```rust,no_run
+/// use ahenk::{merge_with, DeleteWins, DeviceClock, LastWriteWins, MergeRegistry, OplogEntry};
+/// # use rusqlite::Connection;
+/// # use uuid::Uuid;
+///
+/// # fn example(mut conn: Connection, device_id: Uuid, remote_ops: Vec<OplogEntry>) -> Result<(), Box<dyn std::error::Error>> {
+/// let clock = DeviceClock::new(device_id);
+/// let registry = MergeRegistry::new()
+///     .with_resolver("tasks", LastWriteWins::new())
+///     .with_resolver("contacts", DeleteWins::with_key("contact_id"));
+///
+/// merge_with(&mut conn, &clock, &remote_ops, &registry)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn merge_with(
+    conn: &mut Connection,
+    clock: &DeviceClock,
+    remote_ops: &[OplogEntry],
+    registry: &MergeRegistry,
+) -> Result<()> {
+    // Reject the whole batch before anything is persisted
+    for op in remote_ops {
+        op.validate()?;
+    }
+
+    let tx = conn.transaction()?;
+    let mut recorded = Vec::new();
+
+    for op in remote_ops {
+        if !oplog_contains(&tx, op)? {
+            let timestamp = HybridLogicalClock::from_timestamp(op.timestamp);
+            match clock.observe(&tx, timestamp) {
+                Ok(_) => {}
+                Err(e @ AhenkError::ClockDrift { .. }) => {
+                    quarantine::quarantine_op(&tx, op, &e.to_string(), clock.wall_time())?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+
+            deliver(&tx, registry, op, &mut recorded)?;
+        }
+    }
+    deliver_ready(&tx, registry, &mut recorded)?;
+
+    tx.commit()?;
+    registry.notify(&recorded);
+    Ok(())
+}
+
+/// Record a remote operation that is not in the oplog yet, or hold it in the
+/// causal buffer until its dependencies arrive
+fn deliver(
+    conn: &Connection,
+    registry: &MergeRegistry,
+    op: &OplogEntry,
+    recorded: &mut Vec<OplogEntry>,
+) -> Result<()> {
+    if !causal::deps_satisfied(conn, &op.deps)? {
+        return causal::buffer_op(conn, op);
+    }
+
+    // Record operation in oplog, in the newest payload shape
+    let op = registry.upcast(op)?;
+    conflicts::detect(conn, registry, &op)?;
+    insert_oplog_entry(conn, &op, registry.key_field(&op.table))?;
+    conflicts::settle(conn, &op)?;
+
+    registry.resolve(conn, &op)?;
+    recorded.push(op);
+    Ok(())
+}
+
+/// Deliver buffered operations whose dependencies have now arrived
+fn deliver_ready(
+    conn: &Connection,
+    registry: &MergeRegistry,
+    recorded: &mut Vec<OplogEntry>,
+) -> Result<()> {
+    loop {
+        let ready = causal::take_ready(conn)?;
+        if ready.is_empty() {
+            return Ok(());
+        }
+        for op in &ready {
+            if !oplog_contains(conn, op)? {
+                deliver(conn, registry, op, recorded)?;
+            }
+        }
+    }
+}
+
+/// Whether `op` was already recorded, including operations since removed by
+/// compaction
+fn oplog_contains(conn: &Connection, op: &OplogEntry) -> rusqlite::Result<bool> {
+    let mut stmt = conn.prepare(
+        "SELECT 1 FROM oplog WHERE id = ?1 UNION ALL SELECT 1 FROM oplog_compacted WHERE id = ?1",
+    )?;
+    stmt.exists([op.id.to_string()])
+}
+
+/// Record `op` in the oplog, indexed by the value of its `key_field`
+fn insert_oplog_entry(conn: &Connection, op: &OplogEntry, key_field: &str) -> rusqlite::Result<()> {
+    let data = serde_json::to_string(&op.data)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    let deps = crate::db::operations::deps_column(&op.deps)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    let entity_id = op.data.get(key_field).and_then(history::key_string);
+    conn.execute(
+        "INSERT INTO oplog (id, device_id, timestamp, table_name, op_type, data, deps, schema_version, entity_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        rusqlite::params![
+            op.id.to_string(),
+            op.device_id.to_string(),
+            op.timestamp,
+            op.table,
+            op.op_type,
+            data,
+            deps,
+            op.schema_version,
+            entity_id,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Build a local operation stamped by the device clock, depending on the
+/// device's previous operation
+pub(crate) fn new_local_op(
+    conn: &Connection,
+    clock: &DeviceClock,
+    table: &str,
+    op_type: &str,
+    data: serde_json::Value,
+) -> Result<OplogEntry> {
+    let timestamp = clock.tick(conn)?;
+    let deps = causal::device_head(conn, clock.device_id())?;
+    Ok(OplogEntry {
+        id: Uuid::new_v4(),
+        device_id: clock.device_id(),
+        timestamp: timestamp.to_timestamp(),
+        table: table.to_string(),
+        op_type: op_type.to_string(),
+        data,
+        deps: deps.into_iter().collect(),
+        schema_version: 1,
+    })
+}
@@ -0,0 +1,207 @@
+//! Change subscriptions for committed operations.
+//!
+//! Apps subscribe on a [`MergeRegistry`] with a [`TableFilter`] and are told
+//! about every operation newly recorded through that registry, after the
+//! transaction that recorded it commits:
+//! - [`local_apply_with`](super::local_apply_with), undo/redo and conflict
+//!   resolution (local operations)
+//! - [`merge_with`](super::merge_with), and so
+//!   [`handle_sync_message`](crate::handle_sync_message) and
+//!   [`release_quarantined`](super::release_quarantined) (remote operations)
+//!
+//! Each commit delivers one batch, in the order the operations were recorded
+//! and in their upcast payload shape. Operations that were already known,
+//! or are still quarantined or waiting for dependencies, are not delivered.
+//!
+//! [`MergeRegistry::subscribe`] runs a callback on the committing thread;
+//! [`MergeRegistry::subscribe_channel`] returns a stream for async consumers.
+//! Clones of a registry share their subscriptions, so subscribing on the
+//! registry passed to a [`SyncManager`](crate::SyncManager) still works.
+
+use super::MergeRegistry;
+use crate::models::OplogEntry;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Tables a subscription receives operations for
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TableFilter {
+    /// Every table
+    #[default]
+    All,
+    /// Only the listed tables
+    Tables(BTreeSet<String>),
+}
+
+impl TableFilter {
+    /// Only operations on the given tables
+    pub fn tables(tables: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        TableFilter::Tables(tables.into_iter().map(Into::into).collect())
+    }
+
+    /// Whether operations on `table` pass the filter
+    pub fn matches(&self, table: &str) -> bool {
+        match self {
+            TableFilter::All => true,
+            TableFilter::Tables(tables) => tables.contains(table),
+        }
+    }
+}
+
+/// Handle for removing a subscription with [`MergeRegistry::unsubscribe`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+/// Callback receiving each committed batch of matching operations.
+///
+/// Returns `false` to cancel the subscription.
+type Callback = Arc<dyn Fn(&[OplogEntry]) -> bool + Send + Sync>;
+
+/// Subscriptions shared by clones of a [`MergeRegistry`]
+#[derive(Default)]
+pub(crate) struct Observers {
+    next_id: AtomicU64,
+    subscriptions: Mutex<Vec<(SubscriptionId, TableFilter, Callback)>>,
+}
+
+impl Observers {
+    fn add(&self, filter: TableFilter, callback: Callback) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.lock().push((id, filter, callback));
+        id
+    }
+
+    fn remove(&self, id: SubscriptionId) -> bool {
+        let mut subscriptions = self.lock();
+        let before = subscriptions.len();
+        subscriptions.retain(|(sub_id, _, _)| *sub_id != id);
+        subscriptions.len() != before
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<(SubscriptionId, TableFilter, Callback)>> {
+        // A panicking callback runs outside the lock, so the list is intact
+        self.subscriptions
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+}
+
+impl MergeRegistry {
+    /// Call `callback` with the operations on tables matching `filter` after
+    /// each commit that records any
+    pub fn subscribe(
+        &self,
+        filter: TableFilter,
+        callback: impl Fn(&[OplogEntry]) + Send + Sync + 'static,
+    ) -> SubscriptionId {
+        self.observers.add(
+            filter,
+            Arc::new(move |ops: &[OplogEntry]| {
+                callback(ops);
+                true
+            }),
+        )
+    }
+
+    /// Stream of committed batches of operations on tables matching `filter`.
+    ///
+    /// The subscription ends when the receiver is dropped.
+    pub fn subscribe_channel(&self, filter: TableFilter) -> UnboundedReceiver<Vec<OplogEntry>> {
+        let (sender, receiver) = unbounded();
+        self.observers.add(
+            filter,
+            Arc::new(move |ops: &[OplogEntry]| sender.unbounded_send(ops.to_vec()).is_ok()),
+        );
+        receiver
+    }
+
+    /// Remove a subscription; returns whether it existed
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        self.observers.remove(id)
+    }
+
+    /// Deliver operations recorded by a committed transaction
+    pub(crate) fn notify(&self, ops: &[OplogEntry]) {
+        if ops.is_empty() || self.observers.is_empty() {
+            return;
+        }
+
+        let subscriptions = self.observers.lock().clone();
+        for (id, filter, callback) in subscriptions {
+            let matching: Vec<OplogEntry> = ops
+                .iter()
+                .filter(|op| filter.matches(&op.table))
+                .cloned()
+                .collect();
+            if !matching.is_empty() && !callback(&matching) {
+                self.observers.remove(id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{local_apply_with, merge_with, DeviceClock};
+    use crate::db::operations::initialize_database;
+    use crate::logic::build_oplog_entry;
+    use futures::StreamExt;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_subscribers_see_committed_ops() {
+        let mut phone = initialize_database(":memory:").unwrap();
+        let mut laptop = initialize_database(":memory:").unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+        let registry = MergeRegistry::new();
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let id = registry.subscribe(TableFilter::tables(["todos"]), move |ops| {
+            sink.lock()
+                .unwrap()
+                .extend(ops.iter().map(|op| op.table.clone()));
+        });
+        let mut all = registry.clone().subscribe_channel(TableFilter::All);
+
+        let todo = build_oplog_entry(&phone, &clock, "todos", "create", &json!({"id": 1})).unwrap();
+        let note = build_oplog_entry(&phone, &clock, "notes", "create", &json!({"id": 2})).unwrap();
+        local_apply_with(&mut phone, &todo, &registry).unwrap();
+        // Already-known operations are not delivered again
+        local_apply_with(&mut phone, &todo, &registry).unwrap();
+
+        let laptop_clock = DeviceClock::new(Uuid::new_v4());
+        merge_with(
+            &mut laptop,
+            &laptop_clock,
+            &[todo.clone(), note.clone()],
+            &registry,
+        )
+        .unwrap();
+        assert_eq!(*seen.lock().unwrap(), vec!["todos", "todos"]);
+
+        let batches: Vec<Vec<Uuid>> = futures::executor::block_on(async {
+            vec![
+                all.next().await.unwrap().iter().map(|op| op.id).collect(),
+                all.next().await.unwrap().iter().map(|op| op.id).collect(),
+            ]
+        });
+        assert_eq!(batches, vec![vec![todo.id], vec![todo.id, note.id]]);
+
+        assert!(registry.unsubscribe(id));
+        drop(all);
+        let later =
+            build_oplog_entry(&phone, &clock, "todos", "update", &json!({"id": 1})).unwrap();
+        local_apply_with(&mut phone, &later, &registry).unwrap();
+        assert_eq!(seen.lock().unwrap().len(), 2);
+        assert!(registry.observers.is_empty());
+    }
+}
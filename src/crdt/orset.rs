@@ -0,0 +1,181 @@
+//! Observed-remove set (OR-Set) operations.
+//!
+//! A set is identified by `(table, set id)`. Adding an element records an
+//! `add` operation; the operation's own ID is the unique tag of that add.
+//! Removing an element records a `remove` operation listing the add tags the
+//! device has observed:
+//!
+//! ```json
+//! {"id": "doc-1-tags", "element": "urgent"}
+//! {"id": "doc-1-tags", "element": "urgent", "tags": ["<add op id>", ...]}
+//! ```
+//!
+//! An element is a member while at least one of its add tags has not been
+//! removed, so an add concurrent with a remove wins.
+
+use super::{new_local_op, DeviceClock};
+use crate::error::{AhenkError, Result};
+use crate::models::OplogEntry;
+use rusqlite::{params, Connection};
+use std::collections::HashSet;
+
+/// Operation type for adding an element to a set
+pub const SET_ADD: &str = "add";
+/// Operation type for removing observed adds of an element from a set
+pub const SET_REMOVE: &str = "remove";
+
+/// Build an operation adding `element` to a set
+pub fn set_add_op(
+    conn: &Connection,
+    clock: &DeviceClock,
+    table: &str,
+    set_id: &str,
+    element: &serde_json::Value,
+) -> Result<OplogEntry> {
+    new_local_op(
+        conn,
+        clock,
+        table,
+        SET_ADD,
+        serde_json::json!({"id": set_id, "element": element}),
+    )
+}
+
+/// Build an operation removing `element` from a set.
+///
+/// Only the adds already in the local oplog are removed; adds made
+/// concurrently on other devices survive.
+pub fn set_remove_op(
+    conn: &Connection,
+    clock: &DeviceClock,
+    table: &str,
+    set_id: &str,
+    element: &serde_json::Value,
+) -> Result<OplogEntry> {
+    let tags: Vec<String> = load_ops(conn, table, set_id, SET_ADD)?
+        .into_iter()
+        .filter(|(_, data)| data.get("element") == Some(element))
+        .map(|(id, _)| id)
+        .collect();
+
+    new_local_op(
+        conn,
+        clock,
+        table,
+        SET_REMOVE,
+        serde_json::json!({"id": set_id, "element": element, "tags": tags}),
+    )
+}
+
+/// Converged members of a set, sorted by their JSON encoding
+pub fn set_members(conn: &Connection, table: &str, set_id: &str) -> Result<Vec<serde_json::Value>> {
+    let mut removed = HashSet::new();
+    for (_, data) in load_ops(conn, table, set_id, SET_REMOVE)? {
+        if let Some(tags) = data.get("tags").and_then(|t| t.as_array()) {
+            removed.extend(tags.iter().filter_map(|t| t.as_str().map(str::to_string)));
+        }
+    }
+
+    let mut members: Vec<serde_json::Value> = Vec::new();
+    for (id, mut data) in load_ops(conn, table, set_id, SET_ADD)? {
+        if removed.contains(&id) {
+            continue;
+        }
+        let element = data
+            .get_mut("element")
+            .map(serde_json::Value::take)
+            .unwrap_or_default();
+        if !members.contains(&element) {
+            members.push(element);
+        }
+    }
+
+    members.sort_by_cached_key(|m| m.to_string());
+    Ok(members)
+}
+
+fn load_ops(
+    conn: &Connection,
+    table: &str,
+    set_id: &str,
+    op_type: &str,
+) -> Result<Vec<(String, serde_json::Value)>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, data FROM oplog
+         WHERE table_name = ?1 AND op_type = ?2 AND json_extract(data, '$.id') = ?3",
+    )?;
+    let rows = stmt.query_map(params![table, op_type, set_id], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+
+    let mut ops = Vec::new();
+    for row in rows {
+        let (id, data) = row?;
+        let data =
+            serde_json::from_str(&data).map_err(|e| AhenkError::Serialization(e.to_string()))?;
+        ops.push((id, data));
+    }
+    Ok(ops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{local_apply, merge};
+    use crate::db::operations::{get_oplog_entries_since, initialize_database};
+    use serde_json::json;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_add_and_remove() {
+        let mut conn = initialize_database(":memory:").unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+
+        for element in [json!("urgent"), json!("home"), json!("urgent")] {
+            let op = set_add_op(&conn, &clock, "tags", "doc-1", &element).unwrap();
+            local_apply(&mut conn, &op).unwrap();
+        }
+        assert_eq!(
+            set_members(&conn, "tags", "doc-1").unwrap(),
+            vec![json!("home"), json!("urgent")]
+        );
+
+        let op = set_remove_op(&conn, &clock, "tags", "doc-1", &json!("urgent")).unwrap();
+        local_apply(&mut conn, &op).unwrap();
+        assert_eq!(
+            set_members(&conn, "tags", "doc-1").unwrap(),
+            vec![json!("home")]
+        );
+    }
+
+    #[test]
+    fn test_concurrent_add_wins_over_remove() {
+        let mut phone = initialize_database(":memory:").unwrap();
+        let mut laptop = initialize_database(":memory:").unwrap();
+        let phone_clock = DeviceClock::new(Uuid::new_v4());
+        let laptop_clock = DeviceClock::new(Uuid::new_v4());
+
+        let add = set_add_op(&phone, &phone_clock, "tags", "doc-1", &json!("urgent")).unwrap();
+        local_apply(&mut phone, &add).unwrap();
+        merge(&mut laptop, &laptop_clock, &[add]).unwrap();
+
+        // Offline: the phone removes the tag while the laptop re-adds it
+        let remove =
+            set_remove_op(&phone, &phone_clock, "tags", "doc-1", &json!("urgent")).unwrap();
+        local_apply(&mut phone, &remove).unwrap();
+        let readd = set_add_op(&laptop, &laptop_clock, "tags", "doc-1", &json!("urgent")).unwrap();
+        local_apply(&mut laptop, &readd).unwrap();
+
+        let from_phone = get_oplog_entries_since(&phone, 0).unwrap();
+        let from_laptop = get_oplog_entries_since(&laptop, 0).unwrap();
+        merge(&mut phone, &phone_clock, &from_laptop).unwrap();
+        merge(&mut laptop, &laptop_clock, &from_phone).unwrap();
+
+        for conn in [&phone, &laptop] {
+            assert_eq!(
+                set_members(conn, "tags", "doc-1").unwrap(),
+                vec![json!("urgent")]
+            );
+        }
+    }
+}
@@ -0,0 +1,384 @@
+//! Partial updates with JSON Patch and JSON Merge Patch.
+//!
+//! Instead of shipping the whole entity, an operation can carry a patch
+//! against the entity's current state:
+//!
+//! ```json
+//! {"id": "doc-1", "patch": [{"op": "replace", "path": "/body/title", "value": "Draft"}]}
+//! {"id": "doc-1", "patch": {"body": {"title": "Draft"}, "archived": null}}
+//! ```
+//!
+//! - `patch`: an [RFC 6902](https://www.rfc-editor.org/rfc/rfc6902) JSON Patch
+//!   document (`add`, `remove`, `replace`, `move`, `copy`, `test`)
+//! - `merge-patch`: an [RFC 7386](https://www.rfc-editor.org/rfc/rfc7386)
+//!   JSON Merge Patch
+//!
+//! [`build_oplog_entry`](crate::build_oplog_entry) rejects malformed patches.
+//! When materializing, the built-in resolvers replay an entity's writes and
+//! patches in HLC order, so every replica applies each patch to the same
+//! state whatever order the operations arrived in. A patch that does not
+//! apply (a failed `test`, a missing path) leaves the entity unchanged, and a
+//! patch to an entity that does not exist is ignored.
+
+use super::{new_local_op, DeviceClock};
+use crate::error::{AhenkError, Result};
+use crate::models::OplogEntry;
+use rusqlite::Connection;
+use serde_json::{json, Map, Value};
+
+/// Operation type for RFC 6902 JSON Patch updates
+pub const PATCH: &str = "patch";
+/// Operation type for RFC 7386 JSON Merge Patch updates
+pub const MERGE_PATCH: &str = "merge-patch";
+
+/// Whether an op type is a `patch` or `merge-patch`
+pub fn is_patch_op(op_type: &str) -> bool {
+    matches!(op_type, PATCH | MERGE_PATCH)
+}
+
+/// Build a `patch` operation for an entity keyed by `"id"`
+pub fn patch_op(
+    conn: &Connection,
+    clock: &DeviceClock,
+    table: &str,
+    entity_id: &str,
+    patch: &Value,
+) -> Result<OplogEntry> {
+    let data = json!({"id": entity_id, "patch": patch});
+    validate_patch(PATCH, &data)?;
+    new_local_op(conn, clock, table, PATCH, data)
+}
+
+/// Build a `merge-patch` operation for an entity keyed by `"id"`
+pub fn merge_patch_op(
+    conn: &Connection,
+    clock: &DeviceClock,
+    table: &str,
+    entity_id: &str,
+    patch: &Value,
+) -> Result<OplogEntry> {
+    let data = json!({"id": entity_id, "patch": patch});
+    validate_patch(MERGE_PATCH, &data)?;
+    new_local_op(conn, clock, table, MERGE_PATCH, data)
+}
+
+/// Check the payload of a `patch` or `merge-patch` operation
+pub fn validate_patch(op_type: &str, data: &Value) -> Result<()> {
+    let invalid = |msg: String| Err(AhenkError::Validation(msg));
+    let Some(patch) = data.get("patch") else {
+        return invalid(format!("'{}' operation has no 'patch' field", op_type));
+    };
+
+    match op_type {
+        MERGE_PATCH if !patch.is_object() => {
+            invalid("Merge patch must be a JSON object".to_string())
+        }
+        MERGE_PATCH => Ok(()),
+        PATCH => {
+            let Some(ops) = patch.as_array() else {
+                return invalid("JSON Patch must be an array of operations".to_string());
+            };
+            for (i, op) in ops.iter().enumerate() {
+                validate_patch_operation(op)
+                    .map_err(|e| AhenkError::Validation(format!("Patch operation {}: {}", i, e)))?;
+            }
+            Ok(())
+        }
+        other => invalid(format!("'{}' is not a patch operation type", other)),
+    }
+}
+
+/// Apply a `patch` or `merge-patch` payload to an entity's state.
+///
+/// Leaves `state` unchanged if the patch does not apply or would not leave a
+/// JSON object.
+pub(crate) fn apply_patch_op(state: &mut Map<String, Value>, op_type: &str, data: &Value) {
+    let Some(patch) = data.get("patch") else {
+        return;
+    };
+    let mut doc = Value::Object(state.clone());
+    let applied = match op_type {
+        PATCH => apply_patch(&mut doc, patch).is_ok(),
+        MERGE_PATCH => {
+            apply_merge_patch(&mut doc, patch);
+            true
+        }
+        _ => false,
+    };
+    if let (true, Value::Object(patched)) = (applied, doc) {
+        *state = patched;
+    }
+}
+
+/// Apply an RFC 6902 JSON Patch to `doc`, atomically
+pub fn apply_patch(doc: &mut Value, patch: &Value) -> Result<()> {
+    let ops = patch
+        .as_array()
+        .ok_or_else(|| AhenkError::Validation("JSON Patch must be an array".to_string()))?;
+
+    let mut patched = doc.clone();
+    for op in ops {
+        validate_patch_operation(op).map_err(AhenkError::Validation)?;
+        let path = op["path"].as_str().unwrap_or_default();
+        match op["op"].as_str().unwrap_or_default() {
+            "add" => add(&mut patched, path, op["value"].clone())?,
+            "remove" => {
+                remove(&mut patched, path)?;
+            }
+            "replace" => {
+                remove(&mut patched, path)?;
+                add(&mut patched, path, op["value"].clone())?;
+            }
+            "move" => {
+                let from = op["from"].as_str().unwrap_or_default();
+                if path.starts_with(from) && path[from.len()..].starts_with('/') {
+                    return Err(AhenkError::Validation(format!(
+                        "Cannot move '{}' into its own child '{}'",
+                        from, path
+                    )));
+                }
+                let value = remove(&mut patched, from)?;
+                add(&mut patched, path, value)?;
+            }
+            "copy" => {
+                let from = op["from"].as_str().unwrap_or_default();
+                let value = patched
+                    .pointer(from)
+                    .cloned()
+                    .ok_or_else(|| missing(from))?;
+                add(&mut patched, path, value)?;
+            }
+            "test" => {
+                if patched.pointer(path) != Some(&op["value"]) {
+                    return Err(AhenkError::Validation(format!("Test failed at '{}'", path)));
+                }
+            }
+            _ => unreachable!("validated above"),
+        }
+    }
+
+    *doc = patched;
+    Ok(())
+}
+
+/// Apply an RFC 7386 JSON Merge Patch to `doc`
+pub fn apply_merge_patch(doc: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *doc = patch.clone();
+        return;
+    };
+    if !doc.is_object() {
+        *doc = Value::Object(Map::new());
+    }
+    let target = doc.as_object_mut().expect("doc is an object");
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            apply_merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+fn validate_patch_operation(op: &Value) -> std::result::Result<(), String> {
+    let Some(name) = op.get("op").and_then(Value::as_str) else {
+        return Err("missing 'op'".to_string());
+    };
+    let pointer = |field: &str| match op.get(field).and_then(Value::as_str) {
+        Some(p) if p.is_empty() || p.starts_with('/') => Ok(()),
+        Some(p) => Err(format!("'{}' is not a JSON pointer: '{}'", field, p)),
+        None => Err(format!("missing '{}'", field)),
+    };
+
+    pointer("path")?;
+    match name {
+        "add" | "replace" | "test" if op.get("value").is_none() => {
+            Err(format!("'{}' needs a 'value'", name))
+        }
+        "add" | "replace" | "test" | "remove" => Ok(()),
+        "move" | "copy" => pointer("from"),
+        other => Err(format!("unknown operation '{}'", other)),
+    }
+}
+
+/// Split a JSON pointer into its parent pointer and unescaped last token
+fn split_pointer(path: &str) -> Option<(&str, String)> {
+    let (parent, last) = path.rsplit_once('/')?;
+    Some((parent, last.replace("~1", "/").replace("~0", "~")))
+}
+
+fn missing(path: &str) -> AhenkError {
+    AhenkError::Validation(format!("No value at '{}'", path))
+}
+
+fn add(doc: &mut Value, path: &str, value: Value) -> Result<()> {
+    let Some((parent, token)) = split_pointer(path) else {
+        *doc = value;
+        return Ok(());
+    };
+    match doc.pointer_mut(parent).ok_or_else(|| missing(parent))? {
+        Value::Object(object) => {
+            object.insert(token, value);
+        }
+        Value::Array(array) => {
+            let index = if token == "-" {
+                array.len()
+            } else {
+                token.parse().map_err(|_| missing(path))?
+            };
+            if index > array.len() {
+                return Err(missing(path));
+            }
+            array.insert(index, value);
+        }
+        _ => return Err(missing(path)),
+    }
+    Ok(())
+}
+
+fn remove(doc: &mut Value, path: &str) -> Result<Value> {
+    let Some((parent, token)) = split_pointer(path) else {
+        return Ok(std::mem::take(doc));
+    };
+    match doc.pointer_mut(parent).ok_or_else(|| missing(parent))? {
+        Value::Object(object) => object.remove(&token).ok_or_else(|| missing(path)),
+        Value::Array(array) => match token.parse::<usize>() {
+            Ok(index) if index < array.len() => Ok(array.remove(index)),
+            _ => Err(missing(path)),
+        },
+        _ => Err(missing(path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{local_apply_with, merge_with, FieldLww, LastWriteWins, MergeRegistry};
+    use crate::db::operations::{get_oplog_entries_since, initialize_database};
+    use crate::logic::build_oplog_entry;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_apply_json_patch() {
+        let mut doc = json!({"title": "a", "tags": ["x", "y"], "meta": {"n": 1}});
+        let patch = json!([
+            {"op": "test", "path": "/title", "value": "a"},
+            {"op": "replace", "path": "/title", "value": "b"},
+            {"op": "add", "path": "/tags/1", "value": "z"},
+            {"op": "remove", "path": "/tags/0"},
+            {"op": "copy", "from": "/meta/n", "path": "/count"},
+            {"op": "move", "from": "/meta", "path": "/info"},
+        ]);
+        apply_patch(&mut doc, &patch).unwrap();
+        assert_eq!(
+            doc,
+            json!({"title": "b", "tags": ["z", "y"], "count": 1, "info": {"n": 1}})
+        );
+
+        // A failing op leaves the document untouched
+        let before = doc.clone();
+        let patch = json!([
+            {"op": "replace", "path": "/title", "value": "c"},
+            {"op": "test", "path": "/count", "value": 2},
+        ]);
+        assert!(apply_patch(&mut doc, &patch).is_err());
+        assert_eq!(doc, before);
+    }
+
+    #[test]
+    fn test_apply_merge_patch() {
+        let mut doc = json!({"title": "a", "meta": {"n": 1, "m": 2}, "tags": ["x"]});
+        apply_merge_patch(
+            &mut doc,
+            &json!({"meta": {"m": null, "k": 3}, "tags": ["y"], "title": null}),
+        );
+        assert_eq!(doc, json!({"meta": {"n": 1, "k": 3}, "tags": ["y"]}));
+    }
+
+    #[test]
+    fn test_build_oplog_entry_validates_patches() {
+        let conn = initialize_database(":memory:").unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+        let build = |op_type: &str, patch: Value| {
+            build_oplog_entry(
+                &conn,
+                &clock,
+                "docs",
+                op_type,
+                &json!({"id": "d1", "patch": patch}),
+            )
+        };
+
+        assert!(build(PATCH, json!([{"op": "add", "path": "/a", "value": 1}])).is_ok());
+        assert!(build(MERGE_PATCH, json!({"a": 1})).is_ok());
+        assert!(build(PATCH, json!({"a": 1})).is_err());
+        assert!(build(PATCH, json!([{"op": "add", "path": "a", "value": 1}])).is_err());
+        assert!(build(PATCH, json!([{"op": "replace", "path": "/a"}])).is_err());
+        assert!(build(PATCH, json!([{"op": "copy", "path": "/a"}])).is_err());
+        assert!(build(MERGE_PATCH, json!([1])).is_err());
+    }
+
+    fn docs_db() -> Connection {
+        let conn = initialize_database(":memory:").unwrap();
+        conn.execute(
+            "CREATE TABLE docs (id TEXT PRIMARY KEY, title TEXT, body TEXT)",
+            [],
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_patches_converge_in_any_order() {
+        for registry in [
+            MergeRegistry::new().with_resolver("docs", LastWriteWins::new()),
+            MergeRegistry::new().with_resolver("docs", FieldLww::new()),
+        ] {
+            let mut origin = docs_db();
+            let clock = DeviceClock::new(Uuid::new_v4());
+            let ops = vec![
+                build_oplog_entry(
+                    &origin,
+                    &clock,
+                    "docs",
+                    "create",
+                    &json!({"id": "d1", "title": "a", "body": "{\"n\": 1}"}),
+                )
+                .unwrap(),
+                patch_op(
+                    &origin,
+                    &clock,
+                    "docs",
+                    "d1",
+                    &json!([{"op": "replace", "path": "/title", "value": "b"}]),
+                )
+                .unwrap(),
+                merge_patch_op(&origin, &clock, "docs", "d1", &json!({"body": null})).unwrap(),
+            ];
+            for op in &ops {
+                local_apply_with(&mut origin, op, &registry).unwrap();
+            }
+
+            let mut reversed = docs_db();
+            let other_clock = DeviceClock::new(Uuid::new_v4());
+            for op in get_oplog_entries_since(&origin, 0).unwrap().iter().rev() {
+                merge_with(
+                    &mut reversed,
+                    &other_clock,
+                    std::slice::from_ref(op),
+                    &registry,
+                )
+                .unwrap();
+            }
+
+            for conn in [&origin, &reversed] {
+                let row: (String, Option<String>) = conn
+                    .query_row("SELECT title, body FROM docs WHERE id = 'd1'", [], |row| {
+                        Ok((row.get(0)?, row.get(1)?))
+                    })
+                    .unwrap();
+                assert_eq!(row, ("b".to_string(), None));
+            }
+        }
+    }
+}
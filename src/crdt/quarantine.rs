@@ -0,0 +1,249 @@
+//! Quarantine for remote operations rejected by merge.
+//!
+//! When [`merge`](super::merge) refuses a remote operation (for example because
+//! its timestamp exceeds the device clock's maximum drift), the operation is
+//! stored in `oplog_quarantine` instead of the oplog. Operators can list
+//! quarantined operations and either release them into the oplog or discard
+//! them.
+
+use super::MergeRegistry;
+use crate::db::operations::{deps_column, parse_datetime_column, row_to_oplog_entry};
+use crate::error::{AhenkError, Result};
+use crate::models::OplogEntry;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use uuid::Uuid;
+
+/// A remote operation held back from the oplog
+#[derive(Debug, Clone)]
+pub struct QuarantinedOp {
+    /// The rejected operation
+    pub entry: OplogEntry,
+    /// Why the operation was rejected
+    pub reason: String,
+    /// When the operation was quarantined
+    pub quarantined_at: DateTime<Utc>,
+}
+
+const SELECT_QUARANTINED: &str = "SELECT id, device_id, timestamp, table_name, op_type, data, deps, schema_version, reason, quarantined_at FROM oplog_quarantine";
+
+fn row_to_quarantined(row: &rusqlite::Row) -> rusqlite::Result<QuarantinedOp> {
+    Ok(QuarantinedOp {
+        entry: row_to_oplog_entry(row)?,
+        reason: row.get(8)?,
+        quarantined_at: parse_datetime_column(row, 9)?,
+    })
+}
+
+/// Store a rejected operation in the quarantine table
+pub(crate) fn quarantine_op(
+    conn: &Connection,
+    op: &OplogEntry,
+    reason: &str,
+    quarantined_at: DateTime<Utc>,
+) -> Result<()> {
+    let data =
+        serde_json::to_string(&op.data).map_err(|e| AhenkError::Serialization(e.to_string()))?;
+    let deps = deps_column(&op.deps).map_err(|e| AhenkError::Serialization(e.to_string()))?;
+
+    conn.execute(
+        "INSERT OR IGNORE INTO oplog_quarantine (id, device_id, timestamp, table_name, op_type, data, deps, schema_version, reason, quarantined_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            op.id.to_string(),
+            op.device_id.to_string(),
+            op.timestamp,
+            op.table,
+            op.op_type,
+            data,
+            deps,
+            op.schema_version,
+            reason,
+            quarantined_at.to_rfc3339(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// List all quarantined operations, oldest first
+pub fn list_quarantined(conn: &Connection) -> Result<Vec<QuarantinedOp>> {
+    let mut stmt = conn.prepare(&format!(
+        "{} ORDER BY quarantined_at ASC",
+        SELECT_QUARANTINED
+    ))?;
+    let rows = stmt.query_map([], row_to_quarantined)?;
+
+    let mut ops = Vec::new();
+    for row in rows {
+        ops.push(row?);
+    }
+
+    Ok(ops)
+}
+
+/// Get a single quarantined operation by ID
+pub fn get_quarantined(conn: &Connection, op_id: Uuid) -> Result<Option<QuarantinedOp>> {
+    let op = conn
+        .query_row(
+            &format!("{} WHERE id = ?1", SELECT_QUARANTINED),
+            params![op_id.to_string()],
+            row_to_quarantined,
+        )
+        .optional()?;
+    Ok(op)
+}
+
+/// Release a quarantined operation into the oplog.
+///
+/// The operator accepts the operation as-is: it is merged like any other
+/// remote operation (see [`merge_with`](super::merge_with)), except that the
+/// drift check is skipped and the device clock is not advanced to its
+/// timestamp. If its dependencies have not arrived yet it moves to the causal
+/// delivery buffer. Returns the released operation.
+pub fn release_quarantined(
+    conn: &mut Connection,
+    op_id: Uuid,
+    registry: &MergeRegistry,
+) -> Result<OplogEntry> {
+    let tx = conn.transaction()?;
+
+    let op = get_quarantined(&tx, op_id)?
+        .ok_or_else(|| AhenkError::NotFound(format!("Quarantined operation {}", op_id)))?;
+    tx.execute(
+        "DELETE FROM oplog_quarantine WHERE id = ?1",
+        params![op_id.to_string()],
+    )?;
+
+    let mut recorded = Vec::new();
+    if !super::oplog_contains(&tx, &op.entry)? {
+        super::deliver(&tx, registry, &op.entry, &mut recorded)?;
+        super::deliver_ready(&tx, registry, &mut recorded)?;
+    }
+
+    tx.commit()?;
+    registry.notify(&recorded);
+    let entry = recorded
+        .iter()
+        .find(|entry| entry.id == op_id)
+        .cloned()
+        .unwrap_or(op.entry);
+    Ok(entry)
+}
+
+/// Permanently drop a quarantined operation
+pub fn discard_quarantined(conn: &Connection, op_id: Uuid) -> Result<()> {
+    let deleted = conn.execute(
+        "DELETE FROM oplog_quarantine WHERE id = ?1",
+        params![op_id.to_string()],
+    )?;
+
+    if deleted == 0 {
+        return Err(AhenkError::NotFound(format!(
+            "Quarantined operation {}",
+            op_id
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{list_pending, merge, DeviceClock, HybridLogicalClock, ManualClock};
+    use crate::db::operations::{get_oplog_entries_since, initialize_database};
+    use chrono::{Duration, TimeZone};
+    use std::sync::Arc;
+
+    fn remote_op(time: DateTime<Utc>) -> OplogEntry {
+        OplogEntry {
+            id: Uuid::new_v4(),
+            device_id: Uuid::new_v4(),
+            timestamp: HybridLogicalClock::new(time, 0).to_timestamp(),
+            table: "tasks".to_string(),
+            op_type: "update".to_string(),
+            data: serde_json::json!({"id": "t1", "title": "from the future"}),
+            deps: Vec::new(),
+            schema_version: 1,
+        }
+    }
+
+    #[test]
+    fn test_merge_quarantines_drifted_ops() {
+        let mut conn = initialize_database(":memory:").unwrap();
+        let start = Utc.with_ymd_and_hms(2024, 6, 1, 8, 0, 0).unwrap();
+        let clock = DeviceClock::with_clock(Uuid::new_v4(), Arc::new(ManualClock::new(start)))
+            .with_max_drift(Duration::minutes(5));
+
+        let ok = remote_op(start + Duration::minutes(1));
+        let drifted = remote_op(start + Duration::days(365 * 5));
+        merge(&mut conn, &clock, &[ok.clone(), drifted.clone()]).unwrap();
+
+        let oplog = get_oplog_entries_since(&conn, 0).unwrap();
+        assert_eq!(oplog.len(), 1);
+        assert_eq!(oplog[0].id, ok.id);
+        assert!(
+            clock.last(&conn).unwrap().unwrap()
+                < HybridLogicalClock::from_timestamp(drifted.timestamp)
+        );
+
+        let quarantined = list_quarantined(&conn).unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].entry.id, drifted.id);
+        assert_eq!(quarantined[0].entry.data, drifted.data);
+        assert_eq!(quarantined[0].quarantined_at, start);
+        assert!(quarantined[0].reason.contains("drift"));
+
+        // Redelivery does not duplicate the quarantined op
+        merge(&mut conn, &clock, &[drifted]).unwrap();
+        assert_eq!(list_quarantined(&conn).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_release_and_discard() {
+        let mut conn = initialize_database(":memory:").unwrap();
+        let now = Utc::now();
+        let released = remote_op(now + Duration::days(30));
+        let discarded = remote_op(now + Duration::days(60));
+        quarantine_op(&conn, &released, "test", now).unwrap();
+        quarantine_op(&conn, &discarded, "test", now).unwrap();
+
+        let entry = release_quarantined(&mut conn, released.id, &MergeRegistry::default()).unwrap();
+        assert_eq!(entry.id, released.id);
+        let oplog = get_oplog_entries_since(&conn, 0).unwrap();
+        assert_eq!(oplog.len(), 1);
+        assert_eq!(oplog[0].timestamp, released.timestamp);
+
+        discard_quarantined(&conn, discarded.id).unwrap();
+        assert!(list_quarantined(&conn).unwrap().is_empty());
+        assert_eq!(get_oplog_entries_since(&conn, 0).unwrap().len(), 1);
+
+        assert!(matches!(
+            release_quarantined(&mut conn, discarded.id, &MergeRegistry::default()),
+            Err(AhenkError::NotFound(_))
+        ));
+        assert!(matches!(
+            discard_quarantined(&conn, released.id),
+            Err(AhenkError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_release_goes_through_causal_delivery() {
+        let mut conn = initialize_database(":memory:").unwrap();
+        let now = Utc::now();
+        let create = remote_op(now);
+        let update = remote_op(now + Duration::days(30)).with_deps([create.id]);
+        quarantine_op(&conn, &update, "test", now).unwrap();
+
+        // The update's create has not arrived, so it waits in the buffer
+        release_quarantined(&mut conn, update.id, &MergeRegistry::default()).unwrap();
+        assert!(get_oplog_entries_since(&conn, 0).unwrap().is_empty());
+        assert_eq!(list_pending(&conn).unwrap().len(), 1);
+
+        let clock = DeviceClock::new(Uuid::new_v4());
+        merge(&mut conn, &clock, &[create]).unwrap();
+        assert_eq!(get_oplog_entries_since(&conn, 0).unwrap().len(), 2);
+        assert!(list_pending(&conn).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,672 @@
+//! Per-table merge resolvers.
+//!
+//! A [`MergeResolver`] decides how a remote operation changes an app table.
+//! Apps register one resolver per `table_name` in a [`MergeRegistry`] and pass
+//! it to [`merge_with`](super::merge_with); resolvers run inside the same
+//! transaction that records the operation in the oplog, so the oplog and the
+//! app tables never disagree after a merge.
+//!
+//! The built-in resolvers treat `OplogEntry.data` as the full JSON row of the
+//! entity, keyed by a primary-key field (`"id"` by default). They handle
+//! `create`, `update` and `delete` operations and ignore other op types:
+//! - [`LastWriteWins`]: the operation with the highest HLC wins
+//! - [`DeleteWins`]: once any replica deletes an entity it stays deleted
+//! - [`KeepFirst`]: the operation with the lowest HLC wins
+//!
+//! Operations with equal HLCs are ordered by [`OrderKey`](crate::OrderKey),
+//! so every replica picks the same winner.
+//!
+//! [`LastWriteWins`] and [`DeleteWins`] also apply [`patch`](super::patch)
+//! operations: entities with patches are rebuilt by replaying their writes and
+//! patches in HLC order. [`KeepFirst`] records are write-once and ignore them.
+
+use super::capture::without_capture;
+use super::history::{has_patches, key_string, replay_entity};
+use super::observer::Observers;
+use super::patch::is_patch_op;
+use super::upcast::Upcaster;
+use crate::error::{AhenkError, Result};
+use crate::models::OplogEntry;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+/// Decides how a merged operation is applied to an app table
+pub trait MergeResolver: Send + Sync {
+    /// Apply `op` to the app table.
+    ///
+    /// Called inside the merge transaction, once per operation that is new to
+    /// this replica and after it has been recorded in the oplog, so the
+    /// oplog already contains `op` and every earlier operation.
+    fn resolve(&self, conn: &Connection, op: &OplogEntry) -> Result<()>;
+
+    /// Primary-key field of the entities in `OplogEntry.data`
+    fn key_field(&self) -> &str {
+        "id"
+    }
+
+    /// How the resolver folds an entity's writes into its row, so history
+    /// (see [`table_at`](super::table_at)) can be replayed the same way
+    fn replay(&self) -> Replay {
+        Replay::MergeFields
+    }
+}
+
+/// How an entity's `create`, `update`, `delete` and patch operations, taken
+/// in HLC order, fold into its row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replay {
+    /// A write sets only the fields it carries; a delete removes the entity
+    /// until it is written again
+    MergeFields,
+    /// Creates and updates replace the whole row; a delete removes the entity
+    /// until it is written again
+    ReplaceRow,
+    /// Like [`ReplaceRow`](Self::ReplaceRow), but once deleted the entity
+    /// stays deleted
+    DeleteWins,
+    /// Only the entity's first create, update or delete counts
+    KeepFirst,
+}
+
+impl<F> MergeResolver for F
+where
+    F: Fn(&Connection, &OplogEntry) -> Result<()> + Send + Sync,
+{
+    fn resolve(&self, conn: &Connection, op: &OplogEntry) -> Result<()> {
+        self(conn, op)
+    }
+}
+
+/// Resolvers keyed by table name
+#[derive(Clone, Default)]
+pub struct MergeRegistry {
+    resolvers: HashMap<String, Arc<dyn MergeResolver>>,
+    pub(super) upcasters: HashMap<String, BTreeMap<u32, Arc<dyn Upcaster>>>,
+    pub(super) observers: Arc<Observers>,
+}
+
+impl MergeRegistry {
+    /// Create an empty registry; operations on every table are only recorded
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the resolver for a table, replacing any previous one
+    pub fn register(&mut self, table: impl Into<String>, resolver: impl MergeResolver + 'static) {
+        self.resolvers.insert(table.into(), Arc::new(resolver));
+    }
+
+    /// Builder form of [`register`](Self::register)
+    pub fn with_resolver(
+        mut self,
+        table: impl Into<String>,
+        resolver: impl MergeResolver + 'static,
+    ) -> Self {
+        self.register(table, resolver);
+        self
+    }
+
+    /// Tables with a registered resolver
+    pub fn tables(&self) -> impl Iterator<Item = &str> {
+        self.resolvers.keys().map(String::as_str)
+    }
+
+    /// Resolver registered for a table, if any
+    pub fn resolver(&self, table: &str) -> Option<&dyn MergeResolver> {
+        self.resolvers.get(table).map(|r| r.as_ref())
+    }
+
+    /// Primary-key field of a table's entities (`"id"` without a resolver)
+    pub fn key_field(&self, table: &str) -> &str {
+        self.resolver(table).map_or("id", |r| r.key_field())
+    }
+
+    /// How writes to `table` fold into its rows ([`Replay::MergeFields`]
+    /// without a resolver)
+    pub fn replay(&self, table: &str) -> Replay {
+        self.resolver(table)
+            .map_or(Replay::MergeFields, |r| r.replay())
+    }
+
+    /// Apply `op` with the resolver registered for its table, if any.
+    ///
+    /// Rows the resolver writes are not [captured](super::capture) again.
+    pub fn resolve(&self, conn: &Connection, op: &OplogEntry) -> Result<()> {
+        match self.resolver(&op.table) {
+            Some(resolver) => without_capture(conn, || resolver.resolve(conn, op)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for MergeRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut tables: Vec<_> = self.resolvers.keys().collect();
+        tables.sort();
+        let schemas: BTreeMap<_, _> = self
+            .upcasters
+            .keys()
+            .map(|table| (table, self.schema_version(table)))
+            .collect();
+        f.debug_struct("MergeRegistry")
+            .field("tables", &tables)
+            .field("schema_versions", &schemas)
+            .finish()
+    }
+}
+
+// ============================================================================
+// Built-in Resolvers
+// ============================================================================
+
+/// The operation with the highest HLC timestamp wins.
+///
+/// Creates and updates replace the whole row; deletes remove it. Operations
+/// older than the entity's latest known operation, including a compacted
+/// delete, are ignored. Patches apply to the row as written by the latest
+/// earlier operation.
+#[derive(Debug, Clone)]
+pub struct LastWriteWins {
+    key: String,
+}
+
+impl LastWriteWins {
+    /// Resolve rows keyed by the `"id"` field
+    pub fn new() -> Self {
+        Self::with_key("id")
+    }
+
+    /// Resolve rows keyed by a custom primary-key field
+    pub fn with_key(key: impl Into<String>) -> Self {
+        Self { key: key.into() }
+    }
+}
+
+impl Default for LastWriteWins {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MergeResolver for LastWriteWins {
+    fn key_field(&self) -> &str {
+        &self.key
+    }
+
+    fn replay(&self) -> Replay {
+        Replay::ReplaceRow
+    }
+
+    fn resolve(&self, conn: &Connection, op: &OplogEntry) -> Result<()> {
+        if !is_row_op(&op.op_type) && !is_patch_op(&op.op_type) {
+            return Ok(());
+        }
+        let key = entity_key(op, &self.key)?;
+        if is_patch_op(&op.op_type) || entity_has_patches(conn, op, &self.key)? {
+            return rebuild_row(conn, op, &self.key, &key);
+        }
+        if winning_op_id(conn, &op.table, &key, "DESC", false)? == Some(op.id.to_string()) {
+            apply_row(conn, op, &self.key, &key)?;
+        }
+        Ok(())
+    }
+}
+
+/// Deletes beat concurrent and later writes.
+///
+/// Once any replica deletes an entity, creates, updates and patches for it
+/// are ignored. Until then, writes are resolved last-write-wins.
+#[derive(Debug, Clone)]
+pub struct DeleteWins {
+    key: String,
+}
+
+impl DeleteWins {
+    /// Resolve rows keyed by the `"id"` field
+    pub fn new() -> Self {
+        Self::with_key("id")
+    }
+
+    /// Resolve rows keyed by a custom primary-key field
+    pub fn with_key(key: impl Into<String>) -> Self {
+        Self { key: key.into() }
+    }
+}
+
+impl Default for DeleteWins {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MergeResolver for DeleteWins {
+    fn key_field(&self) -> &str {
+        &self.key
+    }
+
+    fn replay(&self) -> Replay {
+        Replay::DeleteWins
+    }
+
+    fn resolve(&self, conn: &Connection, op: &OplogEntry) -> Result<()> {
+        if !is_row_op(&op.op_type) && !is_patch_op(&op.op_type) {
+            return Ok(());
+        }
+        let key = entity_key(op, &self.key)?;
+        if op.op_type == "delete" {
+            return delete_row(conn, &op.table, &self.key, &key);
+        }
+
+        // Compacted deletes leave a tombstone instead of an oplog entry
+        let deleted: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM oplog WHERE table_name = ?1 AND op_type = 'delete'
+             AND entity_id = CAST(?2 AS TEXT))
+             OR EXISTS(SELECT 1 FROM oplog_tombstones WHERE table_name = ?1
+             AND entity_id = CAST(?2 AS TEXT))",
+            params![op.table, key],
+            |row| row.get(0),
+        )?;
+        if deleted {
+            return Ok(());
+        }
+        if is_patch_op(&op.op_type) || entity_has_patches(conn, op, &self.key)? {
+            return rebuild_row(conn, op, &self.key, &key);
+        }
+        if winning_op_id(conn, &op.table, &key, "DESC", true)? == Some(op.id.to_string()) {
+            upsert_row(conn, &op.table, &op.data)?;
+        }
+        Ok(())
+    }
+}
+
+/// The operation with the lowest HLC timestamp wins.
+///
+/// Intended for write-once records: later creates, updates and deletes for an
+/// entity are ignored unless an earlier operation arrives late.
+#[derive(Debug, Clone)]
+pub struct KeepFirst {
+    key: String,
+}
+
+impl KeepFirst {
+    /// Resolve rows keyed by the `"id"` field
+    pub fn new() -> Self {
+        Self::with_key("id")
+    }
+
+    /// Resolve rows keyed by a custom primary-key field
+    pub fn with_key(key: impl Into<String>) -> Self {
+        Self { key: key.into() }
+    }
+}
+
+impl Default for KeepFirst {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MergeResolver for KeepFirst {
+    fn key_field(&self) -> &str {
+        &self.key
+    }
+
+    fn replay(&self) -> Replay {
+        Replay::KeepFirst
+    }
+
+    fn resolve(&self, conn: &Connection, op: &OplogEntry) -> Result<()> {
+        if !is_row_op(&op.op_type) {
+            return Ok(());
+        }
+        let key = entity_key(op, &self.key)?;
+        if winning_op_id(conn, &op.table, &key, "ASC", false)? == Some(op.id.to_string()) {
+            apply_row(conn, op, &self.key, &key)?;
+        }
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Row Helpers
+// ============================================================================
+
+/// Extract the primary-key value of the entity an operation targets
+pub fn entity_key(op: &OplogEntry, key_field: &str) -> Result<Value> {
+    match op.data.get(key_field) {
+        Some(value) if !value.is_null() => Ok(json_to_sql(value)),
+        _ => Err(AhenkError::Validation(format!(
+            "Operation {} on '{}' has no '{}' field",
+            op.id, op.table, key_field
+        ))),
+    }
+}
+
+/// Insert or replace a row from a JSON object whose keys are column names
+pub fn upsert_row(conn: &Connection, table: &str, data: &serde_json::Value) -> Result<()> {
+    let object = data.as_object().ok_or_else(|| {
+        AhenkError::Validation(format!("Row data for '{}' must be a JSON object", table))
+    })?;
+
+    upsert_values(
+        conn,
+        table,
+        object
+            .iter()
+            .map(|(column, value)| (column.as_str(), json_to_sql(value))),
+    )
+}
+
+/// Insert or replace a row from its column names and SQL values
+pub(crate) fn upsert_values<'a>(
+    conn: &Connection,
+    table: &str,
+    row: impl IntoIterator<Item = (&'a str, Value)>,
+) -> Result<()> {
+    let (columns, values): (Vec<String>, Vec<Value>) = row
+        .into_iter()
+        .map(|(column, value)| (quote_identifier(column), value))
+        .unzip();
+    let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("?{}", i)).collect();
+    let sql = format!(
+        "INSERT OR REPLACE INTO {} ({}) VALUES ({})",
+        quote_identifier(table),
+        columns.join(", "),
+        placeholders.join(", ")
+    );
+
+    conn.execute(&sql, params_from_iter(values))?;
+    Ok(())
+}
+
+/// Delete the row whose `key_field` column equals `key`
+pub fn delete_row(conn: &Connection, table: &str, key_field: &str, key: &Value) -> Result<()> {
+    let sql = format!(
+        "DELETE FROM {} WHERE {} = ?1",
+        quote_identifier(table),
+        quote_identifier(key_field)
+    );
+    conn.execute(&sql, params![key])?;
+    Ok(())
+}
+
+/// Whether an op type is a whole-row `create`, `update` or `delete`
+pub fn is_row_op(op_type: &str) -> bool {
+    matches!(op_type, "create" | "update" | "delete")
+}
+
+fn apply_row(conn: &Connection, op: &OplogEntry, key_field: &str, key: &Value) -> Result<()> {
+    if op.op_type == "delete" {
+        delete_row(conn, &op.table, key_field, key)
+    } else {
+        upsert_row(conn, &op.table, &op.data)
+    }
+}
+
+fn entity_has_patches(conn: &Connection, op: &OplogEntry, key_field: &str) -> Result<bool> {
+    match op.data.get(key_field).and_then(key_string) {
+        Some(entity_id) => has_patches(conn, &op.table, &entity_id),
+        None => Ok(false),
+    }
+}
+
+/// Write an entity's row as replayed from its whole-row writes and patches
+fn rebuild_row(conn: &Connection, op: &OplogEntry, key_field: &str, key: &Value) -> Result<()> {
+    let entity_id = op
+        .data
+        .get(key_field)
+        .and_then(key_string)
+        .unwrap_or_default();
+    match replay_entity(conn, &op.table, &entity_id, Replay::ReplaceRow)? {
+        Some(mut fields) => {
+            // A patch cannot move the row to another key
+            fields.insert(key_field.to_string(), op.data[key_field].clone());
+            upsert_row(conn, &op.table, &serde_json::Value::Object(fields))
+        }
+        None => delete_row(conn, &op.table, key_field, key),
+    }
+}
+
+/// ID of the first oplog entry for an entity in the given timestamp order.
+///
+/// Unless only writes are wanted, the entity's compacted delete (see
+/// [`compaction`](super::compaction)) takes part as if it were still in the
+/// oplog.
+fn winning_op_id(
+    conn: &Connection,
+    table: &str,
+    key: &Value,
+    order: &str,
+    writes_only: bool,
+) -> Result<Option<String>> {
+    let candidates = if writes_only {
+        "SELECT id, timestamp, device_id FROM oplog
+         WHERE table_name = ?1 AND entity_id = CAST(?2 AS TEXT)
+           AND op_type IN ('create', 'update')"
+    } else {
+        "SELECT id, timestamp, device_id FROM oplog
+         WHERE table_name = ?1 AND entity_id = CAST(?2 AS TEXT)
+           AND op_type IN ('create', 'update', 'delete')
+         UNION ALL
+         SELECT op_id, timestamp, device_id FROM oplog_tombstones
+         WHERE table_name = ?1 AND entity_id = CAST(?2 AS TEXT)"
+    };
+    let sql = format!(
+        "SELECT id FROM ({candidates})
+         ORDER BY timestamp {order}, device_id {order}, id {order} LIMIT 1"
+    );
+    let id = conn
+        .query_row(&sql, params![table, key], |row| row.get(0))
+        .optional()?;
+    Ok(id)
+}
+
+pub(crate) fn json_to_sql(value: &serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Integer(*b as i64),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Real(n.as_f64().unwrap_or_default()),
+        },
+        serde_json::Value::String(s) => Value::Text(s.clone()),
+        other => Value::Text(other.to_string()),
+    }
+}
+
+pub(crate) fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{merge_with, DeviceClock, HybridLogicalClock};
+    use crate::db::operations::initialize_database;
+    use chrono::{Duration, TimeZone, Utc};
+    use uuid::Uuid;
+
+    fn setup() -> (Connection, DeviceClock) {
+        let conn = initialize_database(":memory:").unwrap();
+        conn.execute(
+            "CREATE TABLE tasks (id TEXT PRIMARY KEY, title TEXT, done INTEGER)",
+            [],
+        )
+        .unwrap();
+        (conn, DeviceClock::new(Uuid::new_v4()))
+    }
+
+    fn op(minute: i64, op_type: &str, data: serde_json::Value) -> OplogEntry {
+        let time = Utc.with_ymd_and_hms(2024, 6, 1, 8, 0, 0).unwrap() + Duration::minutes(minute);
+        OplogEntry {
+            id: Uuid::new_v4(),
+            device_id: Uuid::new_v4(),
+            timestamp: HybridLogicalClock::new(time, 0).to_timestamp(),
+            table: "tasks".to_string(),
+            op_type: op_type.to_string(),
+            data,
+            deps: Vec::new(),
+            schema_version: 1,
+        }
+    }
+
+    fn title(conn: &Connection) -> Option<String> {
+        conn.query_row("SELECT title FROM tasks WHERE id = 't1'", [], |row| {
+            row.get(0)
+        })
+        .optional()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_last_write_wins_in_any_delivery_order() {
+        let create = op(
+            0,
+            "create",
+            serde_json::json!({"id": "t1", "title": "draft", "done": false}),
+        );
+        let older = op(
+            1,
+            "update",
+            serde_json::json!({"id": "t1", "title": "older", "done": false}),
+        );
+        let newer = op(
+            2,
+            "update",
+            serde_json::json!({"id": "t1", "title": "newer", "done": true}),
+        );
+        let registry = MergeRegistry::new().with_resolver("tasks", LastWriteWins::new());
+
+        for batches in [
+            vec![vec![create.clone(), older.clone(), newer.clone()]],
+            vec![vec![newer.clone()], vec![create.clone(), older.clone()]],
+        ] {
+            let (mut conn, clock) = setup();
+            for batch in batches {
+                merge_with(&mut conn, &clock, &batch, &registry).unwrap();
+            }
+            assert_eq!(title(&conn).as_deref(), Some("newer"));
+        }
+    }
+
+    #[test]
+    fn test_equal_hlc_tie_broken_by_device() {
+        let mut first = op(
+            0,
+            "update",
+            serde_json::json!({"id": "t1", "title": "first"}),
+        );
+        let mut second = first.clone();
+        second.id = Uuid::new_v4();
+        second.device_id = Uuid::new_v4();
+        second.data = serde_json::json!({"id": "t1", "title": "second"});
+        if first.device_id > second.device_id {
+            std::mem::swap(&mut first.device_id, &mut second.device_id);
+        }
+        // The higher device ID wins even with the lower op ID
+        if first.id < second.id {
+            std::mem::swap(&mut first.id, &mut second.id);
+        }
+        let registry = MergeRegistry::new().with_resolver("tasks", LastWriteWins::new());
+
+        for batch in [
+            [first.clone(), second.clone()],
+            [second.clone(), first.clone()],
+        ] {
+            let (mut conn, clock) = setup();
+            for op in batch {
+                merge_with(&mut conn, &clock, &[op], &registry).unwrap();
+            }
+            assert_eq!(title(&conn).as_deref(), Some("second"));
+        }
+    }
+
+    #[test]
+    fn test_delete_wins_over_later_update() {
+        let (mut conn, clock) = setup();
+        let registry = MergeRegistry::new().with_resolver("tasks", DeleteWins::new());
+
+        let create = op(
+            0,
+            "create",
+            serde_json::json!({"id": "t1", "title": "draft"}),
+        );
+        let delete = op(1, "delete", serde_json::json!({"id": "t1"}));
+        let update = op(
+            2,
+            "update",
+            serde_json::json!({"id": "t1", "title": "renamed"}),
+        );
+
+        merge_with(&mut conn, &clock, &[create, delete], &registry).unwrap();
+        merge_with(&mut conn, &clock, &[update], &registry).unwrap();
+        assert_eq!(title(&conn), None);
+    }
+
+    #[test]
+    fn test_keep_first_ignores_later_writes() {
+        let (mut conn, clock) = setup();
+        let registry = MergeRegistry::new().with_resolver("tasks", KeepFirst::new());
+
+        let first = op(
+            0,
+            "create",
+            serde_json::json!({"id": "t1", "title": "first"}),
+        );
+        let second = op(
+            5,
+            "create",
+            serde_json::json!({"id": "t1", "title": "second"}),
+        );
+
+        merge_with(&mut conn, &clock, &[second], &registry).unwrap();
+        assert_eq!(title(&conn).as_deref(), Some("second"));
+        merge_with(&mut conn, &clock, &[first], &registry).unwrap();
+        assert_eq!(title(&conn).as_deref(), Some("first"));
+    }
+
+    #[test]
+    fn test_resolver_error_rolls_back_merge() {
+        let (mut conn, clock) = setup();
+        let registry = MergeRegistry::new().with_resolver("tasks", LastWriteWins::new());
+
+        let valid = op(0, "create", serde_json::json!({"id": "t1", "title": "ok"}));
+        let missing_key = op(1, "create", serde_json::json!({"title": "no id"}));
+
+        let err = merge_with(&mut conn, &clock, &[valid, missing_key], &registry).unwrap_err();
+        assert!(matches!(err, AhenkError::Validation(_)));
+
+        let oplog_rows: i64 = conn
+            .query_row("SELECT COUNT(*) FROM oplog", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(oplog_rows, 0);
+        assert_eq!(title(&conn), None);
+    }
+
+    #[test]
+    fn test_closure_resolver() {
+        let (mut conn, clock) = setup();
+        let registry = MergeRegistry::new().with_resolver(
+            "tasks",
+            |conn: &Connection, op: &OplogEntry| -> Result<()> {
+                upsert_row(
+                    conn,
+                    &op.table,
+                    &serde_json::json!({"id": "t1", "title": op.op_type}),
+                )
+            },
+        );
+
+        merge_with(
+            &mut conn,
+            &clock,
+            &[op(0, "x-archive", serde_json::json!({}))],
+            &registry,
+        )
+        .unwrap();
+        assert_eq!(title(&conn).as_deref(), Some("x-archive"));
+    }
+}
@@ -0,0 +1,336 @@
+//! RGA sequence CRDT for ordered lists and collaborative text.
+//!
+//! A sequence is identified by `(table, sequence id)`. Each element is created
+//! by one `seq_insert` operation whose own ID becomes the element ID; the
+//! operation names the element it was inserted after (`null` for the start):
+//!
+//! ```json
+//! {"id": "note-1", "after": "<element id>", "value": "a"}
+//! {"id": "note-1", "target": "<element id>"}
+//! ```
+//!
+//! `seq_delete` operations hide an element but keep it as an anchor, so
+//! later inserts after it still have a position.
+//!
+//! Elements inserted after the same anchor are ordered newest first by
+//! [`OrderKey`](crate::OrderKey) (timestamp, device, element id). Every replica therefore materializes the same
+//! order, and concurrent inserts at one position from two devices both
+//! appear, one run after the other.
+
+use super::{new_local_op, DeviceClock};
+use crate::error::{AhenkError, Result};
+use crate::models::OplogEntry;
+use rusqlite::{params, Connection};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+/// Operation type for inserting an element into a sequence
+pub const SEQ_INSERT: &str = "seq_insert";
+/// Operation type for deleting an element from a sequence
+pub const SEQ_DELETE: &str = "seq_delete";
+
+/// A visible element of a materialized sequence
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequenceElement {
+    /// Element ID (the ID of the operation that inserted it)
+    pub id: Uuid,
+    /// Element value
+    pub value: serde_json::Value,
+}
+
+/// Build an operation inserting `value` after the element `after`
+/// (`None` inserts at the start)
+pub fn seq_insert_op(
+    conn: &Connection,
+    clock: &DeviceClock,
+    table: &str,
+    seq_id: &str,
+    after: Option<Uuid>,
+    value: &serde_json::Value,
+) -> Result<OplogEntry> {
+    new_local_op(
+        conn,
+        clock,
+        table,
+        SEQ_INSERT,
+        serde_json::json!({
+            "id": seq_id,
+            "after": after.map(|id| id.to_string()),
+            "value": value,
+        }),
+    )
+}
+
+/// Build an operation inserting `value` at a visible index
+pub fn seq_insert_at(
+    conn: &Connection,
+    clock: &DeviceClock,
+    table: &str,
+    seq_id: &str,
+    index: usize,
+    value: &serde_json::Value,
+) -> Result<OplogEntry> {
+    let after = anchor_before(conn, table, seq_id, index)?;
+    seq_insert_op(conn, clock, table, seq_id, after, value)
+}
+
+/// Build operations inserting each character of `text` at a visible index.
+///
+/// Record them in order.
+pub fn seq_insert_text(
+    conn: &Connection,
+    clock: &DeviceClock,
+    table: &str,
+    seq_id: &str,
+    index: usize,
+    text: &str,
+) -> Result<Vec<OplogEntry>> {
+    let mut after = anchor_before(conn, table, seq_id, index)?;
+    let mut ops = Vec::new();
+    for ch in text.chars() {
+        // Each character depends on the one before it, which is not recorded
+        // yet, so peers record the batch in order
+        let op = seq_insert_op(conn, clock, table, seq_id, after, &ch.to_string().into())?
+            .with_deps(ops.last().map(|prev: &OplogEntry| prev.id));
+        after = Some(op.id);
+        ops.push(op);
+    }
+    Ok(ops)
+}
+
+/// Build an operation deleting the element `element`
+pub fn seq_delete_op(
+    conn: &Connection,
+    clock: &DeviceClock,
+    table: &str,
+    seq_id: &str,
+    element: Uuid,
+) -> Result<OplogEntry> {
+    new_local_op(
+        conn,
+        clock,
+        table,
+        SEQ_DELETE,
+        serde_json::json!({"id": seq_id, "target": element.to_string()}),
+    )
+}
+
+/// Build an operation deleting the element at a visible index
+pub fn seq_delete_at(
+    conn: &Connection,
+    clock: &DeviceClock,
+    table: &str,
+    seq_id: &str,
+    index: usize,
+) -> Result<OplogEntry> {
+    let elements = sequence_elements(conn, table, seq_id)?;
+    let element = elements.get(index).ok_or_else(|| {
+        AhenkError::Validation(format!(
+            "Index {} out of range for sequence '{}' ({} elements)",
+            index,
+            seq_id,
+            elements.len()
+        ))
+    })?;
+    seq_delete_op(conn, clock, table, seq_id, element.id)
+}
+
+/// Visible elements of a sequence in order
+pub fn sequence_elements(
+    conn: &Connection,
+    table: &str,
+    seq_id: &str,
+) -> Result<Vec<SequenceElement>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, timestamp, device_id, op_type, data FROM oplog
+         WHERE table_name = ?1 AND op_type IN (?2, ?3) AND json_extract(data, '$.id') = ?4",
+    )?;
+    let rows = stmt.query_map(params![table, SEQ_INSERT, SEQ_DELETE, seq_id], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, i64>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, String>(3)?,
+            row.get::<_, String>(4)?,
+        ))
+    })?;
+
+    let mut children: HashMap<Option<String>, Vec<(i64, String, String)>> = HashMap::new();
+    let mut values: HashMap<String, serde_json::Value> = HashMap::new();
+    let mut deleted: HashSet<String> = HashSet::new();
+
+    for row in rows {
+        let (id, timestamp, device_id, op_type, data) = row?;
+        let mut data: serde_json::Value =
+            serde_json::from_str(&data).map_err(|e| AhenkError::Serialization(e.to_string()))?;
+
+        if op_type == SEQ_DELETE {
+            if let Some(target) = data.get("target").and_then(|t| t.as_str()) {
+                deleted.insert(target.to_string());
+            }
+            continue;
+        }
+
+        let after = data
+            .get("after")
+            .and_then(|a| a.as_str())
+            .map(str::to_string);
+        children
+            .entry(after)
+            .or_default()
+            .push((timestamp, device_id, id.clone()));
+        values.insert(id, data["value"].take());
+    }
+
+    for siblings in children.values_mut() {
+        siblings.sort_unstable_by(|a, b| b.cmp(a));
+    }
+
+    // Depth-first walk from the start; elements whose anchor has not arrived
+    // yet are unreachable and stay hidden until it does
+    let mut elements = Vec::new();
+    let mut stack: Vec<&String> = children
+        .get(&None)
+        .map(|s| s.iter().rev().map(|(_, _, id)| id).collect())
+        .unwrap_or_default();
+    while let Some(id) = stack.pop() {
+        if let Some(siblings) = children.get(&Some(id.clone())) {
+            stack.extend(siblings.iter().rev().map(|(_, _, id)| id));
+        }
+        if !deleted.contains(id) {
+            elements.push(SequenceElement {
+                id: Uuid::parse_str(id).map_err(|e| AhenkError::Serialization(e.to_string()))?,
+                value: values.remove(id).unwrap_or_default(),
+            });
+        }
+    }
+
+    Ok(elements)
+}
+
+/// Visible values of a sequence in order
+pub fn sequence_values(
+    conn: &Connection,
+    table: &str,
+    seq_id: &str,
+) -> Result<Vec<serde_json::Value>> {
+    Ok(sequence_elements(conn, table, seq_id)?
+        .into_iter()
+        .map(|e| e.value)
+        .collect())
+}
+
+/// Sequence materialized as text, concatenating its string values
+pub fn sequence_text(conn: &Connection, table: &str, seq_id: &str) -> Result<String> {
+    Ok(sequence_elements(conn, table, seq_id)?
+        .iter()
+        .filter_map(|e| e.value.as_str())
+        .collect())
+}
+
+/// Element after which an insert at `index` should be anchored
+fn anchor_before(
+    conn: &Connection,
+    table: &str,
+    seq_id: &str,
+    index: usize,
+) -> Result<Option<Uuid>> {
+    if index == 0 {
+        return Ok(None);
+    }
+    let elements = sequence_elements(conn, table, seq_id)?;
+    elements.get(index - 1).map(|e| Some(e.id)).ok_or_else(|| {
+        AhenkError::Validation(format!(
+            "Index {} out of range for sequence '{}' ({} elements)",
+            index,
+            seq_id,
+            elements.len()
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{local_apply, merge};
+    use crate::db::operations::{get_oplog_entries_since, initialize_database};
+    use serde_json::json;
+
+    fn apply(conn: &mut Connection, ops: Vec<OplogEntry>) {
+        for op in ops {
+            local_apply(conn, &op).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_insert_and_delete_text() {
+        let mut conn = initialize_database(":memory:").unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+
+        let ops = seq_insert_text(&conn, &clock, "notes", "n1", 0, "helo").unwrap();
+        apply(&mut conn, ops);
+        let op = seq_insert_at(&conn, &clock, "notes", "n1", 3, &json!("l")).unwrap();
+        apply(&mut conn, vec![op]);
+        assert_eq!(sequence_text(&conn, "notes", "n1").unwrap(), "hello");
+
+        let op = seq_delete_at(&conn, &clock, "notes", "n1", 0).unwrap();
+        apply(&mut conn, vec![op]);
+        let ops = seq_insert_text(&conn, &clock, "notes", "n1", 0, "j").unwrap();
+        apply(&mut conn, ops);
+        assert_eq!(sequence_text(&conn, "notes", "n1").unwrap(), "jello");
+
+        assert!(seq_delete_at(&conn, &clock, "notes", "n1", 5).is_err());
+        assert!(seq_insert_at(&conn, &clock, "notes", "n1", 6, &json!("!")).is_err());
+    }
+
+    #[test]
+    fn test_concurrent_inserts_converge() {
+        let mut phone = initialize_database(":memory:").unwrap();
+        let mut laptop = initialize_database(":memory:").unwrap();
+        let phone_clock = DeviceClock::new(Uuid::new_v4());
+        let laptop_clock = DeviceClock::new(Uuid::new_v4());
+
+        let base = seq_insert_text(&phone, &phone_clock, "lists", "l1", 0, "ad").unwrap();
+        apply(&mut phone, base.clone());
+        merge(&mut laptop, &laptop_clock, &base).unwrap();
+
+        // Both devices insert between "a" and "d" while offline
+        let ops = seq_insert_text(&phone, &phone_clock, "lists", "l1", 1, "b").unwrap();
+        apply(&mut phone, ops);
+        let ops = seq_insert_text(&laptop, &laptop_clock, "lists", "l1", 1, "c").unwrap();
+        apply(&mut laptop, ops);
+        let op = seq_delete_at(&laptop, &laptop_clock, "lists", "l1", 0).unwrap();
+        apply(&mut laptop, vec![op]);
+
+        let from_phone = get_oplog_entries_since(&phone, 0).unwrap();
+        let from_laptop = get_oplog_entries_since(&laptop, 0).unwrap();
+        merge(&mut phone, &phone_clock, &from_laptop).unwrap();
+        merge(&mut laptop, &laptop_clock, &from_phone).unwrap();
+
+        let text = sequence_text(&phone, "lists", "l1").unwrap();
+        assert_eq!(text, sequence_text(&laptop, "lists", "l1").unwrap());
+        assert!(text == "bcd" || text == "cbd", "unexpected order: {}", text);
+        assert_eq!(
+            sequence_values(&phone, "lists", "l1").unwrap(),
+            sequence_values(&laptop, "lists", "l1").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_insert_after_unknown_anchor_stays_hidden() {
+        let mut conn = initialize_database(":memory:").unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+
+        let op = seq_insert_op(
+            &conn,
+            &clock,
+            "lists",
+            "l1",
+            Some(Uuid::new_v4()),
+            &json!(1),
+        )
+        .unwrap();
+        apply(&mut conn, vec![op]);
+        assert!(sequence_values(&conn, "lists", "l1").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,419 @@
+//! Snapshot checkpoints for bootstrapping new devices.
+//!
+//! A [`Snapshot`] captures the current state of a replica, tagged with the
+//! [`VersionVector`] it covers:
+//! - the oplog without superseded creates/updates (the same rule as
+//!   [compaction](super::compaction), at any timestamp; deletes are kept so
+//!   late concurrent writes still resolve correctly)
+//! - the IDs of every operation left out, so dependencies on them are met
+//! - the rows of every table with a registered resolver, plus the field-level
+//!   merge state, so nothing has to be replayed. BLOB cells are carried as
+//!   `{"$blob": "<base64>"}` and written back as BLOBs
+//!
+//! A new device restores a snapshot with [`restore_snapshot`] and then only
+//! syncs the operations its version vector does not cover. The newest
+//! checkpoint is kept in the `snapshots` table ([`checkpoint`],
+//! [`latest_checkpoint`]) and can be moved between devices as bytes.
+
+use super::capture::without_capture;
+use super::compaction::superseded_ops;
+use super::resolver::{json_to_sql, quote_identifier, upsert_values};
+use super::{insert_oplog_entry, DeviceClock, HybridLogicalClock, MergeRegistry, VersionVector};
+use crate::db::operations::{get_oplog_entries_since, get_version_vector};
+use crate::error::{AhenkError, Result};
+use crate::models::OplogEntry;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use uuid::Uuid;
+
+/// Library tables holding materialized state, included in every snapshot
+const STATE_TABLES: &[&str] = &["document_fields", "document_tombstones", "oplog_tombstones"];
+
+/// Key of the JSON object a BLOB cell is carried in
+const BLOB_TAG: &str = "$blob";
+
+/// Materialized state of a replica at a version vector
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    /// Unique ID of the snapshot
+    pub id: Uuid,
+    /// When the snapshot was taken
+    pub created_at: DateTime<Utc>,
+    /// Operations covered by the snapshot
+    pub version_vector: VersionVector,
+    /// Operations still needed to resolve future merges
+    pub entries: Vec<OplogEntry>,
+    /// Covered operations left out of `entries`
+    pub compacted: Vec<Uuid>,
+    /// Table rows as JSON objects keyed by column name, with BLOB cells as
+    /// `{"$blob": "<base64>"}`
+    pub tables: BTreeMap<String, Vec<serde_json::Map<String, serde_json::Value>>>,
+}
+
+impl Snapshot {
+    /// Serialize the snapshot for transfer to another device
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self).map_err(|e| AhenkError::Serialization(e.to_string()))
+    }
+
+    /// Deserialize a snapshot produced by [`to_bytes`](Self::to_bytes)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        serde_json::from_slice(bytes).map_err(|e| AhenkError::Serialization(e.to_string()))
+    }
+}
+
+/// Take a snapshot of the local replica.
+///
+/// Rows are captured from every table with a resolver in `registry`.
+pub fn create_snapshot(conn: &Connection, registry: &MergeRegistry) -> Result<Snapshot> {
+    let (superseded, _) = superseded_ops(conn, registry, i64::MAX)?;
+    let superseded: HashSet<String> = superseded.into_iter().collect();
+
+    let mut entries = Vec::new();
+    let mut compacted = Vec::new();
+    for entry in get_oplog_entries_since(conn, i64::MIN)? {
+        if superseded.contains(&entry.id.to_string()) {
+            compacted.push(entry.id);
+        } else {
+            entries.push(entry);
+        }
+    }
+
+    let mut stmt = conn.prepare("SELECT id FROM oplog_compacted")?;
+    for id in stmt.query_map([], |row| row.get::<_, String>(0))? {
+        if let Ok(id) = Uuid::parse_str(&id?) {
+            compacted.push(id);
+        }
+    }
+
+    let mut tables = BTreeMap::new();
+    for table in registry.tables().chain(STATE_TABLES.iter().copied()) {
+        tables.insert(table.to_string(), table_rows(conn, table)?);
+    }
+
+    Ok(Snapshot {
+        id: Uuid::new_v4(),
+        created_at: Utc::now(),
+        version_vector: get_version_vector(conn)?,
+        entries,
+        compacted,
+        tables,
+    })
+}
+
+/// Take a snapshot and store it as the local checkpoint
+pub fn checkpoint(conn: &Connection, registry: &MergeRegistry) -> Result<Snapshot> {
+    let snapshot = create_snapshot(conn, registry)?;
+    save_checkpoint(conn, &snapshot)?;
+    Ok(snapshot)
+}
+
+/// Store a snapshot as the local checkpoint, replacing older ones
+pub fn save_checkpoint(conn: &Connection, snapshot: &Snapshot) -> Result<()> {
+    let version_vector = serde_json::to_string(&snapshot.version_vector)
+        .map_err(|e| AhenkError::Serialization(e.to_string()))?;
+    let data =
+        serde_json::to_string(snapshot).map_err(|e| AhenkError::Serialization(e.to_string()))?;
+
+    conn.execute("DELETE FROM snapshots", [])?;
+    conn.execute(
+        "INSERT INTO snapshots (id, created_at, version_vector, data) VALUES (?1, ?2, ?3, ?4)",
+        params![
+            snapshot.id.to_string(),
+            snapshot.created_at.to_rfc3339(),
+            version_vector,
+            data
+        ],
+    )?;
+    Ok(())
+}
+
+/// The newest local checkpoint, if one was taken
+pub fn latest_checkpoint(conn: &Connection) -> Result<Option<Snapshot>> {
+    let data: Option<String> = conn
+        .query_row(
+            "SELECT data FROM snapshots ORDER BY created_at DESC LIMIT 1",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    data.map(|data| Snapshot::from_bytes(data.as_bytes()))
+        .transpose()
+}
+
+/// Bootstrap an empty replica from a snapshot.
+///
+/// Records the snapshot's operations and version vector, writes its table
+/// rows, advances `clock` past every covered operation and keeps the snapshot
+/// as the local checkpoint. Operations are indexed by the key fields of the
+/// resolvers in `registry`. The app tables must already exist. Fails with a
+/// validation error if the local oplog is not empty.
+pub fn restore_snapshot(
+    conn: &mut Connection,
+    clock: &DeviceClock,
+    registry: &MergeRegistry,
+    snapshot: &Snapshot,
+) -> Result<()> {
+    let tx = conn.transaction()?;
+
+    let existing: i64 = tx.query_row("SELECT COUNT(*) FROM oplog", [], |row| row.get(0))?;
+    if existing > 0 {
+        return Err(AhenkError::Validation(format!(
+            "Cannot restore snapshot {} into a replica with {} oplog entries",
+            snapshot.id, existing
+        )));
+    }
+
+    for entry in &snapshot.entries {
+        insert_oplog_entry(&tx, entry, registry.key_field(&entry.table))?;
+    }
+
+    let mut compacted = tx.prepare("INSERT OR IGNORE INTO oplog_compacted (id) VALUES (?1)")?;
+    for id in &snapshot.compacted {
+        compacted.execute(params![id.to_string()])?;
+    }
+    drop(compacted);
+
+    // The version vector also covers the operations left out of the snapshot
+    let mut version_vector = tx.prepare(
+        "INSERT INTO version_vector (device_id, timestamp) VALUES (?1, ?2)
+         ON CONFLICT(device_id) DO UPDATE SET timestamp = MAX(timestamp, excluded.timestamp)",
+    )?;
+    for (device_id, timestamp) in snapshot.version_vector.iter() {
+        version_vector.execute(params![device_id.to_string(), timestamp])?;
+    }
+    drop(version_vector);
+
+    without_capture(&tx, || {
+        for (table, rows) in &snapshot.tables {
+            for row in rows {
+                let values = row
+                    .iter()
+                    .map(|(column, cell)| Ok((column.as_str(), cell_value(cell)?)))
+                    .collect::<Result<Vec<_>>>()?;
+                upsert_values(&tx, table, values)?;
+            }
+        }
+        Ok(())
+    })?;
+
+    if let Some(latest) = snapshot.version_vector.iter().map(|(_, ts)| ts).max() {
+        clock.observe(&tx, HybridLogicalClock::from_timestamp(latest))?;
+    }
+    save_checkpoint(&tx, snapshot)?;
+
+    tx.commit()?;
+    Ok(())
+}
+
+/// Every row of a table as a JSON object
+fn table_rows(
+    conn: &Connection,
+    table: &str,
+) -> Result<Vec<serde_json::Map<String, serde_json::Value>>> {
+    let mut stmt = conn.prepare(&format!("SELECT * FROM {}", quote_identifier(table)))?;
+    let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+
+    let mut rows = stmt.query([])?;
+    let mut result = Vec::new();
+    while let Some(row) = rows.next()? {
+        let mut object = serde_json::Map::new();
+        for (i, column) in columns.iter().enumerate() {
+            let value = match row.get_ref(i)? {
+                ValueRef::Null => serde_json::Value::Null,
+                ValueRef::Integer(i) => i.into(),
+                ValueRef::Real(f) => f.into(),
+                ValueRef::Text(t) => String::from_utf8_lossy(t).into(),
+                ValueRef::Blob(b) => serde_json::json!({ BLOB_TAG: BASE64.encode(b) }),
+            };
+            object.insert(column.clone(), value);
+        }
+        result.push(object);
+    }
+    Ok(result)
+}
+
+/// SQL value of a cell produced by [`table_rows`]
+fn cell_value(cell: &serde_json::Value) -> Result<Value> {
+    let blob = cell
+        .as_object()
+        .filter(|object| object.len() == 1)
+        .and_then(|object| object.get(BLOB_TAG));
+    match blob {
+        Some(serde_json::Value::String(encoded)) => BASE64
+            .decode(encoded)
+            .map(Value::Blob)
+            .map_err(|e| AhenkError::Serialization(format!("Invalid BLOB cell: {}", e))),
+        _ => Ok(json_to_sql(cell)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{local_apply_with, merge_with, FieldLww, LastWriteWins};
+    use crate::db::operations::{get_oplog_entries_missing, initialize_database};
+    use crate::logic::build_oplog_entry;
+    use serde_json::json;
+
+    fn setup() -> (Connection, DeviceClock, MergeRegistry) {
+        let conn = initialize_database(":memory:").unwrap();
+        conn.execute(
+            "CREATE TABLE todos (id TEXT PRIMARY KEY, title TEXT, completed INTEGER)",
+            [],
+        )
+        .unwrap();
+        let registry = MergeRegistry::new().with_resolver("todos", FieldLww::new());
+        (conn, DeviceClock::new(Uuid::new_v4()), registry)
+    }
+
+    fn write(
+        conn: &mut Connection,
+        clock: &DeviceClock,
+        registry: &MergeRegistry,
+        op_type: &str,
+        data: serde_json::Value,
+    ) -> OplogEntry {
+        let op = build_oplog_entry(conn, clock, "todos", op_type, &data).unwrap();
+        local_apply_with(conn, &op, registry).unwrap();
+        op
+    }
+
+    fn rows(conn: &Connection) -> Vec<(String, String, bool)> {
+        conn.prepare("SELECT id, title, completed FROM todos ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_new_device_bootstraps_from_snapshot() {
+        let (mut origin, origin_clock, registry) = setup();
+        for i in 0..5 {
+            write(
+                &mut origin,
+                &origin_clock,
+                &registry,
+                "update",
+                json!({"id": "t1", "title": format!("draft {}", i), "completed": false}),
+            );
+        }
+        write(
+            &mut origin,
+            &origin_clock,
+            &registry,
+            "create",
+            json!({"id": "t2", "title": "groceries", "completed": true}),
+        );
+
+        let snapshot = checkpoint(&origin, &registry).unwrap();
+        assert_eq!(snapshot.entries.len(), 2);
+        assert_eq!(snapshot.compacted.len(), 4);
+        let bytes = latest_checkpoint(&origin)
+            .unwrap()
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+
+        let (mut device, device_clock, _) = setup();
+        restore_snapshot(
+            &mut device,
+            &device_clock,
+            &registry,
+            &Snapshot::from_bytes(&bytes).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(rows(&device), rows(&origin));
+        assert_eq!(
+            get_version_vector(&device).unwrap(),
+            get_version_vector(&origin).unwrap()
+        );
+        assert!(
+            device_clock.last(&device).unwrap().unwrap().to_timestamp()
+                >= origin_clock.last(&origin).unwrap().unwrap().to_timestamp()
+        );
+
+        // Only operations after the snapshot are synced
+        let later = write(
+            &mut origin,
+            &origin_clock,
+            &registry,
+            "update",
+            json!({"id": "t1", "completed": true}),
+        );
+        let missing =
+            get_oplog_entries_missing(&origin, &get_version_vector(&device).unwrap()).unwrap();
+        assert_eq!(
+            missing.iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![later.id]
+        );
+
+        merge_with(&mut device, &device_clock, &missing, &registry).unwrap();
+        assert_eq!(rows(&device), rows(&origin));
+    }
+
+    #[test]
+    fn test_restore_requires_empty_oplog() {
+        let (mut conn, clock, registry) = setup();
+        let snapshot = create_snapshot(&conn, &registry).unwrap();
+        write(
+            &mut conn,
+            &clock,
+            &registry,
+            "create",
+            json!({"id": "t1", "title": "x", "completed": false}),
+        );
+        assert!(matches!(
+            restore_snapshot(&mut conn, &clock, &registry, &snapshot),
+            Err(AhenkError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn test_blob_columns_round_trip() {
+        let schema = "CREATE TABLE attachments (id TEXT PRIMARY KEY, body BLOB)";
+        let registry = MergeRegistry::new().with_resolver("attachments", LastWriteWins::new());
+        let body: Vec<u8> = vec![0x00, 0xff, 0x7b, 0x22, 0x80];
+
+        let origin = initialize_database(":memory:").unwrap();
+        origin.execute(schema, []).unwrap();
+        origin
+            .execute(
+                "INSERT INTO attachments (id, body) VALUES ('a1', ?1)",
+                params![body],
+            )
+            .unwrap();
+        let snapshot = create_snapshot(&origin, &registry).unwrap();
+        assert_eq!(
+            snapshot.tables["attachments"][0]["body"],
+            json!({"$blob": "AP97IoA="})
+        );
+
+        let mut device = initialize_database(":memory:").unwrap();
+        device.execute(schema, []).unwrap();
+        restore_snapshot(
+            &mut device,
+            &DeviceClock::new(Uuid::new_v4()),
+            &registry,
+            &Snapshot::from_bytes(&snapshot.to_bytes().unwrap()).unwrap(),
+        )
+        .unwrap();
+        let (kind, restored): (String, Vec<u8>) = device
+            .query_row(
+                "SELECT typeof(body), body FROM attachments WHERE id = 'a1'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(kind, "blob");
+        assert_eq!(restored, body);
+    }
+}
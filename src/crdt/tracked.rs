@@ -0,0 +1,242 @@
+//! Tracked transactions: app writes and their oplog entries, atomically.
+//!
+//! [`local_apply`](super::local_apply) records an operation in its own
+//! statement, so an app write and the operation describing it can commit
+//! separately. [`with_tracked_tx`] runs the app's writes and the operations
+//! it records in one transaction instead:
+//!
+//! ```rust,no_run
+//! use ahenk::{build_oplog_entry, with_tracked_tx, DeviceClock, MergeRegistry};
+//! # use rusqlite::Connection;
+//! # use uuid::Uuid;
+//!
+//! # fn example(mut conn: Connection, device_id: Uuid) -> ahenk::Result<()> {
+//! let clock = DeviceClock::new(device_id);
+//! let registry = MergeRegistry::new();
+//!
+//! let group_id = with_tracked_tx(&mut conn, &registry, |tx| {
+//!     tx.execute("INSERT INTO todos (id, title) VALUES ('t1', 'Buy milk')", [])?;
+//!     let op = build_oplog_entry(tx, &clock, "todos", "create", &serde_json::json!({
+//!         "id": "t1",
+//!         "title": "Buy milk",
+//!     }))?;
+//!     tx.record(&op)?;
+//!     Ok(tx.group_id())
+//! })?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! If the closure returns an error, nothing it wrote is kept. Every operation
+//! that enters the oplog during the transaction, whether recorded with
+//! [`TrackedTx::record`] or [captured](super::capture) from a tracked table,
+//! gets the transaction's group ID (see [`group_operations`]). Groups are
+//! local bookkeeping and are not synced.
+
+use super::{apply_local, MergeRegistry};
+use crate::db::operations::{parse_uuid_column, row_to_oplog_entry};
+use crate::error::Result;
+use crate::models::OplogEntry;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::ops::Deref;
+use uuid::Uuid;
+
+/// Transaction passed to a [`with_tracked_tx`] closure.
+///
+/// Dereferences to the underlying [`Connection`] for the app's own
+/// statements.
+pub struct TrackedTx<'conn> {
+    tx: Transaction<'conn>,
+    registry: &'conn MergeRegistry,
+    group_id: Uuid,
+}
+
+impl TrackedTx<'_> {
+    /// Group ID shared by the operations recorded in this transaction
+    pub fn group_id(&self) -> Uuid {
+        self.group_id
+    }
+
+    /// Record a local operation, as [`local_apply_with`](super::local_apply_with)
+    /// does, as part of this transaction
+    pub fn record(&self, op: &OplogEntry) -> Result<()> {
+        op.validate()?;
+        apply_local(&self.tx, op, self.registry)?;
+        Ok(())
+    }
+}
+
+impl Deref for TrackedTx<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        &self.tx
+    }
+}
+
+/// Run `f` in a transaction that commits the app's writes together with the
+/// operations recorded for them.
+///
+/// Commits only if `f` succeeds. Subscribers on `registry` (see
+/// [`observer`](super::observer)) receive the group's operations after commit.
+pub fn with_tracked_tx<T>(
+    conn: &mut Connection,
+    registry: &MergeRegistry,
+    f: impl FnOnce(&TrackedTx<'_>) -> Result<T>,
+) -> Result<T> {
+    let tx = conn.transaction()?;
+    // Rows are appended, so everything above the current largest rowid is
+    // written by this transaction
+    let start: i64 = tx.query_row("SELECT COALESCE(MAX(rowid), 0) FROM oplog", [], |row| {
+        row.get(0)
+    })?;
+    let tracked = TrackedTx {
+        tx,
+        registry,
+        group_id: Uuid::new_v4(),
+    };
+
+    let value = f(&tracked)?;
+
+    let TrackedTx { tx, group_id, .. } = tracked;
+    tx.execute(
+        "INSERT INTO oplog_groups (op_id, group_id)
+         SELECT id, ?1 FROM oplog WHERE rowid > ?2",
+        params![group_id.to_string(), start],
+    )?;
+    let recorded = tx
+        .prepare(
+            "SELECT id, device_id, timestamp, table_name, op_type, data, deps, schema_version
+             FROM oplog WHERE rowid > ?1 ORDER BY rowid",
+        )?
+        .query_map(params![start], row_to_oplog_entry)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    tx.commit()?;
+    registry.notify(&recorded);
+    Ok(value)
+}
+
+/// Operations recorded by the tracked transaction `group_id`, in order
+pub fn group_operations(conn: &Connection, group_id: Uuid) -> Result<Vec<OplogEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT o.id, o.device_id, o.timestamp, o.table_name, o.op_type, o.data, o.deps,
+                o.schema_version
+         FROM oplog_groups g JOIN oplog o ON o.id = g.op_id
+         WHERE g.group_id = ?1
+         ORDER BY o.timestamp, o.device_id, o.id",
+    )?;
+    let ops = stmt
+        .query_map(params![group_id.to_string()], row_to_oplog_entry)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(ops)
+}
+
+/// Group of the tracked transaction that recorded `op_id`, if any
+pub fn operation_group(conn: &Connection, op_id: Uuid) -> Result<Option<Uuid>> {
+    let group = conn
+        .query_row(
+            "SELECT group_id FROM oplog_groups WHERE op_id = ?1",
+            params![op_id.to_string()],
+            |row| parse_uuid_column(row, 0),
+        )
+        .optional()?;
+    Ok(group)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::observer::TableFilter;
+    use crate::crdt::{track_table, DeviceClock, LastWriteWins};
+    use crate::db::operations::{get_oplog_entries_since, initialize_database};
+    use crate::error::AhenkError;
+    use crate::logic::build_oplog_entry;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    fn setup() -> (Connection, DeviceClock) {
+        let conn = initialize_database(":memory:").unwrap();
+        conn.execute("CREATE TABLE todos (id TEXT PRIMARY KEY, title TEXT)", [])
+            .unwrap();
+        (conn, DeviceClock::new(Uuid::new_v4()))
+    }
+
+    fn todo_count(conn: &Connection) -> i64 {
+        conn.query_row("SELECT COUNT(*) FROM todos", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_writes_and_ops_commit_together() {
+        let (mut conn, clock) = setup();
+        let registry = MergeRegistry::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        registry.subscribe(TableFilter::All, move |ops| {
+            sink.lock().unwrap().push(ops.len());
+        });
+
+        let group_id = with_tracked_tx(&mut conn, &registry, |tx| {
+            for id in ["t1", "t2"] {
+                tx.execute(
+                    "INSERT INTO todos (id, title) VALUES (?1, 'new')",
+                    params![id],
+                )?;
+                let op = build_oplog_entry(tx, &clock, "todos", "create", &json!({"id": id}))?;
+                tx.record(&op)?;
+            }
+            Ok(tx.group_id())
+        })
+        .unwrap();
+
+        let ops = group_operations(&conn, group_id).unwrap();
+        let ids: Vec<Uuid> = ops.iter().map(|op| op.id).collect();
+        let all: Vec<Uuid> = get_oplog_entries_since(&conn, 0)
+            .unwrap()
+            .iter()
+            .map(|op| op.id)
+            .collect();
+        assert_eq!(ids.len(), 2);
+        assert_eq!(ids, all);
+        assert_eq!(operation_group(&conn, ops[0].id).unwrap(), Some(group_id));
+        assert_eq!(todo_count(&conn), 2);
+        assert_eq!(*seen.lock().unwrap(), vec![2]);
+
+        // A failure after the oplog insert rolls back the app write too
+        let result = with_tracked_tx(&mut conn, &registry, |tx| {
+            tx.execute("INSERT INTO todos (id, title) VALUES ('t3', 'new')", [])?;
+            let op = build_oplog_entry(tx, &clock, "todos", "create", &json!({"id": "t3"}))?;
+            tx.record(&op)?;
+            Err::<(), _>(AhenkError::Validation("title required".to_string()))
+        });
+        assert!(result.is_err());
+        assert_eq!(todo_count(&conn), 2);
+        assert_eq!(get_oplog_entries_since(&conn, 0).unwrap().len(), 2);
+        assert_eq!(seen.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_captured_writes_join_the_group() {
+        let (mut conn, clock) = setup();
+        let registry = MergeRegistry::new().with_resolver("todos", LastWriteWins::new());
+        track_table(&conn, &clock, &registry, "todos").unwrap();
+
+        let group_id = with_tracked_tx(&mut conn, &registry, |tx| {
+            tx.execute("INSERT INTO todos (id, title) VALUES ('t1', 'draft')", [])?;
+            tx.execute("UPDATE todos SET title = 'final' WHERE id = 't1'", [])?;
+            Ok(tx.group_id())
+        })
+        .unwrap();
+
+        let ops = group_operations(&conn, group_id).unwrap();
+        let op_types: Vec<&str> = ops.iter().map(|op| op.op_type.as_str()).collect();
+        assert_eq!(op_types, vec!["create", "update"]);
+
+        // Writes outside a tracked transaction are captured without a group
+        conn.execute("DELETE FROM todos", []).unwrap();
+        let delete = get_oplog_entries_since(&conn, 0).unwrap().pop().unwrap();
+        assert_eq!(delete.op_type, "delete");
+        assert_eq!(operation_group(&conn, delete.id).unwrap(), None);
+    }
+}
@@ -0,0 +1,716 @@
+//! Undo and redo with compensating operations.
+//!
+//! Operations are never removed from the oplog. Undoing one records a new
+//! operation that reverts its effect, computed from the state before it:
+//!
+//! | Undone op | Compensating op |
+//! |---|---|
+//! | `create` / `update` | `update` restoring the fields it wrote, or `delete` if the entity did not exist before |
+//! | `delete` | `create` with the entity's state before the delete |
+//! | `increment` | `increment` by the negated amount |
+//! | `add` / `remove` | `remove` of that add's tag / `add` of the element |
+//! | `seq_insert` / `seq_delete` | `seq_delete` of the element / `seq_insert` of its value right after it |
+//!
+//! Compensating operations depend on the operation they revert (see
+//! [`OplogEntry::deps`]) and sync like any other operation, so an undo on one
+//! device is undone everywhere.
+//!
+//! Each device keeps its own undo and redo stacks of operation groups (one
+//! user action, possibly several operations). [`record_undo_group`] pushes a
+//! group, [`undo`] reverts the newest one and moves it to the redo stack, and
+//! [`redo`] reverts that undo.
+//!
+//! Entity state is rebuilt from the oplog the way the table's resolver folds
+//! it (see [`Replay`]), so operations must not have been removed by
+//! [compaction](super::compaction) yet. Row operations on tables whose
+//! resolver would ignore the compensating operation cannot be undone: any of
+//! them on [`KeepFirst`](super::KeepFirst) tables, and deletes on
+//! [`DeleteWins`](super::DeleteWins) tables.
+
+use super::counter::INCREMENT;
+use super::history::EntityReplay;
+use super::orset::{SET_ADD, SET_REMOVE};
+use super::patch::is_patch_op;
+use super::resolver::{entity_key, is_row_op, Replay};
+use super::sequence::{SEQ_DELETE, SEQ_INSERT};
+use super::{apply_local, new_local_op, DeviceClock, MergeRegistry};
+use crate::db::operations::{parse_uuid_column, row_to_oplog_entry};
+use crate::error::{AhenkError, Result};
+use crate::models::{OplogEntry, OrderKey};
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+const UNDO: &str = "undo";
+const REDO: &str = "redo";
+
+/// A group of operations on an undo or redo stack
+struct StackEntry {
+    seq: i64,
+    group_id: Uuid,
+    op_ids: Vec<Uuid>,
+}
+
+/// Build the operation that reverts `op_id`.
+///
+/// Returns `None` if the operation no longer has an effect to revert, e.g. an
+/// update to an entity that has since been deleted. Record the result with
+/// [`local_apply_with`](super::local_apply_with) like any other operation.
+pub fn inverse_op(
+    conn: &Connection,
+    clock: &DeviceClock,
+    registry: &MergeRegistry,
+    op_id: Uuid,
+) -> Result<Option<OplogEntry>> {
+    let op = load_op(conn, op_id)?;
+
+    let inverse = match op.op_type.as_str() {
+        op_type if is_row_op(op_type) || is_patch_op(op_type) => row_inverse(conn, registry, &op)?,
+        INCREMENT => {
+            let amount = op.data["amount"].as_i64().unwrap_or_default();
+            Some((INCREMENT, json!({"id": op.data["id"], "amount": -amount})))
+        }
+        SET_ADD => Some((
+            SET_REMOVE,
+            json!({"id": op.data["id"], "element": op.data["element"], "tags": [op.id.to_string()]}),
+        )),
+        SET_REMOVE => Some((
+            SET_ADD,
+            json!({"id": op.data["id"], "element": op.data["element"]}),
+        )),
+        SEQ_INSERT => Some((
+            SEQ_DELETE,
+            json!({"id": op.data["id"], "target": op.id.to_string()}),
+        )),
+        SEQ_DELETE => {
+            // Re-insert the value right after the hidden element, which
+            // still anchors its old position
+            let target = op.data["target"].as_str().unwrap_or_default();
+            let value = Uuid::parse_str(target)
+                .ok()
+                .map(|id| load_op(conn, id))
+                .transpose()?
+                .map(|insert| insert.data["value"].clone());
+            value.map(|value| {
+                (
+                    SEQ_INSERT,
+                    json!({"id": op.data["id"], "after": target, "value": value}),
+                )
+            })
+        }
+        other => {
+            return Err(AhenkError::Validation(format!(
+                "Operations of type '{}' cannot be undone",
+                other
+            )))
+        }
+    };
+
+    inverse
+        .map(|(op_type, data)| {
+            Ok(new_local_op(conn, clock, &op.table, op_type, data)?
+                .with_deps([op.id])
+                .with_schema_version(op.schema_version))
+        })
+        .transpose()
+}
+
+/// Push a group of local operations onto the device's undo stack and clear
+/// its redo stack, returning the group ID
+pub fn record_undo_group(conn: &Connection, device_id: Uuid, op_ids: &[Uuid]) -> Result<Uuid> {
+    let group_id = Uuid::new_v4();
+    conn.execute(
+        "DELETE FROM undo_stack WHERE device_id = ?1 AND stack = ?2",
+        params![device_id.to_string(), REDO],
+    )?;
+    push(conn, device_id, UNDO, group_id, op_ids)?;
+    Ok(group_id)
+}
+
+/// Revert the newest group on the device's undo stack.
+///
+/// Records and resolves the compensating operations in one transaction and
+/// moves the group to the redo stack. Returns the recorded operations (none
+/// if the stack is empty) so the caller can send them to peers.
+pub fn undo(
+    conn: &mut Connection,
+    clock: &DeviceClock,
+    registry: &MergeRegistry,
+) -> Result<Vec<OplogEntry>> {
+    move_group(conn, clock, registry, UNDO, REDO, None)
+}
+
+/// Revert the newest undo on the device's redo stack and move its group back
+/// to the undo stack
+pub fn redo(
+    conn: &mut Connection,
+    clock: &DeviceClock,
+    registry: &MergeRegistry,
+) -> Result<Vec<OplogEntry>> {
+    move_group(conn, clock, registry, REDO, UNDO, None)
+}
+
+/// Revert a specific group on the device's undo stack, even if newer groups
+/// were recorded after it, and move it to the redo stack
+pub fn undo_group(
+    conn: &mut Connection,
+    clock: &DeviceClock,
+    registry: &MergeRegistry,
+    group_id: Uuid,
+) -> Result<Vec<OplogEntry>> {
+    move_group(conn, clock, registry, UNDO, REDO, Some(group_id))
+}
+
+/// Whether the device has a group to undo
+pub fn can_undo(conn: &Connection, device_id: Uuid) -> Result<bool> {
+    Ok(top(conn, device_id, UNDO, None)?.is_some())
+}
+
+/// Whether the device has an undo to redo
+pub fn can_redo(conn: &Connection, device_id: Uuid) -> Result<bool> {
+    Ok(top(conn, device_id, REDO, None)?.is_some())
+}
+
+/// Revert a stack entry's operations, newest first, and push the
+/// compensating operations onto the other stack
+fn move_group(
+    conn: &mut Connection,
+    clock: &DeviceClock,
+    registry: &MergeRegistry,
+    from: &str,
+    to: &str,
+    group_id: Option<Uuid>,
+) -> Result<Vec<OplogEntry>> {
+    let tx = conn.transaction()?;
+    let device_id = clock.device_id();
+
+    let Some(entry) = top(&tx, device_id, from, group_id)? else {
+        return match group_id {
+            Some(group_id) => Err(AhenkError::NotFound(format!("Undo group {}", group_id))),
+            None => Ok(Vec::new()),
+        };
+    };
+
+    // Each compensating op is applied before the next is computed, so ops in
+    // one group touching the same entity unwind in order
+    let mut applied = Vec::new();
+    for op_id in entry.op_ids.iter().rev() {
+        if let Some(inverse) = inverse_op(&tx, clock, registry, *op_id)? {
+            inverse.validate()?;
+            applied.extend(apply_local(&tx, &inverse, registry)?);
+        }
+    }
+
+    tx.execute("DELETE FROM undo_stack WHERE seq = ?1", params![entry.seq])?;
+    let ids: Vec<Uuid> = applied.iter().map(|op| op.id).collect();
+    push(&tx, device_id, to, entry.group_id, &ids)?;
+
+    tx.commit()?;
+    registry.notify(&applied);
+    Ok(applied)
+}
+
+/// Compensating op type and payload for a `create`, `update` or `delete`.
+///
+/// Fails with a validation error if the table's resolver would ignore it.
+fn row_inverse(
+    conn: &Connection,
+    registry: &MergeRegistry,
+    op: &OplogEntry,
+) -> Result<Option<(&'static str, Value)>> {
+    let replay = registry.replay(&op.table);
+    match replay {
+        Replay::KeepFirst => {
+            return Err(AhenkError::Validation(format!(
+                "Operations on '{}' cannot be undone: its first write is kept",
+                op.table
+            )))
+        }
+        Replay::DeleteWins if op.op_type == "delete" => {
+            return Err(AhenkError::Validation(format!(
+                "Deletes on '{}' cannot be undone: deletes win",
+                op.table
+            )))
+        }
+        _ => {}
+    }
+    let key_field = registry.key_field(&op.table);
+    let key = entity_key(op, key_field)?;
+
+    let mut stmt = conn.prepare(
+        "SELECT id, device_id, timestamp, op_type, data FROM oplog
+         WHERE table_name = ?1
+           AND op_type IN ('create', 'update', 'delete', 'patch', 'merge-patch')
+           AND entity_id = CAST(?2 AS TEXT)
+         ORDER BY timestamp, device_id, id",
+    )?;
+    let rows = stmt.query_map(params![op.table, key], |row| {
+        Ok((
+            OrderKey {
+                id: parse_uuid_column(row, 0)?,
+                device_id: parse_uuid_column(row, 1)?,
+                timestamp: row.get(2)?,
+            },
+            row.get::<_, String>(3)?,
+            row.get::<_, String>(4)?,
+        ))
+    })?;
+
+    // Entity state just before and after `op`, and after every known op
+    let mut before = EntityReplay::default();
+    let mut after = EntityReplay::default();
+    let mut current = EntityReplay::default();
+    let op_order = op.order_key();
+    for row in rows {
+        let (order, op_type, data) = row?;
+        let data: Value =
+            serde_json::from_str(&data).map_err(|e| AhenkError::Serialization(e.to_string()))?;
+        if order < op_order {
+            before.apply(replay, &op_type, &data);
+        }
+        if order <= op_order {
+            after.apply(replay, &op_type, &data);
+        }
+        current.apply(replay, &op_type, &data);
+    }
+    let (before, after, current) = (before.state, after.state, current.state);
+
+    // Fields the op wrote: its payload (the whole row if writes replace
+    // it), or for a patch whatever it changed
+    let written: Vec<String> = if is_patch_op(&op.op_type) {
+        let (before, after) = (
+            before.clone().unwrap_or_default(),
+            after.unwrap_or_default(),
+        );
+        before
+            .keys()
+            .chain(after.keys())
+            .filter(|field| before.get(*field) != after.get(*field))
+            .cloned()
+            .collect()
+    } else {
+        let replaced = match (replay, &before) {
+            (Replay::ReplaceRow | Replay::DeleteWins, Some(before)) => before.keys().collect(),
+            _ => Vec::new(),
+        };
+        op.data
+            .as_object()
+            .into_iter()
+            .flat_map(|o| o.keys())
+            .chain(replaced)
+            .cloned()
+            .collect()
+    };
+
+    let inverse = match (op.op_type.as_str(), before, current) {
+        // Restore the entity, keeping fields written since the delete
+        ("delete", Some(mut before), current) => {
+            before.extend(current.unwrap_or_default());
+            Some(("create", Value::Object(before)))
+        }
+        ("delete", None, _) => None,
+        // A patch to a missing entity did nothing
+        (op_type, None, _) if is_patch_op(op_type) => None,
+        // The write created the entity
+        (_, None, Some(_)) => Some(("delete", json!({ key_field: op.data[key_field] }))),
+        // Put back the fields the write changed
+        (_, Some(before), Some(current)) => {
+            let mut restored = current.clone();
+            for field in &written {
+                if field != key_field {
+                    let value = before.get(field).cloned().unwrap_or(Value::Null);
+                    restored.insert(field.clone(), value);
+                }
+            }
+            (restored != current).then_some(("update", Value::Object(restored)))
+        }
+        // Deleted since the write
+        (_, _, None) => None,
+    };
+    Ok(inverse)
+}
+
+fn load_op(conn: &Connection, op_id: Uuid) -> Result<OplogEntry> {
+    conn.query_row(
+        "SELECT id, device_id, timestamp, table_name, op_type, data, deps, schema_version
+         FROM oplog WHERE id = ?1",
+        params![op_id.to_string()],
+        row_to_oplog_entry,
+    )
+    .optional()?
+    .ok_or_else(|| AhenkError::NotFound(format!("Operation {}", op_id)))
+}
+
+fn push(
+    conn: &Connection,
+    device_id: Uuid,
+    stack: &str,
+    group_id: Uuid,
+    op_ids: &[Uuid],
+) -> Result<()> {
+    let op_ids =
+        serde_json::to_string(op_ids).map_err(|e| AhenkError::Serialization(e.to_string()))?;
+    conn.execute(
+        "INSERT INTO undo_stack (device_id, stack, group_id, op_ids, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            device_id.to_string(),
+            stack,
+            group_id.to_string(),
+            op_ids,
+            Utc::now().to_rfc3339()
+        ],
+    )?;
+    Ok(())
+}
+
+/// Newest entry on a stack, or the entry for `group_id`
+fn top(
+    conn: &Connection,
+    device_id: Uuid,
+    stack: &str,
+    group_id: Option<Uuid>,
+) -> Result<Option<StackEntry>> {
+    let row: Option<(i64, String, String)> = conn
+        .query_row(
+            "SELECT seq, group_id, op_ids FROM undo_stack
+             WHERE device_id = ?1 AND stack = ?2 AND (?3 IS NULL OR group_id = ?3)
+             ORDER BY seq DESC LIMIT 1",
+            params![
+                device_id.to_string(),
+                stack,
+                group_id.map(|g| g.to_string())
+            ],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?;
+
+    row.map(|(seq, group_id, op_ids)| {
+        Ok(StackEntry {
+            seq,
+            group_id: Uuid::parse_str(&group_id)
+                .map_err(|e| AhenkError::Serialization(e.to_string()))?,
+            op_ids: serde_json::from_str(&op_ids)
+                .map_err(|e| AhenkError::Serialization(e.to_string()))?,
+        })
+    })
+    .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{
+        counter_value, increment_op, list_conflicts, local_apply_with, merge_with, seq_delete_at,
+        seq_insert_text, sequence_text, DeleteWins, FieldLww, KeepFirst, LastWriteWins,
+        ManualClock,
+    };
+    use crate::db::operations::{get_oplog_entries_since, initialize_database};
+    use crate::logic::build_oplog_entry;
+    use std::sync::Arc;
+
+    fn setup(registry: MergeRegistry) -> (Connection, DeviceClock, MergeRegistry) {
+        let conn = initialize_database(":memory:").unwrap();
+        conn.execute(
+            "CREATE TABLE todos (id TEXT PRIMARY KEY, title TEXT, completed INTEGER)",
+            [],
+        )
+        .unwrap();
+        (conn, DeviceClock::new(Uuid::new_v4()), registry)
+    }
+
+    fn write(
+        conn: &mut Connection,
+        clock: &DeviceClock,
+        registry: &MergeRegistry,
+        op_type: &str,
+        data: Value,
+    ) -> OplogEntry {
+        let op = build_oplog_entry(conn, clock, "todos", op_type, &data).unwrap();
+        local_apply_with(conn, &op, registry).unwrap();
+        op
+    }
+
+    fn todo(conn: &Connection) -> Option<(String, bool)> {
+        conn.query_row(
+            "SELECT title, completed FROM todos WHERE id = 't1'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_undo_and_redo_row_writes() {
+        let (mut conn, clock, registry) =
+            setup(MergeRegistry::new().with_resolver("todos", FieldLww::new()));
+        let device = clock.device_id();
+
+        let create = write(
+            &mut conn,
+            &clock,
+            &registry,
+            "create",
+            json!({"id": "t1", "title": "draft", "completed": false}),
+        );
+        record_undo_group(&conn, device, &[create.id]).unwrap();
+        let rename = write(
+            &mut conn,
+            &clock,
+            &registry,
+            "update",
+            json!({"id": "t1", "title": "final"}),
+        );
+        let complete = write(
+            &mut conn,
+            &clock,
+            &registry,
+            "update",
+            json!({"id": "t1", "completed": true}),
+        );
+        record_undo_group(&conn, device, &[rename.id, complete.id]).unwrap();
+        assert_eq!(todo(&conn), Some(("final".to_string(), true)));
+
+        let undone = undo(&mut conn, &clock, &registry).unwrap();
+        assert_eq!(undone.len(), 2);
+        assert_eq!(undone[0].deps, vec![complete.id]);
+        assert_eq!(todo(&conn), Some(("draft".to_string(), false)));
+
+        undo(&mut conn, &clock, &registry).unwrap();
+        assert_eq!(todo(&conn), None);
+        assert!(!can_undo(&conn, device).unwrap());
+        assert!(undo(&mut conn, &clock, &registry).unwrap().is_empty());
+
+        redo(&mut conn, &clock, &registry).unwrap();
+        assert_eq!(todo(&conn), Some(("draft".to_string(), false)));
+        redo(&mut conn, &clock, &registry).unwrap();
+        assert_eq!(todo(&conn), Some(("final".to_string(), true)));
+        assert!(!can_redo(&conn, device).unwrap());
+
+        // A new action clears the redo stack
+        undo(&mut conn, &clock, &registry).unwrap();
+        let other = write(
+            &mut conn,
+            &clock,
+            &registry,
+            "update",
+            json!({"id": "t1", "title": "other"}),
+        );
+        record_undo_group(&conn, device, &[other.id]).unwrap();
+        assert!(!can_redo(&conn, device).unwrap());
+    }
+
+    #[test]
+    fn test_undo_syncs_to_other_devices() {
+        let (mut phone, phone_clock, registry) =
+            setup(MergeRegistry::new().with_resolver("todos", LastWriteWins::new()));
+        let (mut laptop, laptop_clock, _) = setup(MergeRegistry::new());
+
+        write(
+            &mut phone,
+            &phone_clock,
+            &registry,
+            "create",
+            json!({"id": "t1", "title": "draft", "completed": false}),
+        );
+        let delete = write(
+            &mut phone,
+            &phone_clock,
+            &registry,
+            "delete",
+            json!({"id": "t1"}),
+        );
+        record_undo_group(&phone, phone_clock.device_id(), &[delete.id]).unwrap();
+        undo(&mut phone, &phone_clock, &registry).unwrap();
+        assert_eq!(todo(&phone), Some(("draft".to_string(), false)));
+
+        // The laptop receives the ops in reverse; the restore waits for the delete
+        let mut ops = get_oplog_entries_since(&phone, 0).unwrap();
+        ops.reverse();
+        merge_with(&mut laptop, &laptop_clock, &ops[..1], &registry).unwrap();
+        assert_eq!(todo(&laptop), None);
+        merge_with(&mut laptop, &laptop_clock, &ops[1..], &registry).unwrap();
+        assert_eq!(todo(&laptop), Some(("draft".to_string(), false)));
+    }
+
+    #[test]
+    fn test_undo_detects_conflicts_with_concurrent_writes() {
+        let (mut phone, phone_clock, registry) =
+            setup(MergeRegistry::new().with_resolver("todos", LastWriteWins::new()));
+        let (mut laptop, _, _) = setup(MergeRegistry::new());
+        // The laptop's clock runs ahead, so its write is the entity's latest
+        let laptop_clock = DeviceClock::with_clock(
+            Uuid::new_v4(),
+            Arc::new(ManualClock::new(Utc::now() + chrono::Duration::minutes(1))),
+        );
+
+        write(
+            &mut phone,
+            &phone_clock,
+            &registry,
+            "create",
+            json!({"id": "t1", "title": "draft", "completed": false}),
+        );
+        let ops = get_oplog_entries_since(&phone, 0).unwrap();
+        merge_with(&mut laptop, &laptop_clock, &ops, &registry).unwrap();
+
+        let rename = write(
+            &mut phone,
+            &phone_clock,
+            &registry,
+            "update",
+            json!({"id": "t1", "title": "phone"}),
+        );
+        record_undo_group(&phone, phone_clock.device_id(), &[rename.id]).unwrap();
+        let concurrent = write(
+            &mut laptop,
+            &laptop_clock,
+            &registry,
+            "update",
+            json!({"id": "t1", "title": "laptop"}),
+        );
+        merge_with(
+            &mut phone,
+            &phone_clock,
+            std::slice::from_ref(&concurrent),
+            &registry,
+        )
+        .unwrap();
+        assert_eq!(list_conflicts(&phone).unwrap().len(), 1);
+
+        // Reverting the rename races the laptop's write as well
+        let undone = undo(&mut phone, &phone_clock, &registry).unwrap();
+        let conflicts = list_conflicts(&phone).unwrap();
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!(conflicts[1].local.id, concurrent.id);
+        assert_eq!(conflicts[1].remote.id, undone[0].id);
+    }
+
+    #[test]
+    fn test_inverse_of_counter_and_sequence_ops() {
+        let (mut conn, clock, registry) = setup(MergeRegistry::new());
+        let device = clock.device_id();
+
+        let op = increment_op(&conn, &clock, "likes", "p1", 3).unwrap();
+        local_apply_with(&mut conn, &op, &registry).unwrap();
+        record_undo_group(&conn, device, &[op.id]).unwrap();
+
+        let inserts = seq_insert_text(&conn, &clock, "notes", "n1", 0, "ab").unwrap();
+        for op in &inserts {
+            local_apply_with(&mut conn, op, &registry).unwrap();
+        }
+        let delete = seq_delete_at(&conn, &clock, "notes", "n1", 0).unwrap();
+        local_apply_with(&mut conn, &delete, &registry).unwrap();
+        let group = record_undo_group(&conn, device, &[delete.id]).unwrap();
+        assert_eq!(sequence_text(&conn, "notes", "n1").unwrap(), "b");
+
+        // Undo the counter first, out of stack order
+        let counter_group: Uuid = conn
+            .query_row(
+                "SELECT group_id FROM undo_stack WHERE op_ids LIKE ?1",
+                params![format!("%{}%", op.id)],
+                |row| row.get::<_, String>(0),
+            )
+            .map(|g| Uuid::parse_str(&g).unwrap())
+            .unwrap();
+        undo_group(&mut conn, &clock, &registry, counter_group).unwrap();
+        assert_eq!(counter_value(&conn, "likes", "p1").unwrap(), 0);
+
+        undo_group(&mut conn, &clock, &registry, group).unwrap();
+        assert_eq!(sequence_text(&conn, "notes", "n1").unwrap(), "ab");
+        assert!(matches!(
+            undo_group(&mut conn, &clock, &registry, group),
+            Err(AhenkError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_undo_restores_replaced_rows() {
+        let (mut conn, clock, registry) =
+            setup(MergeRegistry::new().with_resolver("todos", LastWriteWins::new()));
+        let device = clock.device_id();
+
+        write(
+            &mut conn,
+            &clock,
+            &registry,
+            "create",
+            json!({"id": "t1", "title": "draft", "completed": true}),
+        );
+        // The update replaces the whole row, dropping `completed`
+        let rename = write(
+            &mut conn,
+            &clock,
+            &registry,
+            "update",
+            json!({"id": "t1", "title": "final"}),
+        );
+        record_undo_group(&conn, device, &[rename.id]).unwrap();
+        let completed: Option<bool> = conn
+            .query_row("SELECT completed FROM todos WHERE id = 't1'", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(completed, None);
+
+        let undone = undo(&mut conn, &clock, &registry).unwrap();
+        assert_eq!(undone.len(), 1);
+        assert_eq!(todo(&conn), Some(("draft".to_string(), true)));
+    }
+
+    #[test]
+    fn test_undo_refuses_ops_the_resolver_would_ignore() {
+        let (mut conn, clock, registry) =
+            setup(MergeRegistry::new().with_resolver("todos", DeleteWins::new()));
+        let device = clock.device_id();
+
+        write(
+            &mut conn,
+            &clock,
+            &registry,
+            "create",
+            json!({"id": "t1", "title": "draft", "completed": false}),
+        );
+        let rename = write(
+            &mut conn,
+            &clock,
+            &registry,
+            "update",
+            json!({"id": "t1", "title": "final"}),
+        );
+        record_undo_group(&conn, device, &[rename.id]).unwrap();
+        undo(&mut conn, &clock, &registry).unwrap();
+        assert_eq!(todo(&conn), Some(("draft".to_string(), false)));
+
+        // The compensating create would lose to the delete, so the group
+        // stays on the undo stack and nothing moves to the redo stack
+        let delete = write(&mut conn, &clock, &registry, "delete", json!({"id": "t1"}));
+        record_undo_group(&conn, device, &[delete.id]).unwrap();
+        assert!(matches!(
+            undo(&mut conn, &clock, &registry),
+            Err(AhenkError::Validation(_))
+        ));
+        assert!(can_undo(&conn, device).unwrap());
+        assert!(!can_redo(&conn, device).unwrap());
+        assert_eq!(todo(&conn), None);
+
+        // Keep-first tables ignore every later write
+        let (mut conn, clock, registry) =
+            setup(MergeRegistry::new().with_resolver("todos", KeepFirst::new()));
+        let create = write(
+            &mut conn,
+            &clock,
+            &registry,
+            "create",
+            json!({"id": "t1", "title": "draft", "completed": false}),
+        );
+        record_undo_group(&conn, clock.device_id(), &[create.id]).unwrap();
+        assert!(matches!(
+            undo(&mut conn, &clock, &registry),
+            Err(AhenkError::Validation(_))
+        ));
+        assert_eq!(todo(&conn), Some(("draft".to_string(), false)));
+    }
+}
@@ -0,0 +1,260 @@
+//! Schema-versioned operation payloads.
+//!
+//! Every [`OplogEntry`] records the version of the app's payload shape its
+//! `data` follows (`schema_version`, 1 unless set). When an app changes the
+//! shape of a table's entities, it registers an [`Upcaster`] from the old
+//! version to the next one on its [`MergeRegistry`]:
+//!
+//! ```rust
+//! use ahenk::MergeRegistry;
+//!
+//! // v1 stored "name"; v2 splits it into "first" and "last"
+//! let split_name = |mut data: serde_json::Value| {
+//!     let name = data["name"].take();
+//!     let name = name.as_str().unwrap_or_default();
+//!     let (first, last) = name.split_once(' ').unwrap_or((name, ""));
+//!     data["first"] = first.into();
+//!     data["last"] = last.into();
+//!     Ok(data)
+//! };
+//! let registry = MergeRegistry::new().with_upcaster("contacts", 1, split_name);
+//! assert_eq!(registry.schema_version("contacts"), 2);
+//! ```
+//!
+//! [`merge_with`](super::merge_with) and
+//! [`local_apply_with`](super::local_apply_with) upcast every operation to the
+//! newest version before recording it, so the oplog, resolvers and every
+//! materializer reading the oplog only see the newest shape. Operations from
+//! newer app versions are stored unchanged. [`upcast_oplog`] upgrades
+//! operations already in the oplog after an app update.
+
+use super::history::key_string;
+use super::resolver::MergeRegistry;
+use crate::error::{AhenkError, Result};
+use crate::models::OplogEntry;
+use rusqlite::{params, Connection};
+use std::sync::Arc;
+
+/// Upgrades a payload from one schema version to the next
+pub trait Upcaster: Send + Sync {
+    /// Convert `data` from version `n` to version `n + 1`
+    fn upcast(&self, data: serde_json::Value) -> Result<serde_json::Value>;
+}
+
+impl<F> Upcaster for F
+where
+    F: Fn(serde_json::Value) -> Result<serde_json::Value> + Send + Sync,
+{
+    fn upcast(&self, data: serde_json::Value) -> Result<serde_json::Value> {
+        self(data)
+    }
+}
+
+impl MergeRegistry {
+    /// Register the upcaster from `from_version` to `from_version + 1` for a
+    /// table, replacing any previous one
+    pub fn register_upcaster(
+        &mut self,
+        table: impl Into<String>,
+        from_version: u32,
+        upcaster: impl Upcaster + 'static,
+    ) {
+        self.upcasters
+            .entry(table.into())
+            .or_default()
+            .insert(from_version, Arc::new(upcaster));
+    }
+
+    /// Builder form of [`register_upcaster`](Self::register_upcaster)
+    pub fn with_upcaster(
+        mut self,
+        table: impl Into<String>,
+        from_version: u32,
+        upcaster: impl Upcaster + 'static,
+    ) -> Self {
+        self.register_upcaster(table, from_version, upcaster);
+        self
+    }
+
+    /// Newest payload schema version of a table (1 without upcasters)
+    pub fn schema_version(&self, table: &str) -> u32 {
+        self.upcasters
+            .get(table)
+            .and_then(|chain| chain.keys().next_back())
+            .map_or(1, |from| from + 1)
+    }
+
+    /// Bring an operation's payload up to the table's newest schema version.
+    ///
+    /// Fails with a validation error if an upcaster along the way is missing.
+    pub fn upcast(&self, op: &OplogEntry) -> Result<OplogEntry> {
+        let mut op = op.clone();
+        let Some(chain) = self.upcasters.get(&op.table) else {
+            return Ok(op);
+        };
+
+        let target = self.schema_version(&op.table);
+        while op.schema_version < target {
+            let upcaster = chain.get(&op.schema_version).ok_or_else(|| {
+                AhenkError::Validation(format!(
+                    "No upcaster for table '{}' from schema version {}",
+                    op.table, op.schema_version
+                ))
+            })?;
+            op.data = upcaster.upcast(op.data)?;
+            op.schema_version += 1;
+        }
+        Ok(op)
+    }
+}
+
+/// Upcast every operation in the oplog that is older than its table's newest
+/// schema version, returning how many were rewritten.
+///
+/// Run this once after registering new upcasters so materializers that read
+/// the oplog directly see the newest shape for old operations too.
+pub fn upcast_oplog(conn: &mut Connection, registry: &MergeRegistry) -> Result<usize> {
+    let tx = conn.transaction()?;
+    let mut upgraded = 0;
+
+    for table in registry.upcasters.keys() {
+        let mut stmt = tx.prepare(
+            "SELECT id, device_id, timestamp, table_name, op_type, data, deps, schema_version
+             FROM oplog WHERE table_name = ?1 AND schema_version < ?2",
+        )?;
+        let stale = stmt
+            .query_map(
+                params![table, registry.schema_version(table)],
+                crate::db::operations::row_to_oplog_entry,
+            )?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut update = tx.prepare(
+            "UPDATE oplog SET data = ?1, schema_version = ?2, entity_id = ?3 WHERE id = ?4",
+        )?;
+        let key_field = registry.key_field(table);
+        for op in stale {
+            let op = registry.upcast(&op)?;
+            let data = serde_json::to_string(&op.data)
+                .map_err(|e| AhenkError::Serialization(e.to_string()))?;
+            let entity_id = op.data.get(key_field).and_then(key_string);
+            update.execute(params![
+                data,
+                op.schema_version,
+                entity_id,
+                op.id.to_string()
+            ])?;
+            upgraded += 1;
+        }
+    }
+
+    tx.commit()?;
+    Ok(upgraded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{merge_with, DeviceClock, HybridLogicalClock, LastWriteWins};
+    use crate::db::operations::{get_oplog_entries_since, initialize_database};
+    use serde_json::json;
+    use uuid::Uuid;
+
+    fn registry() -> MergeRegistry {
+        MergeRegistry::new()
+            .with_resolver("contacts", LastWriteWins::new())
+            // v1 -> v2: "name" renamed to "full_name"
+            .with_upcaster("contacts", 1, |mut data: serde_json::Value| {
+                data["full_name"] = data["name"].take();
+                data.as_object_mut().unwrap().remove("name");
+                Ok(data)
+            })
+            // v2 -> v3: new "starred" field
+            .with_upcaster("contacts", 2, |mut data: serde_json::Value| {
+                data["starred"] = json!(false);
+                Ok(data)
+            })
+    }
+
+    fn op(schema_version: u32, data: serde_json::Value) -> OplogEntry {
+        OplogEntry {
+            id: Uuid::new_v4(),
+            device_id: Uuid::new_v4(),
+            timestamp: HybridLogicalClock::now().to_timestamp(),
+            table: "contacts".to_string(),
+            op_type: "create".to_string(),
+            data,
+            deps: Vec::new(),
+            schema_version,
+        }
+    }
+
+    #[test]
+    fn test_merge_applies_newest_payload_shape() {
+        let mut conn = initialize_database(":memory:").unwrap();
+        conn.execute(
+            "CREATE TABLE contacts (id TEXT PRIMARY KEY, full_name TEXT, starred INTEGER)",
+            [],
+        )
+        .unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+        let registry = registry();
+        assert_eq!(registry.schema_version("contacts"), 3);
+        assert_eq!(registry.schema_version("notes"), 1);
+
+        let old = op(1, json!({"id": "c1", "name": "Ada Lovelace"}));
+        let current = op(
+            3,
+            json!({"id": "c2", "full_name": "Alan Turing", "starred": true}),
+        );
+        merge_with(&mut conn, &clock, &[old, current], &registry).unwrap();
+
+        let rows: Vec<(String, String, bool)> = conn
+            .prepare("SELECT id, full_name, starred FROM contacts ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("c1".to_string(), "Ada Lovelace".to_string(), false),
+                ("c2".to_string(), "Alan Turing".to_string(), true),
+            ]
+        );
+
+        for entry in get_oplog_entries_since(&conn, 0).unwrap() {
+            assert_eq!(entry.schema_version, 3);
+            assert!(entry.data.get("name").is_none());
+        }
+    }
+
+    #[test]
+    fn test_upcast_oplog_rewrites_stored_ops() {
+        let mut conn = initialize_database(":memory:").unwrap();
+        let stored = op(1, json!({"id": "c1", "name": "Grace Hopper"}));
+        crate::crdt::local_apply(&mut conn, &stored).unwrap();
+
+        let registry = registry();
+        assert_eq!(upcast_oplog(&mut conn, &registry).unwrap(), 1);
+        assert_eq!(upcast_oplog(&mut conn, &registry).unwrap(), 0);
+
+        let entry = get_oplog_entries_since(&conn, 0).unwrap().remove(0);
+        assert_eq!(entry.schema_version, 3);
+        assert_eq!(
+            entry.data,
+            json!({"id": "c1", "full_name": "Grace Hopper", "starred": false})
+        );
+    }
+
+    #[test]
+    fn test_missing_upcaster_is_rejected() {
+        let registry =
+            MergeRegistry::new().with_upcaster("contacts", 2, |data: serde_json::Value| Ok(data));
+        assert!(matches!(
+            registry.upcast(&op(1, json!({"id": "c1"}))),
+            Err(AhenkError::Validation(_))
+        ));
+    }
+}
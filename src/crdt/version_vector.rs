@@ -0,0 +1,153 @@
+//! Per-device version vectors for delta sync.
+//!
+//! A [`VersionVector`] maps each `device_id` to the highest HLC timestamp seen
+//! from that device. Every device stamps its own operations with a strictly
+//! increasing clock, so two replicas can compare vectors and send exactly
+//! the operations the other side is missing, even when a device's clock lags
+//! far behind its peers.
+//!
+//! This relies on each replica holding a prefix of every device's
+//! operations. Local operations depend on their device's previous operation
+//! (see [`causal`](super::causal)), so an operation whose predecessor has not
+//! arrived waits in the causal buffer and does not advance the vector.
+
+use crate::models::OplogEntry;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// Highest HLC timestamp seen from each device
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct VersionVector {
+    entries: BTreeMap<Uuid, i64>,
+}
+
+impl VersionVector {
+    /// Create an empty version vector
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Highest timestamp seen from `device_id` (0 if none)
+    pub fn get(&self, device_id: Uuid) -> i64 {
+        self.entries.get(&device_id).copied().unwrap_or(0)
+    }
+
+    /// Record a timestamp seen from `device_id`
+    pub fn observe(&mut self, device_id: Uuid, timestamp: i64) {
+        let entry = self.entries.entry(device_id).or_insert(timestamp);
+        *entry = (*entry).max(timestamp);
+    }
+
+    /// Whether an operation is already covered by this vector, i.e. falls in
+    /// the prefix of its device's operations the vector has seen
+    pub fn contains(&self, op: &OplogEntry) -> bool {
+        op.timestamp <= self.get(op.device_id)
+    }
+
+    /// Pointwise maximum with another vector
+    pub fn merge(&mut self, other: &VersionVector) {
+        for (&device_id, &timestamp) in &other.entries {
+            self.observe(device_id, timestamp);
+        }
+    }
+
+    /// Whether this vector has seen everything `other` has
+    pub fn dominates(&self, other: &VersionVector) -> bool {
+        other
+            .entries
+            .iter()
+            .all(|(&device_id, &timestamp)| self.get(device_id) >= timestamp)
+    }
+
+    /// Devices and their highest timestamps, ordered by device ID
+    pub fn iter(&self) -> impl Iterator<Item = (Uuid, i64)> + '_ {
+        self.entries.iter().map(|(&d, &t)| (d, t))
+    }
+
+    /// Number of devices in the vector
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether no device has been seen
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl FromIterator<(Uuid, i64)> for VersionVector {
+    fn from_iter<I: IntoIterator<Item = (Uuid, i64)>>(iter: I) -> Self {
+        let mut vv = Self::new();
+        for (device_id, timestamp) in iter {
+            vv.observe(device_id, timestamp);
+        }
+        vv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_and_dominates() {
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+
+        let mut left: VersionVector = [(a, 10), (b, 3)].into_iter().collect();
+        let right: VersionVector = [(a, 7), (b, 5)].into_iter().collect();
+        assert!(!left.dominates(&right));
+        assert!(!right.dominates(&left));
+
+        left.merge(&right);
+        assert_eq!(left.get(a), 10);
+        assert_eq!(left.get(b), 5);
+        assert!(left.dominates(&right));
+        assert_eq!(left.get(Uuid::new_v4()), 0);
+    }
+
+    #[test]
+    fn test_lost_operation_is_not_skipped_by_delta_sync() {
+        use crate::crdt::{local_apply, merge, DeviceClock};
+        use crate::db::operations::{
+            get_oplog_entries_missing, get_version_vector, initialize_database,
+        };
+        use crate::logic::build_oplog_entry;
+
+        let mut origin = initialize_database(":memory:").unwrap();
+        let clock = DeviceClock::new(Uuid::new_v4());
+        let mut ops = Vec::new();
+        for n in 0..3 {
+            let op = build_oplog_entry(
+                &origin,
+                &clock,
+                "notes",
+                "create",
+                &serde_json::json!({"id": n}),
+            )
+            .unwrap();
+            local_apply(&mut origin, &op).unwrap();
+            ops.push(op);
+        }
+
+        // The second op is lost on the way; the third must not hide it
+        let mut replica = initialize_database(":memory:").unwrap();
+        let replica_clock = DeviceClock::new(Uuid::new_v4());
+        merge(
+            &mut replica,
+            &replica_clock,
+            &[ops[0].clone(), ops[2].clone()],
+        )
+        .unwrap();
+        let vv = get_version_vector(&replica).unwrap();
+        assert_eq!(vv.get(clock.device_id()), ops[0].timestamp);
+
+        let missing: Vec<Uuid> = get_oplog_entries_missing(&origin, &vv)
+            .unwrap()
+            .iter()
+            .map(|op| op.id)
+            .collect();
+        assert_eq!(missing, vec![ops[1].id, ops[2].id]);
+    }
+}
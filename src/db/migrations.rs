@@ -10,11 +10,108 @@ pub struct Migration {
 
 /// List of all migrations in order
 /// Each migration should be numbered sequentially starting from 1
-const MIGRATIONS: &[Migration] = &[Migration {
-    version: 1,
-    description: "Initial schema - database synchronization infrastructure",
-    sql: include_str!("migrations/001_initial_schema.sql"),
-}];
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Initial schema - database synchronization infrastructure",
+        sql: include_str!("migrations/001_initial_schema.sql"),
+    },
+    Migration {
+        version: 2,
+        description: "Re-encode oplog HLC timestamps as 48-bit milliseconds",
+        sql: include_str!("migrations/002_hlc_millisecond_encoding.sql"),
+    },
+    Migration {
+        version: 3,
+        description: "Persistent per-device HLC state",
+        sql: include_str!("migrations/003_device_clock.sql"),
+    },
+    Migration {
+        version: 4,
+        description: "Quarantine table for rejected remote operations",
+        sql: include_str!("migrations/004_oplog_quarantine.sql"),
+    },
+    Migration {
+        version: 5,
+        description: "Per-field clocks for field-level document merge",
+        sql: include_str!("migrations/005_document_fields.sql"),
+    },
+    Migration {
+        version: 6,
+        description: "Peer acknowledgement watermarks for oplog compaction",
+        sql: include_str!("migrations/006_peer_acks.sql"),
+    },
+    Migration {
+        version: 7,
+        description: "Per-device version vector for delta sync",
+        sql: include_str!("migrations/007_version_vector.sql"),
+    },
+    Migration {
+        version: 8,
+        description: "Causal dependencies and delivery buffer",
+        sql: include_str!("migrations/008_causal_deps.sql"),
+    },
+    Migration {
+        version: 9,
+        description: "Payload schema versions for upcasting",
+        sql: include_str!("migrations/009_schema_version.sql"),
+    },
+    Migration {
+        version: 10,
+        description: "Snapshot checkpoints for bootstrapping devices",
+        sql: include_str!("migrations/010_snapshots.sql"),
+    },
+    Migration {
+        version: 11,
+        description: "Per-device undo and redo stacks",
+        sql: include_str!("migrations/011_undo_stack.sql"),
+    },
+    Migration {
+        version: 12,
+        description: "Conflict log for concurrent writes",
+        sql: include_str!("migrations/012_conflicts.sql"),
+    },
+    Migration {
+        version: 13,
+        description: "Device tie-breaker for field clocks",
+        sql: include_str!("migrations/013_document_order_device.sql"),
+    },
+    Migration {
+        version: 14,
+        description: "Trigger-based change capture",
+        sql: include_str!("migrations/014_change_capture.sql"),
+    },
+    Migration {
+        version: 15,
+        description: "Operation groups for tracked transactions",
+        sql: include_str!("migrations/015_oplog_groups.sql"),
+    },
+    Migration {
+        version: 16,
+        description: "Oplog indexes for paginated reads",
+        sql: include_str!("migrations/016_oplog_indexes.sql"),
+    },
+    Migration {
+        version: 17,
+        description: "Tombstones of compacted deletes",
+        sql: include_str!("migrations/017_oplog_tombstones.sql"),
+    },
+    Migration {
+        version: 18,
+        description: "Latest operations on tracked entities",
+        sql: include_str!("migrations/018_oplog_heads.sql"),
+    },
+    Migration {
+        version: 19,
+        description: "Entity index on the oplog",
+        sql: include_str!("migrations/019_oplog_entity.sql"),
+    },
+    Migration {
+        version: 20,
+        description: "Ordered tombstones and two-way peer acknowledgements",
+        sql: include_str!("migrations/020_compaction_stability.sql"),
+    },
+];
 
 /// Initialize the schema_version table if it doesn't exist
 fn ensure_schema_version_table(conn: &Connection) -> Result<()> {
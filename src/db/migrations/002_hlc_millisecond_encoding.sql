@@ -0,0 +1,23 @@
+-- Migration 002: HLC Millisecond Encoding
+-- Description: Rewrites oplog timestamps from the legacy HLC encoding
+-- (microseconds << 16, which overflowed 64 bits and lost the top bits of
+-- the physical time) to 48 bits of milliseconds + 16 bits counter.
+-- Applied: Initial HLC redesign
+
+-- The legacy value only kept the low 48 bits of the microsecond timestamp.
+-- Those bits repeat every 2^48 microseconds (~8.9 years), so the most recent
+-- window that does not lie in the future is assumed to be the original one.
+UPDATE oplog
+SET timestamp = (
+    (
+        (
+            ((timestamp >> 16) & 281474976710655)
+            + (
+                (CAST(strftime('%s', 'now') AS INTEGER) * 1000000
+                    - ((timestamp >> 16) & 281474976710655))
+                / 281474976710656
+            ) * 281474976710656
+        ) / 1000
+    ) << 16
+) | (timestamp & 65535);
+
@@ -0,0 +1,15 @@
+-- Migration 003: Device Clock
+-- Description: Persists the last HLC issued or observed by each local device
+-- so timestamps stay strictly increasing across restarts and wall-clock
+-- rollbacks.
+-- Applied: Persistent clock service
+
+CREATE TABLE IF NOT EXISTS device_clock (
+    device_id TEXT PRIMARY KEY,       -- Device that owns this clock
+    timestamp INTEGER NOT NULL        -- Last HLC issued or observed (raw encoding)
+);
+
+-- Resume every device's clock from the operations it already wrote, so a
+-- wall clock set back after the upgrade cannot issue older timestamps
+INSERT OR IGNORE INTO device_clock (device_id, timestamp)
+SELECT device_id, MAX(timestamp) FROM oplog GROUP BY device_id;
@@ -0,0 +1,15 @@
+-- Migration 004: Oplog Quarantine
+-- Description: Holds remote operations rejected by merge (e.g. timestamps too
+-- far ahead of local time) until an operator releases or discards them.
+-- Applied: Clock drift protection
+
+CREATE TABLE IF NOT EXISTS oplog_quarantine (
+    id TEXT PRIMARY KEY,              -- UUID of the quarantined operation
+    device_id TEXT NOT NULL,          -- Device that created this operation
+    timestamp INTEGER NOT NULL,       -- HLC timestamp (64-bit)
+    table_name TEXT NOT NULL,         -- Table this operation affects
+    op_type TEXT NOT NULL,            -- Operation type
+    data TEXT NOT NULL,               -- JSON-encoded operation data
+    reason TEXT NOT NULL,             -- Why the operation was quarantined
+    quarantined_at TEXT NOT NULL      -- RFC3339 time the operation was quarantined
+);
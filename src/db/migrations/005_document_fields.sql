@@ -0,0 +1,22 @@
+-- Migration 005: Field-Level Document State
+-- Description: Per-field HLC clocks and entity tombstones used by the
+-- field-level last-write-wins materializer.
+-- Applied: Field-level merge
+
+CREATE TABLE IF NOT EXISTS document_fields (
+    table_name TEXT NOT NULL,         -- App table the entity belongs to
+    entity_id TEXT NOT NULL,          -- Primary-key value of the entity
+    field TEXT NOT NULL,              -- Field (column) name
+    value TEXT NOT NULL,              -- JSON-encoded field value
+    timestamp INTEGER NOT NULL,       -- HLC timestamp of the winning write
+    op_id TEXT NOT NULL,              -- Operation that wrote the value (tie-breaker)
+    PRIMARY KEY (table_name, entity_id, field)
+);
+
+CREATE TABLE IF NOT EXISTS document_tombstones (
+    table_name TEXT NOT NULL,         -- App table the entity belongs to
+    entity_id TEXT NOT NULL,          -- Primary-key value of the entity
+    timestamp INTEGER NOT NULL,       -- HLC timestamp of the latest delete
+    op_id TEXT NOT NULL,              -- Delete operation (tie-breaker)
+    PRIMARY KEY (table_name, entity_id)
+);
@@ -0,0 +1,11 @@
+-- Migration 006: Peer Acknowledgements
+-- Description: Highest HLC of the local oplog each peer device has
+-- acknowledged receiving. The minimum across known peers is the causally
+-- stable point below which the oplog can be compacted.
+-- Applied: Oplog compaction
+
+CREATE TABLE IF NOT EXISTS peer_acks (
+    device_id TEXT PRIMARY KEY,       -- Peer device that sent the acknowledgement
+    timestamp INTEGER NOT NULL,       -- Acknowledged HLC watermark (64-bit)
+    acked_at TEXT NOT NULL            -- RFC3339 time the watermark last advanced
+);
@@ -0,0 +1,21 @@
+-- Migration 007: Version Vector
+-- Description: Highest HLC timestamp seen from each device. Maintained by a
+-- trigger on oplog inserts so it survives oplog compaction, and exchanged
+-- between peers to send exactly the operations the other side is missing.
+-- Applied: Delta sync
+
+CREATE TABLE IF NOT EXISTS version_vector (
+    device_id TEXT PRIMARY KEY,       -- Device that created the operations
+    timestamp INTEGER NOT NULL        -- Highest HLC timestamp seen from it
+);
+
+INSERT OR REPLACE INTO version_vector (device_id, timestamp)
+SELECT device_id, MAX(timestamp) FROM oplog GROUP BY device_id;
+
+CREATE TRIGGER IF NOT EXISTS oplog_version_vector AFTER INSERT ON oplog
+BEGIN
+    INSERT OR IGNORE INTO version_vector (device_id, timestamp)
+    VALUES (NEW.device_id, NEW.timestamp);
+    UPDATE version_vector SET timestamp = NEW.timestamp
+    WHERE device_id = NEW.device_id AND timestamp < NEW.timestamp;
+END;
@@ -0,0 +1,23 @@
+-- Migration 008: Causal Dependencies
+-- Description: Operations may name the operations they depend on. Remote
+-- operations whose dependencies have not arrived wait in oplog_pending, and
+-- compaction remembers the IDs it removes so they still count as delivered.
+-- Applied: Causal delivery
+
+ALTER TABLE oplog ADD COLUMN deps TEXT;             -- JSON array of operation IDs (NULL: none)
+ALTER TABLE oplog_quarantine ADD COLUMN deps TEXT;  -- JSON array of operation IDs (NULL: none)
+
+CREATE TABLE IF NOT EXISTS oplog_pending (
+    id TEXT PRIMARY KEY,              -- UUID of the buffered operation
+    device_id TEXT NOT NULL,          -- Device that created this operation
+    timestamp INTEGER NOT NULL,       -- HLC timestamp (64-bit)
+    table_name TEXT NOT NULL,         -- Table this operation affects
+    op_type TEXT NOT NULL,            -- Operation type
+    data TEXT NOT NULL,               -- JSON-encoded operation data
+    deps TEXT NOT NULL,               -- JSON array of operation IDs it waits for
+    received_at TEXT NOT NULL         -- RFC3339 time the operation was buffered
+);
+
+CREATE TABLE IF NOT EXISTS oplog_compacted (
+    id TEXT PRIMARY KEY               -- UUID of an operation removed by compaction
+);
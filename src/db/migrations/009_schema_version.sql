@@ -0,0 +1,9 @@
+-- Migration 009: Payload Schema Versions
+-- Description: Records which version of the app's payload shape each
+-- operation's data follows, so registered upcasters can bring payloads from
+-- older app versions up to date. Existing operations are version 1.
+-- Applied: Schema-versioned payloads
+
+ALTER TABLE oplog ADD COLUMN schema_version INTEGER NOT NULL DEFAULT 1;            -- Payload schema version
+ALTER TABLE oplog_quarantine ADD COLUMN schema_version INTEGER NOT NULL DEFAULT 1; -- Payload schema version
+ALTER TABLE oplog_pending ADD COLUMN schema_version INTEGER NOT NULL DEFAULT 1;    -- Payload schema version
@@ -0,0 +1,12 @@
+-- Migration 010: Snapshot Checkpoints
+-- Description: Serialized materialized state tagged with the version vector
+-- it covers, used to bootstrap new devices without replaying the oplog.
+-- Only the newest checkpoint is kept.
+-- Applied: Snapshot checkpoints
+
+CREATE TABLE IF NOT EXISTS snapshots (
+    id TEXT PRIMARY KEY,              -- UUID of the snapshot
+    created_at TEXT NOT NULL,         -- RFC3339 time the snapshot was taken
+    version_vector TEXT NOT NULL,     -- JSON version vector the snapshot covers
+    data TEXT NOT NULL                -- JSON-encoded snapshot
+);
@@ -0,0 +1,16 @@
+-- Migration 011: Undo/Redo Stacks
+-- Description: Per-device undo and redo stacks of local operation groups.
+-- Undoing a group records compensating operations in the oplog and moves
+-- the group to the redo stack; redoing moves it back.
+-- Applied: Undo/redo stacks
+
+CREATE TABLE IF NOT EXISTS undo_stack (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,  -- Push order
+    device_id TEXT NOT NULL,                -- Device that owns the stack
+    stack TEXT NOT NULL CHECK (stack IN ('undo', 'redo')),
+    group_id TEXT NOT NULL,                 -- UUID of the operation group
+    op_ids TEXT NOT NULL,                   -- JSON array of oplog IDs, in applied order
+    created_at TEXT NOT NULL                -- RFC3339 time the group was pushed
+);
+
+CREATE INDEX IF NOT EXISTS idx_undo_stack_device ON undo_stack(device_id, stack, seq);
@@ -0,0 +1,19 @@
+-- Migration 012: Conflict Log
+-- Description: Concurrent writes to the same entity detected by merge, with
+-- both operations, until they are resolved by a new operation.
+-- Applied: Conflict log
+
+CREATE TABLE IF NOT EXISTS conflicts (
+    id TEXT PRIMARY KEY,              -- UUID of the conflict
+    table_name TEXT NOT NULL,         -- Table of the entity
+    entity_id TEXT NOT NULL,          -- Primary key of the entity
+    local_op_id TEXT NOT NULL,        -- Write already in the oplog
+    local_op TEXT NOT NULL,           -- JSON-encoded local operation
+    remote_op_id TEXT NOT NULL,       -- Concurrent write from a peer
+    remote_op TEXT NOT NULL,          -- JSON-encoded remote operation
+    detected_at TEXT NOT NULL,        -- RFC3339 time merge detected it
+    resolved_at TEXT,                 -- RFC3339 time it was resolved
+    resolution_op_id TEXT             -- Operation that resolved it
+);
+
+CREATE INDEX IF NOT EXISTS idx_conflicts_unresolved ON conflicts(resolved_at, detected_at);
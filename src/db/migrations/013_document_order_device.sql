@@ -0,0 +1,15 @@
+-- Migration 013: Device Tie-Breaker for Field Clocks
+-- Description: Records the writing device of each field value and tombstone,
+-- so field-level LWW breaks equal-HLC ties by (timestamp, device_id, op_id)
+-- like every other part of the oplog.
+-- Applied: Deterministic total order
+
+ALTER TABLE document_fields ADD COLUMN device_id TEXT NOT NULL DEFAULT '';     -- Device that wrote the value
+ALTER TABLE document_tombstones ADD COLUMN device_id TEXT NOT NULL DEFAULT ''; -- Device that deleted the entity
+
+-- Writes removed by compaction keep an empty device_id
+UPDATE document_fields
+SET device_id = COALESCE((SELECT device_id FROM oplog WHERE oplog.id = document_fields.op_id), '');
+
+UPDATE document_tombstones
+SET device_id = COALESCE((SELECT device_id FROM oplog WHERE oplog.id = document_tombstones.op_id), '');
@@ -0,0 +1,21 @@
+-- Migration 014: Change Capture
+-- Description: App tables whose writes are captured into the oplog by
+-- triggers, and the flag that keeps resolver writes from being captured.
+-- Applied: Automatic change capture
+
+CREATE TABLE IF NOT EXISTS tracked_tables (
+    table_name TEXT PRIMARY KEY,      -- App table with capture triggers
+    key_field TEXT NOT NULL,          -- Primary-key column of its rows
+    device_id TEXT NOT NULL,          -- Device captured operations belong to
+    registered_at TEXT NOT NULL       -- When the triggers were (re)installed
+);
+
+-- Single row; capture triggers are skipped while suppressed > 0. Only
+-- changed inside the transaction applying merged operations, so other
+-- connections always read 0.
+CREATE TABLE IF NOT EXISTS change_capture_state (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    suppressed INTEGER NOT NULL DEFAULT 0
+);
+
+INSERT OR IGNORE INTO change_capture_state (id, suppressed) VALUES (1, 0);
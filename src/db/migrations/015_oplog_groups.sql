@@ -0,0 +1,11 @@
+-- Migration 015: Operation Groups
+-- Description: Group ID shared by the operations recorded in one tracked
+-- transaction. Local bookkeeping; groups are not synced.
+-- Applied: Tracked transactions
+
+CREATE TABLE IF NOT EXISTS oplog_groups (
+    op_id TEXT PRIMARY KEY,           -- Operation recorded in the transaction
+    group_id TEXT NOT NULL            -- UUID shared by the transaction's operations
+);
+
+CREATE INDEX IF NOT EXISTS idx_oplog_groups_group ON oplog_groups(group_id);
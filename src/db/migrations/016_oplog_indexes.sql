@@ -0,0 +1,14 @@
+-- Migration 016: Oplog Indexes
+-- Description: Indexes for paginated oplog reads in total order and for
+-- per-device and per-table lookups.
+-- Applied: Streaming oplog reads
+
+-- Keyset pagination by (timestamp, device_id, id), the order every replica
+-- agrees on
+CREATE INDEX IF NOT EXISTS idx_oplog_order ON oplog(timestamp, device_id, id);
+
+-- Version-vector deltas and per-device scans
+CREATE INDEX IF NOT EXISTS idx_oplog_device ON oplog(device_id, timestamp);
+
+-- Per-table history, resolvers and compaction
+CREATE INDEX IF NOT EXISTS idx_oplog_table ON oplog(table_name, timestamp);
@@ -0,0 +1,11 @@
+-- Migration 017: Compacted Tombstones
+-- Description: Entities whose deletes were removed by compaction, so
+-- delete-wins resolution still sees them.
+-- Applied: Compaction keeps delete-wins semantics
+
+CREATE TABLE IF NOT EXISTS oplog_tombstones (
+    table_name TEXT NOT NULL,         -- Table of the deleted entity
+    entity_id TEXT NOT NULL,          -- Primary key of the entity, as text
+    op_id TEXT NOT NULL,              -- A compacted delete of the entity
+    PRIMARY KEY (table_name, entity_id)
+);
@@ -0,0 +1,30 @@
+-- Migration 018: Capture Heads
+-- Description: Latest row operation from each device on each entity of a
+-- tracked table. Maintained by a trigger on oplog inserts, so capture
+-- triggers find the dependencies of a write with one index lookup.
+-- Applied: Indexed change capture dependencies
+
+CREATE TABLE IF NOT EXISTS oplog_heads (
+    table_name TEXT NOT NULL,         -- Tracked table of the entity
+    entity_key TEXT NOT NULL,         -- Key field value of the entity, as text
+    device_id TEXT NOT NULL,          -- Device that wrote the operation
+    op_id TEXT NOT NULL,              -- Its latest row operation on the entity
+    timestamp INTEGER NOT NULL,       -- HLC timestamp of that operation
+    PRIMARY KEY (table_name, entity_key, device_id)
+);
+
+CREATE TRIGGER IF NOT EXISTS oplog_capture_heads AFTER INSERT ON oplog
+WHEN NEW.op_type IN ('create', 'update', 'delete')
+BEGIN
+    INSERT OR IGNORE INTO oplog_heads (table_name, entity_key, device_id, op_id, timestamp)
+    SELECT NEW.table_name, CAST(json_extract(NEW.data, '$.' || key_field) AS TEXT),
+           NEW.device_id, NEW.id, NEW.timestamp
+    FROM tracked_tables
+    WHERE table_name = NEW.table_name
+      AND json_extract(NEW.data, '$.' || key_field) IS NOT NULL;
+    UPDATE oplog_heads SET op_id = NEW.id, timestamp = NEW.timestamp
+    WHERE table_name = NEW.table_name AND device_id = NEW.device_id
+      AND entity_key = (SELECT CAST(json_extract(NEW.data, '$.' || key_field) AS TEXT)
+                        FROM tracked_tables WHERE table_name = NEW.table_name)
+      AND (timestamp, op_id) < (NEW.timestamp, NEW.id);
+END;
@@ -0,0 +1,15 @@
+-- Migration 019: Oplog Entity Index
+-- Description: Key field value of the entity each operation writes, filled
+-- at insert from the table's resolver key, so per-entity lookups during
+-- merges use an index instead of scanning every payload of the table.
+-- Applied: Indexed per-entity oplog lookups
+
+ALTER TABLE oplog ADD COLUMN entity_id TEXT;  -- Key field value of the entity, as text
+
+-- Existing operations are keyed by their tracked table's key field, or `id`
+UPDATE oplog SET entity_id = CAST(json_extract(data, '$.' || COALESCE(
+    (SELECT key_field FROM tracked_tables WHERE tracked_tables.table_name = oplog.table_name),
+    'id')) AS TEXT);
+
+CREATE INDEX IF NOT EXISTS idx_oplog_entity
+    ON oplog(table_name, entity_id, timestamp, device_id, id);
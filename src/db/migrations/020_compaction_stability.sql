@@ -0,0 +1,10 @@
+-- Migration 020: Compaction Stability
+-- Description: Compacted tombstones keep the HLC order of their delete, so
+-- last-write-wins resolution still ranks late writes against it, and peer
+-- acknowledgements keep the peer's own latest operation, so the stable point
+-- also covers how much of the peer's log the local replica holds.
+-- Applied: Compaction no longer resurrects deleted rows
+
+ALTER TABLE oplog_tombstones ADD COLUMN timestamp INTEGER NOT NULL DEFAULT 0;  -- HLC of the compacted delete
+ALTER TABLE oplog_tombstones ADD COLUMN device_id TEXT NOT NULL DEFAULT '';    -- Device that wrote it
+ALTER TABLE peer_acks ADD COLUMN written INTEGER NOT NULL DEFAULT 0;           -- Peer's latest own operation when it acknowledged (0: none)
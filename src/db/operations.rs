@@ -6,7 +6,8 @@
 //! - OplogEntry: Operation log for CRDT synchronization
 //! - Peer: P2P network peer management
 
-use crate::models::{Device, OplogEntry, Peer, User};
+use crate::crdt::VersionVector;
+use crate::models::{Device, OplogEntry, OrderKey, Peer, User};
 use chrono::{DateTime, Utc};
 use rusqlite::{params, types::Type, Connection, Result, Row};
 use uuid::Uuid;
@@ -34,12 +35,12 @@ where
     rusqlite::Error::FromSqlConversionFailure(column_index, Type::Text, Box::new(err))
 }
 
-fn parse_uuid_column(row: &Row, idx: usize) -> rusqlite::Result<Uuid> {
+pub(crate) fn parse_uuid_column(row: &Row, idx: usize) -> rusqlite::Result<Uuid> {
     let value: String = row.get(idx)?;
     Uuid::parse_str(&value).map_err(|e| conversion_failure(idx, e))
 }
 
-fn parse_datetime_column(row: &Row, idx: usize) -> rusqlite::Result<DateTime<Utc>> {
+pub(crate) fn parse_datetime_column(row: &Row, idx: usize) -> rusqlite::Result<DateTime<Utc>> {
     let value: String = row.get(idx)?;
     DateTime::parse_from_rfc3339(&value)
         .map(|dt| dt.with_timezone(&Utc))
@@ -84,9 +85,13 @@ fn row_to_device(row: &Row) -> rusqlite::Result<Device> {
     })
 }
 
-fn row_to_oplog_entry(row: &Row) -> rusqlite::Result<OplogEntry> {
+pub(crate) fn row_to_oplog_entry(row: &Row) -> rusqlite::Result<OplogEntry> {
     let data_raw: String = row.get(5)?;
     let data = serde_json::from_str(&data_raw).map_err(|e| conversion_failure(5, e))?;
+    let deps = match row.get::<_, Option<String>>(6)? {
+        Some(raw) => serde_json::from_str(&raw).map_err(|e| conversion_failure(6, e))?,
+        None => Vec::new(),
+    };
 
     Ok(OplogEntry {
         id: parse_uuid_column(row, 0)?,
@@ -95,6 +100,8 @@ fn row_to_oplog_entry(row: &Row) -> rusqlite::Result<OplogEntry> {
         table: row.get(3)?,
         op_type: row.get(4)?,
         data,
+        deps,
+        schema_version: row.get(7)?,
     })
 }
 
@@ -224,9 +231,14 @@ pub fn delete_device(conn: &Connection, device_id: Uuid) -> Result<usize> {
 /// Create a new operation log entry
 pub fn create_oplog_entry(conn: &Connection, entry: &OplogEntry) -> Result<()> {
     let data = serde_json::to_string(&entry.data).map_err(|e| conversion_failure(5, e))?;
+    let deps = deps_column(&entry.deps).map_err(|e| conversion_failure(6, e))?;
+    let entity_id = entry
+        .data
+        .get("id")
+        .and_then(crate::crdt::history::key_string);
 
     conn.execute(
-        "INSERT INTO oplog (id, device_id, timestamp, table_name, op_type, data) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        "INSERT INTO oplog (id, device_id, timestamp, table_name, op_type, data, deps, schema_version, entity_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
         params![
             &entry.id.to_string(),
             &entry.device_id.to_string(),
@@ -234,24 +246,239 @@ pub fn create_oplog_entry(conn: &Connection, entry: &OplogEntry) -> Result<()> {
             &entry.table,
             &entry.op_type,
             &data,
+            &deps,
+            entry.schema_version,
+            entity_id,
         ],
     )?;
     Ok(())
 }
 
-/// Get all oplog entries since a timestamp
+/// Encode operation dependencies for the `deps` column (`NULL` when empty)
+pub(crate) fn deps_column(deps: &[Uuid]) -> serde_json::Result<Option<String>> {
+    if deps.is_empty() {
+        return Ok(None);
+    }
+    serde_json::to_string(deps).map(Some)
+}
+
+/// Get all oplog entries since a timestamp.
+///
+/// Loads every matching entry at once; use [`iter_oplog_since`] for large
+/// oplogs.
 pub fn get_oplog_entries_since(conn: &Connection, since: i64) -> Result<Vec<OplogEntry>> {
-    let mut stmt = conn.prepare(
-        "SELECT id, device_id, timestamp, table_name, op_type, data FROM oplog WHERE timestamp > ?1 ORDER BY timestamp ASC",
-    )?;
-    let rows = stmt.query_map(params![since], row_to_oplog_entry)?;
+    iter_oplog_since(conn, since, OPLOG_PAGE_SIZE).collect()
+}
 
-    let mut entries = Vec::new();
+/// Get the version vector of the local oplog
+pub fn get_version_vector(conn: &Connection) -> Result<VersionVector> {
+    let mut stmt = conn.prepare("SELECT device_id, timestamp FROM version_vector")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((parse_uuid_column(row, 0)?, row.get::<_, i64>(1)?))
+    })?;
+
+    let mut vv = VersionVector::new();
     for row in rows {
-        entries.push(row?);
+        let (device_id, timestamp) = row?;
+        vv.observe(device_id, timestamp);
+    }
+
+    Ok(vv)
+}
+
+/// Get the oplog entries a peer with version vector `remote` is missing.
+///
+/// Loads every matching entry at once; use [`iter_oplog_missing`] or
+/// [`get_oplog_page_missing`] for large oplogs.
+pub fn get_oplog_entries_missing(
+    conn: &Connection,
+    remote: &VersionVector,
+) -> Result<Vec<OplogEntry>> {
+    iter_oplog_missing(conn, remote, OPLOG_PAGE_SIZE)?.collect()
+}
+
+// ============================================================================
+// Paginated Oplog Reads
+// ============================================================================
+
+/// Default number of entries per page for paginated oplog reads
+pub const OPLOG_PAGE_SIZE: usize = 500;
+
+/// A bounded page of oplog entries, in [`OrderKey`] order
+#[derive(Debug, Clone)]
+pub struct OplogPage {
+    /// Entries of the page
+    pub entries: Vec<OplogEntry>,
+    /// Cursor to read the next page from, or `None` if this is the last page
+    pub next: Option<OrderKey>,
+}
+
+/// Entries a paginated read selects
+#[derive(Debug, Clone)]
+enum Selection {
+    /// Entries with a timestamp above a bound
+    Since(i64),
+    /// Entries above a peer's version vector: the smallest timestamp any
+    /// device could be missing from, and the vector as a JSON object
+    Missing { floor: i64, remote: String },
+}
+
+impl Selection {
+    fn missing(conn: &Connection, remote: &VersionVector) -> Result<Option<Self>> {
+        let local = get_version_vector(conn)?;
+        let floor = local
+            .iter()
+            .filter(|&(device_id, timestamp)| timestamp > remote.get(device_id))
+            .map(|(device_id, _)| remote.get(device_id))
+            .min();
+        let remote: serde_json::Map<String, serde_json::Value> = remote
+            .iter()
+            .map(|(device_id, timestamp)| (device_id.to_string(), timestamp.into()))
+            .collect();
+        Ok(floor.map(|floor| Selection::Missing {
+            floor,
+            remote: serde_json::Value::Object(remote).to_string(),
+        }))
+    }
+
+    /// Up to `limit` entries after `after`, plus whether more follow
+    fn page(&self, conn: &Connection, after: Option<OrderKey>, limit: usize) -> Result<OplogPage> {
+        let (floor, remote) = match self {
+            Selection::Since(since) => (*since, None),
+            Selection::Missing { floor, remote } => (*floor, Some(remote.as_str())),
+        };
+        // Keyset pagination over (timestamp, device_id, id), which the
+        // idx_oplog_order index serves directly
+        let (timestamp, device_id, id) = after
+            .map_or((i64::MIN, String::new(), String::new()), |key| {
+                (key.timestamp, key.device_id.to_string(), key.id.to_string())
+            });
+        let mut stmt = conn.prepare_cached(
+            "SELECT id, device_id, timestamp, table_name, op_type, data, deps, schema_version
+             FROM oplog
+             WHERE timestamp > ?1 AND (timestamp, device_id, id) > (?2, ?3, ?4)
+               AND (?5 IS NULL
+                    OR timestamp > COALESCE(json_extract(?5, '$.\"' || device_id || '\"'), 0))
+             ORDER BY timestamp, device_id, id
+             LIMIT ?6",
+        )?;
+        // One extra row tells whether another page follows
+        let mut entries = stmt
+            .query_map(
+                params![floor, timestamp, device_id, id, remote, limit as i64 + 1],
+                row_to_oplog_entry,
+            )?
+            .collect::<Result<Vec<_>>>()?;
+
+        let more = entries.len() > limit;
+        entries.truncate(limit);
+        let next = more
+            .then(|| entries.last().map(OplogEntry::order_key))
+            .flatten();
+        Ok(OplogPage { entries, next })
     }
+}
+
+/// Read one page of oplog entries with a timestamp above `since`.
+///
+/// Pass `None` for the first page and the previous page's
+/// [`next`](OplogPage::next) cursor for the following ones.
+pub fn get_oplog_page_since(
+    conn: &Connection,
+    since: i64,
+    after: Option<OrderKey>,
+    limit: usize,
+) -> Result<OplogPage> {
+    Selection::Since(since).page(conn, after, limit)
+}
+
+/// Read one page of the oplog entries a peer with version vector `remote`
+/// is missing.
+///
+/// Every page holds, for each device, the oldest entries the peer lacks, so
+/// a peer that merges a page and asks again with its updated version vector
+/// receives exactly the rest.
+pub fn get_oplog_page_missing(
+    conn: &Connection,
+    remote: &VersionVector,
+    after: Option<OrderKey>,
+    limit: usize,
+) -> Result<OplogPage> {
+    match Selection::missing(conn, remote)? {
+        Some(selection) => selection.page(conn, after, limit),
+        None => Ok(OplogPage {
+            entries: Vec::new(),
+            next: None,
+        }),
+    }
+}
 
-    Ok(entries)
+/// Iterate over the oplog entries with a timestamp above `since`, in
+/// [`OrderKey`] order, reading `page_size` entries at a time
+pub fn iter_oplog_since(conn: &Connection, since: i64, page_size: usize) -> OplogIter<'_> {
+    OplogIter::new(conn, Some(Selection::Since(since)), page_size)
+}
+
+/// Iterate over the oplog entries a peer with version vector `remote` is
+/// missing, in [`OrderKey`] order, reading `page_size` entries at a time
+pub fn iter_oplog_missing<'conn>(
+    conn: &'conn Connection,
+    remote: &VersionVector,
+    page_size: usize,
+) -> Result<OplogIter<'conn>> {
+    Ok(OplogIter::new(
+        conn,
+        Selection::missing(conn, remote)?,
+        page_size,
+    ))
+}
+
+/// Iterator over oplog entries that holds at most one page in memory
+pub struct OplogIter<'conn> {
+    conn: &'conn Connection,
+    /// `None` once the last page has been read
+    selection: Option<Selection>,
+    page_size: usize,
+    cursor: Option<OrderKey>,
+    buffer: std::vec::IntoIter<OplogEntry>,
+}
+
+impl<'conn> OplogIter<'conn> {
+    fn new(conn: &'conn Connection, selection: Option<Selection>, page_size: usize) -> Self {
+        Self {
+            conn,
+            selection,
+            page_size: page_size.max(1),
+            cursor: None,
+            buffer: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl Iterator for OplogIter<'_> {
+    type Item = Result<OplogEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.buffer.next() {
+                return Some(Ok(entry));
+            }
+            let selection = self.selection.as_ref()?;
+            match selection.page(self.conn, self.cursor, self.page_size) {
+                Ok(page) => {
+                    if page.next.is_none() {
+                        self.selection = None;
+                    }
+                    self.cursor = page.next;
+                    self.buffer = page.entries.into_iter();
+                }
+                Err(e) => {
+                    self.selection = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
 }
 
 // ============================================================================
@@ -15,6 +15,22 @@ pub enum AhenkError {
     Serialization(String),
     /// P2P synchronization errors
     Sync(String),
+    /// Remote operation timestamp is further ahead of local time than allowed
+    ClockDrift {
+        /// Raw HLC timestamp of the offending operation
+        timestamp: i64,
+        /// How far the timestamp is ahead of local wall-clock time (ms)
+        ahead_ms: i64,
+        /// Configured maximum drift (ms)
+        max_drift_ms: i64,
+    },
+    /// Operation with a malformed table name or op type
+    InvalidOperation {
+        /// ID of the rejected operation
+        op_id: uuid::Uuid,
+        /// What is wrong with it
+        reason: String,
+    },
     /// I/O errors
     Io(std::io::Error),
     /// Generic errors with custom messages
@@ -30,6 +46,18 @@ impl fmt::Display for AhenkError {
             AhenkError::NotFound(msg) => write!(f, "Not found: {}", msg),
             AhenkError::Serialization(msg) => write!(f, "Serialization error: {}", msg),
             AhenkError::Sync(msg) => write!(f, "Synchronization error: {}", msg),
+            AhenkError::ClockDrift {
+                timestamp,
+                ahead_ms,
+                max_drift_ms,
+            } => write!(
+                f,
+                "Clock drift error: timestamp {} is {}ms ahead of local time (max {}ms)",
+                timestamp, ahead_ms, max_drift_ms
+            ),
+            AhenkError::InvalidOperation { op_id, reason } => {
+                write!(f, "Invalid operation {}: {}", op_id, reason)
+            }
             AhenkError::Io(e) => write!(f, "I/O error: {}", e),
             AhenkError::Other(msg) => write!(f, "{}", msg),
         }
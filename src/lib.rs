@@ -40,6 +40,7 @@
 //! - `logic`: Business logic (user management, device management, sync)
 //! - `crdt`: CRDT implementation with hybrid logical clocks
 //! - `auth`: Device authorization workflows
+//! - `testing`: Deterministic multi-replica simulation for convergence tests
 //! - `error`: Error types and result aliases
 
 // Internal modules
@@ -51,6 +52,7 @@ pub mod ffi;
 pub mod logic;
 pub mod models;
 pub mod tauri_api;
+pub mod testing;
 
 // CLI module (optional, enabled with "cli" feature)
 #[cfg(feature = "cli")]
@@ -66,7 +68,7 @@ pub use error::{AhenkError, Result};
 // Core Models
 // ============================================================================
 
-pub use models::{Device, OplogEntry, Peer, User};
+pub use models::{Device, OpType, OplogEntry, OrderKey, Peer, User};
 
 // ============================================================================
 // Database Operations
@@ -85,7 +87,11 @@ pub use db::operations::{
 };
 
 // OplogEntry operations
-pub use db::operations::{create_oplog_entry, get_oplog_entries_since};
+pub use db::operations::{
+    create_oplog_entry, get_oplog_entries_missing, get_oplog_entries_since, get_oplog_page_missing,
+    get_oplog_page_since, get_version_vector, iter_oplog_missing, iter_oplog_since, OplogIter,
+    OplogPage,
+};
 
 // Peer operations
 pub use db::operations::{create_peer, get_all_peers, get_peer, get_peers_by_user_id};
@@ -126,7 +132,15 @@ pub use auth::{
 // CRDT Operations
 // ============================================================================
 
-pub use crdt::{local_apply, merge, HybridLogicalClock};
+pub use crdt::{
+    checkpoint, compact_oplog, counter_value, create_snapshot, increment_op, list_conflicts,
+    local_apply, local_apply_with, merge, merge_patch_op, merge_with, patch_op, record_undo_group,
+    redo, register_capture_clock, resolve_conflict, restore_snapshot, set_add_op, set_members,
+    set_remove_op, track_table, undo, untrack_table, upcast_oplog, with_tracked_tx, Clock,
+    ConflictResolution, DeleteWins, DeviceClock, FieldLww, HybridLogicalClock, KeepFirst,
+    LastWriteWins, ManualClock, MergeRegistry, MergeResolver, Replay, Snapshot, SystemClock,
+    TrackedTx, Upcaster, VersionVector,
+};
 
 // ============================================================================
 // Tests
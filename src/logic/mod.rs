@@ -14,7 +14,11 @@
 pub mod sync;
 pub mod sync_manager;
 
-use crate::crdt;
+use crate::crdt::causal::device_head;
+use crate::crdt::conflicts::entity_heads;
+use crate::crdt::patch::{is_patch_op, validate_patch};
+use crate::crdt::resolver::is_row_op;
+use crate::crdt::DeviceClock;
 use crate::db::operations;
 use crate::models::{Device, OplogEntry, User};
 use argon2::password_hash::{rand_core::OsRng, SaltString};
@@ -25,8 +29,20 @@ use serde::Serialize;
 use uuid::Uuid;
 
 /// Helper function to build an oplog entry for CRDT synchronization
+///
+/// The entry is stamped with the next timestamp from the device's persistent
+/// clock, so entries built by one device are strictly ordered, and depends on
+/// the device's latest recorded operation, so peers record them in that
+/// order. Record each entry before building the next one. `create`,
+/// `update` and `delete` entries also depend on the latest write from every
+/// other device to the same entity (keyed by `"id"`), so merge can tell them
+/// apart from concurrent writes. `patch` and `merge-patch` entries get the same
+/// dependencies and are rejected if their patch document is malformed.
+/// Malformed table names and op types are rejected (see
+/// [`OplogEntry::validate`]).
 pub fn build_oplog_entry<T: Serialize>(
-    device_id: Uuid,
+    conn: &Connection,
+    clock: &DeviceClock,
     table: &str,
     op_type: &str,
     value: &T,
@@ -34,14 +50,40 @@ pub fn build_oplog_entry<T: Serialize>(
     let data = serde_json::to_value(value)
         .map_err(|e| format!("Failed to serialize {} payload: {}", table, e))?;
 
-    Ok(OplogEntry {
+    let mut entry = OplogEntry {
         id: Uuid::new_v4(),
-        device_id,
-        timestamp: crdt::HybridLogicalClock::now().to_timestamp(),
+        device_id: clock.device_id(),
+        timestamp: 0,
         table: table.to_string(),
         op_type: op_type.to_string(),
         data,
-    })
+        deps: Vec::new(),
+        schema_version: 1,
+    };
+    entry.validate().map_err(|e| e.to_string())?;
+    if is_patch_op(op_type) {
+        validate_patch(op_type, &entry.data)
+            .map_err(|e| format!("Invalid {} payload: {}", table, e))?;
+    }
+
+    entry.timestamp = clock
+        .tick(conn)
+        .map_err(|e| format!("Failed to advance device clock: {}", e))?
+        .to_timestamp();
+
+    let read_deps =
+        |e: crate::error::AhenkError| format!("Failed to read causal dependencies: {}", e);
+    entry.deps = device_head(conn, clock.device_id())
+        .map_err(read_deps)?
+        .into_iter()
+        .collect();
+    if is_row_op(op_type) || is_patch_op(op_type) {
+        let heads =
+            entity_heads(conn, table, "id", &entry.data, clock.device_id()).map_err(read_deps)?;
+        entry.deps.extend(heads);
+    }
+
+    Ok(entry)
 }
 
 // ============================================================================
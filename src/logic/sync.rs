@@ -1,6 +1,6 @@
-use crate::crdt;
+use crate::crdt::{self, DeviceClock, MergeRegistry, RangeSummary, VersionVector};
 use crate::db::operations;
-use crate::models::{OplogEntry, Peer};
+use crate::models::{OplogEntry, OrderKey, Peer};
 use chrono::Utc;
 use libp2p::gossipsub::{MessageAuthenticity, ValidationMode};
 use libp2p::{
@@ -54,15 +54,33 @@ impl Default for P2PConfig {
     }
 }
 
+/// Maximum number of entries in a `SyncData` reply to `RequestSync`
+pub const SYNC_PAGE_SIZE: usize = 100;
+
 /// Message types for P2P communication
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub enum SyncMessage {
-    /// Request oplog entries since a specific timestamp
-    RequestSync { user_id: Uuid, since_timestamp: i64 },
-    /// Response with oplog entries
+    /// Request the oplog entries missing from the sender's version vector.
+    ///
+    /// `to` is `None` for a request any peer may answer, and the responder
+    /// for a follow-up page, which resumes after the `after` cursor.
+    RequestSync {
+        user_id: Uuid,
+        to: Option<Uuid>,
+        after: Option<OrderKey>,
+        version_vector: VersionVector,
+    },
+    /// Response with oplog entries.
+    ///
+    /// Replies to `RequestSync` carry at most [`SYNC_PAGE_SIZE`] entries;
+    /// `next` is set when the sender has further entries for the requester,
+    /// which asks the sender for them from that cursor. `device_id` is the
+    /// sender, which the recipient acknowledges.
     SyncData {
         user_id: Uuid,
+        device_id: Uuid,
         entries: Vec<OplogEntry>,
+        next: Option<OrderKey>,
     },
     /// Announce presence with device info
     Announce {
@@ -70,34 +88,146 @@ pub enum SyncMessage {
         device_id: Uuid,
         peer_id: String,
     },
+    /// Acknowledge `SyncData` from device `to` with the sender's version
+    /// vector, from which `to` works out how much of its oplog the sender holds
+    Ack {
+        device_id: Uuid,
+        to: Uuid,
+        version_vector: VersionVector,
+    },
+    /// Anti-entropy range summaries, plus operations the recipient lacks.
+    ///
+    /// `to` is `None` for the broadcast that starts a reconciliation and the
+    /// initiating device for every reply. `floor` is the highest stable point
+    /// seen so far; operations at or below it are not reconciled.
+    Reconcile {
+        device_id: Uuid,
+        to: Option<Uuid>,
+        floor: i64,
+        ranges: Vec<RangeSummary>,
+        entries: Vec<OplogEntry>,
+    },
     /// Ping message for keepalive
     Ping { timestamp: i64 },
     /// Pong response to ping
     Pong { timestamp: i64 },
 }
 
+/// Handle an incoming sync message, returning a reply if one is needed.
+///
+/// `clock` is the local device's clock; merged remote operations advance it.
+/// Received entries are applied with the resolvers in `registry` and
+/// acknowledged with an [`SyncMessage::Ack`] so the sender can compact its
+/// oplog below the prefix the local device holds (see
+/// [`acknowledged_prefix`](crdt::acknowledged_prefix)).
+/// `RequestSync` is answered one page of entries at a time; a page with more
+/// to follow is answered with a `RequestSync` for the next page, addressed
+/// to its sender, instead of an `Ack`.
 pub fn handle_sync_message(
     conn: &mut Connection,
+    clock: &DeviceClock,
+    registry: &MergeRegistry,
     msg: SyncMessage,
 ) -> Result<Option<SyncMessage>, String> {
     match msg {
         SyncMessage::RequestSync {
             user_id,
-            since_timestamp,
+            to,
+            after,
+            version_vector,
         } => {
-            // Note: get_oplog_entries_since only takes (conn, since_timestamp)
-            // It returns all entries after the timestamp, regardless of user_id
-            let entries = operations::get_oplog_entries_since(conn, since_timestamp)
-                .map_err(|e| e.to_string())?;
-            Ok(Some(SyncMessage::SyncData { user_id, entries }))
+            if to.is_some_and(|to| to != clock.device_id()) {
+                return Ok(None);
+            }
+            // Note: entries are selected by device, regardless of user_id
+            let page =
+                operations::get_oplog_page_missing(conn, &version_vector, after, SYNC_PAGE_SIZE)
+                    .map_err(|e| e.to_string())?;
+            Ok(Some(SyncMessage::SyncData {
+                user_id,
+                device_id: clock.device_id(),
+                entries: page.entries,
+                next: page.next,
+            }))
         }
         SyncMessage::SyncData {
-            user_id: _,
+            user_id,
+            device_id,
             entries,
+            next,
+        } => {
+            if device_id == clock.device_id() {
+                return Ok(None);
+            }
+            crdt::merge_with(conn, clock, &entries, registry).map_err(|e| e.to_string())?;
+
+            // Ask the sender for the next page. The cursor moves past this
+            // page even if none of it was applied (e.g. it was quarantined)
+            if let Some(next) = next {
+                return Ok(Some(SyncMessage::RequestSync {
+                    user_id,
+                    to: Some(device_id),
+                    after: Some(next),
+                    version_vector: operations::get_version_vector(conn)
+                        .map_err(|e| e.to_string())?,
+                }));
+            }
+
+            // Quarantined and buffered entries are not in the oplog, so the
+            // version vector does not cover them
+            Ok(Some(SyncMessage::Ack {
+                device_id: clock.device_id(),
+                to: device_id,
+                version_vector: operations::get_version_vector(conn).map_err(|e| e.to_string())?,
+            }))
+        }
+        SyncMessage::Ack {
+            device_id,
+            to,
+            version_vector,
         } => {
-            crdt::merge(conn, &entries).map_err(|e| e.to_string())?;
+            if to != clock.device_id() {
+                return Ok(None);
+            }
+            if let Some(acked) =
+                crdt::acknowledged_prefix(conn, &version_vector).map_err(|e| e.to_string())?
+            {
+                crdt::record_peer_ack(conn, device_id, acked, version_vector.get(device_id))
+                    .map_err(|e| e.to_string())?;
+            }
             Ok(None)
         }
+        SyncMessage::Reconcile {
+            device_id,
+            to,
+            floor,
+            ranges,
+            entries,
+        } => {
+            let local_id = clock.device_id();
+            if device_id == local_id || to.is_some_and(|to| to != local_id) {
+                return Ok(None);
+            }
+
+            let floor = floor.max(local_floor(conn, local_id)?);
+            let entries: Vec<OplogEntry> = entries
+                .into_iter()
+                .filter(|e| e.timestamp > floor)
+                .collect();
+            crdt::merge_with(conn, clock, &entries, registry).map_err(|e| e.to_string())?;
+
+            let reply = crdt::reconcile(conn, floor, &ranges).map_err(|e| e.to_string())?;
+            if reply.is_empty() {
+                return Ok(None);
+            }
+            Ok(Some(SyncMessage::Reconcile {
+                device_id: local_id,
+                to: Some(device_id),
+                floor,
+                ranges: reply.ranges,
+                entries: reply.entries,
+            }))
+        }
         SyncMessage::Announce {
             user_id,
             device_id,
@@ -111,6 +241,30 @@ pub fn handle_sync_message(
     }
 }
 
+/// Build the broadcast that starts an anti-entropy round.
+///
+/// Every peer that receives it replies with the ranges where its oplog
+/// differs, and the exchange continues through [`handle_sync_message`] until
+/// both oplogs agree above the stable point.
+pub fn reconcile_request(conn: &Connection, clock: &DeviceClock) -> Result<SyncMessage, String> {
+    let floor = local_floor(conn, clock.device_id())?;
+    let root = crdt::root_range(conn, floor).map_err(|e| e.to_string())?;
+    Ok(SyncMessage::Reconcile {
+        device_id: clock.device_id(),
+        to: None,
+        floor,
+        ranges: vec![root],
+        entries: Vec::new(),
+    })
+}
+
+/// Local stable point, below which the oplog may have been compacted
+fn local_floor(conn: &Connection, device_id: Uuid) -> Result<i64, String> {
+    Ok(crdt::stable_watermark(conn, device_id)
+        .map_err(|e| e.to_string())?
+        .map_or(0, |hlc| hlc.to_timestamp()))
+}
+
 /// Generate a unique device ID and keypair for P2P communication
 pub fn generate_device_id() -> (PeerId, identity::Keypair) {
     let local_key = identity::Keypair::generate_ed25519();
@@ -304,9 +458,25 @@ pub fn encode_sync_message(message: &SyncMessage) -> Result<Vec<u8>, String> {
     serde_json::to_vec(message).map_err(|e| format!("Failed to encode message: {}", e))
 }
 
-/// Decode a sync message from bytes
+/// Decode a sync message from bytes.
+///
+/// Messages carrying an entry with a malformed table name or op type are
+/// rejected (see [`OplogEntry::validate`]).
 pub fn decode_sync_message(bytes: &[u8]) -> Result<SyncMessage, String> {
-    serde_json::from_slice(bytes).map_err(|e| format!("Failed to decode message: {}", e))
+    let message: SyncMessage =
+        serde_json::from_slice(bytes).map_err(|e| format!("Failed to decode message: {}", e))?;
+    let entries = match &message {
+        SyncMessage::SyncData { entries, .. } | SyncMessage::Reconcile { entries, .. } => {
+            entries.as_slice()
+        }
+        _ => &[],
+    };
+    for entry in entries {
+        entry
+            .validate()
+            .map_err(|e| format!("Rejected sync message: {}", e))?;
+    }
+    Ok(message)
 }
 
 #[cfg(test)]
@@ -342,6 +512,37 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_decode_rejects_invalid_entries() {
+        let entry = |table: &str, op_type: &str| OplogEntry {
+            id: Uuid::new_v4(),
+            device_id: Uuid::new_v4(),
+            timestamp: 1,
+            table: table.to_string(),
+            op_type: op_type.to_string(),
+            data: serde_json::json!({"id": "t1"}),
+            deps: Vec::new(),
+            schema_version: 1,
+        };
+        let decode = |entry: OplogEntry| {
+            decode_sync_message(
+                &encode_sync_message(&SyncMessage::SyncData {
+                    user_id: Uuid::new_v4(),
+                    device_id: Uuid::new_v4(),
+                    entries: vec![entry],
+                    next: None,
+                })
+                .unwrap(),
+            )
+        };
+
+        assert!(decode(entry("todos", "delete")).is_ok());
+        assert!(decode(entry("todos", "x-archive")).is_ok());
+        assert!(decode(entry("todos", "delte")).is_err());
+        assert!(decode(entry("todos; DROP TABLE users", "create")).is_err());
+        assert!(decode(entry("sqlite_master", "create")).is_err());
+    }
+
     #[test]
     fn test_parse_multiaddr_peer_id() {
         let addr =
@@ -1,15 +1,23 @@
+use crate::crdt::{
+    checkpoint, list_conflicts, resolve_conflict, Clock, Conflict, ConflictResolution, DeviceClock,
+    MergeRegistry, TableFilter,
+};
+use crate::db::operations::get_version_vector;
 use crate::logic::sync::{
     connect_to_bootstrap_nodes, connect_to_relay_servers, create_swarm, encode_sync_message,
-    AhenkBehaviour, AhenkBehaviourEvent, P2PConfig, SyncMessage,
+    handle_sync_message, reconcile_request, AhenkBehaviour, AhenkBehaviourEvent, P2PConfig,
+    SyncMessage,
 };
 use crate::models::OplogEntry;
 use chrono::{DateTime, Utc};
+use futures::channel::mpsc::UnboundedReceiver;
 use libp2p::swarm::SwarmEvent;
 use libp2p::PeerId;
 use libp2p::{gossipsub, identity, mdns, Swarm};
 use rusqlite::Connection;
 use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 #[cfg(feature = "tauri-api")]
 use tauri::AppHandle;
 use uuid::Uuid;
@@ -22,8 +30,12 @@ pub struct SyncManager {
     user_id: Uuid,
     /// Device ID for this device
     device_id: Uuid,
+    /// Persistent HLC for this device
+    clock: DeviceClock,
     /// Database connection (thread-safe)
-    _conn: Arc<Mutex<Connection>>,
+    conn: Arc<Mutex<Connection>>,
+    /// Resolvers applied to merged operations
+    registry: MergeRegistry,
     /// Gossipsub topic for sync messages
     topic: gossipsub::IdentTopic,
     /// Is the manager currently actively syncing/connected to peers
@@ -39,8 +51,19 @@ pub struct SyncManager {
     pending_changes: VecDeque<OplogEntry>,
     /// Is the device currently online
     is_online: bool,
+    /// How often `run` starts an anti-entropy round
+    anti_entropy_interval: Duration,
+    /// When the last anti-entropy round was started
+    last_anti_entropy: Instant,
+    /// How often `run` takes a snapshot checkpoint, if at all
+    checkpoint_interval: Option<Duration>,
+    /// When the last checkpoint was taken
+    last_checkpoint: Instant,
 }
 
+/// Default interval between anti-entropy rounds
+pub const DEFAULT_ANTI_ENTROPY_INTERVAL: Duration = Duration::from_secs(60);
+
 impl SyncManager {
     /// Create a new sync manager
     #[cfg(feature = "tauri-api")]
@@ -59,13 +82,19 @@ impl SyncManager {
             swarm,
             user_id,
             device_id,
-            _conn: conn,
+            clock: DeviceClock::new(device_id),
+            conn,
+            registry: MergeRegistry::default(),
             topic,
             is_syncing: false,
             last_sync_time: None,
             pending_changes: VecDeque::new(),
             is_online: true,
             connected_peers: Vec::new(),
+            anti_entropy_interval: DEFAULT_ANTI_ENTROPY_INTERVAL,
+            last_anti_entropy: Instant::now(),
+            checkpoint_interval: None,
+            last_checkpoint: Instant::now(),
             app_handle,
         })
     }
@@ -85,16 +114,56 @@ impl SyncManager {
             swarm,
             user_id,
             device_id,
-            _conn: conn,
+            clock: DeviceClock::new(device_id),
+            conn,
+            registry: MergeRegistry::default(),
             topic,
             is_syncing: false,
             last_sync_time: None,
             pending_changes: VecDeque::new(),
             is_online: true,
             connected_peers: Vec::new(),
+            anti_entropy_interval: DEFAULT_ANTI_ENTROPY_INTERVAL,
+            last_anti_entropy: Instant::now(),
+            checkpoint_interval: None,
+            last_checkpoint: Instant::now(),
         })
     }
 
+    /// Read wall-clock time from `source` instead of the system clock
+    pub fn with_clock_source(mut self, source: Arc<dyn Clock>) -> Self {
+        let clock = DeviceClock::with_clock(self.device_id, source);
+        self.clock = match self.clock.max_drift() {
+            Some(max_drift) => clock.with_max_drift(max_drift),
+            None => clock,
+        };
+        self
+    }
+
+    /// Quarantine received operations more than `max_drift` ahead of local time
+    pub fn with_max_drift(mut self, max_drift: chrono::Duration) -> Self {
+        self.clock = self.clock.with_max_drift(max_drift);
+        self
+    }
+
+    /// Apply merged operations with the resolvers in `registry`
+    pub fn with_merge_registry(mut self, registry: MergeRegistry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Start an anti-entropy round every `interval` while running
+    pub fn with_anti_entropy_interval(mut self, interval: Duration) -> Self {
+        self.anti_entropy_interval = interval;
+        self
+    }
+
+    /// Take a snapshot checkpoint every `interval` while running
+    pub fn with_checkpoint_interval(mut self, interval: Duration) -> Self {
+        self.checkpoint_interval = Some(interval);
+        self
+    }
+
     /// Start listening on all network interfaces
     pub fn listen(&mut self, port: u16) -> Result<(), Box<dyn std::error::Error>> {
         let listen_addr = format!("/ip4/0.0.0.0/tcp/{}", port);
@@ -128,38 +197,113 @@ impl SyncManager {
             peer_id: peer_id.to_string(),
         };
 
-        let encoded = encode_sync_message(&message).map_err(std::io::Error::other)?;
-
-        self.swarm
-            .behaviour_mut()
-            .gossipsub
-            .publish(self.topic.clone(), encoded)
-            .map_err(|e| std::io::Error::other(format!("Failed to publish: {:?}", e)))?;
-
-        Ok(())
+        self.publish(&message)
     }
 
-    /// Request sync from peers
-    pub fn request_sync(
-        &mut self,
-        since_timestamp: chrono::DateTime<chrono::Utc>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    /// Request the operations this device is missing from peers.
+    ///
+    /// Sends the local version vector; peers reply with exactly the
+    /// operations it does not cover.
+    pub fn request_sync(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let version_vector = {
+            let conn = self
+                .conn
+                .lock()
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+            get_version_vector(&conn)?
+        };
+
         let message = SyncMessage::RequestSync {
             user_id: self.user_id,
-            since_timestamp: since_timestamp.timestamp(),
+            to: None,
+            after: None,
+            version_vector,
         };
 
-        let encoded = encode_sync_message(&message).map_err(std::io::Error::other)?;
+        self.publish(&message)
+    }
 
-        self.swarm
-            .behaviour_mut()
-            .gossipsub
-            .publish(self.topic.clone(), encoded)
-            .map_err(|e| std::io::Error::other(format!("Failed to publish: {:?}", e)))?;
+    /// Start an anti-entropy round with connected peers.
+    ///
+    /// Peers compare oplog range fingerprints and exchange only the
+    /// operations in ranges that differ, repairing gaps left by lost
+    /// `SyncData` messages. `run` calls this periodically.
+    pub fn run_anti_entropy(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.last_anti_entropy = Instant::now();
+        if self.connected_peers.is_empty() {
+            return Ok(());
+        }
+
+        let message = {
+            let conn = self
+                .conn
+                .lock()
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+            reconcile_request(&conn, &self.clock).map_err(std::io::Error::other)?
+        };
+
+        self.publish(&message)
+    }
 
+    /// Store a snapshot checkpoint of the local state.
+    ///
+    /// New devices can bootstrap from it with
+    /// [`restore_snapshot`](crate::crdt::restore_snapshot) instead of
+    /// replaying the whole oplog. `run` calls this every checkpoint interval.
+    pub fn run_checkpoint(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.last_checkpoint = Instant::now();
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        checkpoint(&conn, &self.registry)?;
         Ok(())
     }
 
+    /// Stream of operations merged or resolved by this manager on tables
+    /// matching `filter`, one batch per commit
+    pub fn subscribe_changes(&self, filter: TableFilter) -> UnboundedReceiver<Vec<OplogEntry>> {
+        self.registry.subscribe_channel(filter)
+    }
+
+    /// List unresolved conflicts between concurrent writes
+    pub fn list_conflicts(&self) -> Result<Vec<Conflict>, Box<dyn std::error::Error>> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        Ok(list_conflicts(&conn)?)
+    }
+
+    /// Resolve a conflict and send the resolving operation to peers, or queue
+    /// it while offline
+    pub fn resolve_conflict(
+        &mut self,
+        conflict_id: Uuid,
+        resolution: ConflictResolution,
+    ) -> Result<OplogEntry, Box<dyn std::error::Error>> {
+        let op = {
+            let mut conn = self
+                .conn
+                .lock()
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+            resolve_conflict(
+                &mut conn,
+                &self.clock,
+                &self.registry,
+                conflict_id,
+                resolution,
+            )?
+        };
+
+        if self.is_online && !self.connected_peers.is_empty() {
+            self.send_sync_data(vec![op.clone()])?;
+        } else {
+            self.add_pending_change(op.clone());
+        }
+        Ok(op)
+    }
+
     /// Send sync data to peers
     pub fn send_sync_data(
         &mut self,
@@ -167,18 +311,12 @@ impl SyncManager {
     ) -> Result<(), Box<dyn std::error::Error>> {
         let message = SyncMessage::SyncData {
             user_id: self.user_id,
+            device_id: self.clock.device_id(),
             entries,
+            next: None,
         };
 
-        let encoded = encode_sync_message(&message).map_err(std::io::Error::other)?;
-
-        self.swarm
-            .behaviour_mut()
-            .gossipsub
-            .publish(self.topic.clone(), encoded)
-            .map_err(|e| std::io::Error::other(format!("Failed to publish: {:?}", e)))?;
-
-        Ok(())
+        self.publish(&message)
     }
 
     /// Get the current syncing status
@@ -343,23 +481,84 @@ impl SyncManager {
         Ok(())
     }
 
-    /// Handle a gossipsub message
+    /// Handle a gossipsub message, publishing the reply if there is one
     fn handle_gossipsub_message(
         &mut self,
         message: gossipsub::Message,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let sync_message = crate::logic::sync::decode_sync_message(&message.data)?;
-        if let SyncMessage::SyncData { .. } = sync_message {
-            self.last_sync_time = Some(Utc::now());
+        let is_sync_data = matches!(sync_message, SyncMessage::SyncData { .. });
+
+        let reply = {
+            let mut conn = self
+                .conn
+                .lock()
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+            handle_sync_message(&mut conn, &self.clock, &self.registry, sync_message)
+                .map_err(std::io::Error::other)?
+        };
+
+        if is_sync_data {
+            self.last_sync_time = Some(self.clock.wall_time());
             self.emit_sync_status();
         }
+        if let Some(reply) = reply {
+            self.publish(&reply)?;
+        }
         Ok(())
     }
 
-    /// Run the event loop indefinitely
+    /// Publish a sync message on the sync topic
+    fn publish(&mut self, message: &SyncMessage) -> Result<(), Box<dyn std::error::Error>> {
+        let encoded = encode_sync_message(message).map_err(std::io::Error::other)?;
+
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(self.topic.clone(), encoded)
+            .map_err(|e| std::io::Error::other(format!("Failed to publish: {:?}", e)))?;
+
+        Ok(())
+    }
+
+    /// Run the event loop indefinitely, starting an anti-entropy round
+    /// every anti-entropy interval and taking a checkpoint every checkpoint
+    /// interval
     pub async fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        use futures::future::{select, Either};
+
         loop {
-            self.process_event().await?;
+            let anti_entropy_wait = self
+                .anti_entropy_interval
+                .saturating_sub(self.last_anti_entropy.elapsed());
+            let checkpoint_wait = self
+                .checkpoint_interval
+                .map(|interval| interval.saturating_sub(self.last_checkpoint.elapsed()));
+
+            let due = {
+                let wait = checkpoint_wait.map_or(anti_entropy_wait, |w| w.min(anti_entropy_wait));
+                let timer = async_std::task::sleep(wait);
+                let event = self.process_event();
+                futures::pin_mut!(timer, event);
+                match select(event, timer).await {
+                    Either::Left((result, _)) => {
+                        result?;
+                        false
+                    }
+                    Either::Right(_) => true,
+                }
+            };
+
+            if due {
+                if self.last_anti_entropy.elapsed() >= self.anti_entropy_interval {
+                    self.run_anti_entropy()?;
+                }
+                if let Some(interval) = self.checkpoint_interval {
+                    if self.last_checkpoint.elapsed() >= interval {
+                        self.run_checkpoint()?;
+                    }
+                }
+            }
         }
     }
 }
@@ -367,10 +566,14 @@ impl SyncManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crdt::{list_quarantined, HybridLogicalClock, ManualClock};
+    use crate::db::operations::{get_oplog_entries_since, initialize_database};
     use crate::logic::sync::generate_device_id;
+    use chrono::TimeZone;
 
-    #[test]
-    fn test_sync_manager_creation() {
+    // Building the swarm needs a Tokio reactor for mDNS
+    #[tokio::test]
+    async fn test_sync_manager_creation() {
         use rusqlite::Connection;
 
         let conn = Connection::open_in_memory().unwrap();
@@ -383,4 +586,56 @@ mod tests {
         let manager = SyncManager::new(keypair, user_id, device_id, conn, config);
         assert!(manager.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_far_future_sync_data_is_quarantined() {
+        let start = Utc.with_ymd_and_hms(2024, 6, 1, 8, 0, 0).unwrap();
+        let conn = Arc::new(Mutex::new(initialize_database(":memory:").unwrap()));
+        let (_, keypair) = generate_device_id();
+        let user_id = Uuid::new_v4();
+
+        // The drift bound must survive swapping the clock source
+        let mut manager = SyncManager::new(
+            keypair,
+            user_id,
+            Uuid::new_v4(),
+            conn.clone(),
+            P2PConfig::default(),
+        )
+        .unwrap()
+        .with_max_drift(chrono::Duration::minutes(5))
+        .with_clock_source(Arc::new(ManualClock::new(start)));
+
+        let drifted = OplogEntry {
+            id: Uuid::new_v4(),
+            device_id: Uuid::new_v4(),
+            timestamp: HybridLogicalClock::new(start + chrono::Duration::days(1), 0).to_timestamp(),
+            table: "tasks".to_string(),
+            op_type: "update".to_string(),
+            data: serde_json::json!({"id": "t1", "title": "from the future"}),
+            deps: Vec::new(),
+            schema_version: 1,
+        };
+        let data = encode_sync_message(&SyncMessage::SyncData {
+            user_id,
+            device_id: drifted.device_id,
+            entries: vec![drifted.clone()],
+            next: None,
+        })
+        .unwrap();
+
+        // Nobody is subscribed, so publishing the ack fails after the merge
+        let _ = manager.handle_gossipsub_message(gossipsub::Message {
+            source: None,
+            data,
+            sequence_number: None,
+            topic: manager.topic.hash(),
+        });
+
+        let conn = conn.lock().unwrap();
+        assert!(get_oplog_entries_since(&conn, 0).unwrap().is_empty());
+        let quarantined = list_quarantined(&conn).unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].entry.id, drifted.id);
+    }
 }
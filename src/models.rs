@@ -2,11 +2,14 @@
 //!
 //! This module contains the essential types for P2P database synchronization:
 //! - User and Device models for authentication and device management
-//! - OplogEntry for CRDT-based operation logging
+//! - OplogEntry and OpType for CRDT-based operation logging
 //! - Peer for P2P network peer tracking
 
+use crate::error::{AhenkError, Result};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
 use uuid::Uuid;
 
 /// User account for device ownership and authentication
@@ -43,10 +46,241 @@ pub struct OplogEntry {
     pub timestamp: i64,
     /// Table name (e.g., "users", "app_data")
     pub table: String,
-    /// Operation type (e.g., "create", "update", "delete")
+    /// Operation type (e.g., "create", "update", "delete"), see [`OpType`]
     pub op_type: String,
     /// The full JSON representation of the entity
     pub data: serde_json::Value,
+    /// IDs of operations that must be applied before this one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deps: Vec<Uuid>,
+    /// Version of the app's payload shape for `table` that `data` follows.
+    /// Entries from devices predating schema versions are version 1.
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
+}
+
+fn default_schema_version() -> u32 {
+    1
+}
+
+impl OplogEntry {
+    /// Position of this entry in the total order every replica agrees on
+    pub fn order_key(&self) -> OrderKey {
+        OrderKey {
+            timestamp: self.timestamp,
+            device_id: self.device_id,
+            id: self.id,
+        }
+    }
+
+    /// Parsed [`op_type`](Self::op_type)
+    pub fn kind(&self) -> std::result::Result<OpType, ParseOpTypeError> {
+        self.op_type.parse()
+    }
+
+    /// Check that the table name and op type are well-formed.
+    ///
+    /// Table names must be SQL identifiers (ASCII letters, digits and `_`,
+    /// not starting with a digit, at most 64 characters) outside SQLite's
+    /// reserved `sqlite_` namespace. Op types must parse as an [`OpType`].
+    /// Increments must carry a non-zero integer `amount`.
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |reason: String| AhenkError::InvalidOperation {
+            op_id: self.id,
+            reason,
+        };
+        if !is_valid_table_name(&self.table) {
+            return Err(invalid(format!("invalid table name '{}'", self.table)));
+        }
+        let kind = self.kind().map_err(|e| invalid(e.to_string()))?;
+        if kind == OpType::Increment {
+            match self.data.get("amount").and_then(serde_json::Value::as_i64) {
+                Some(amount) if amount != 0 => {}
+                _ => {
+                    return Err(invalid(
+                        "increment amount must be a non-zero integer".to_string(),
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Declare operations this one causally depends on, in addition to the
+    /// ones it already names.
+    ///
+    /// Merging peers hold the operation back until every dependency is in
+    /// their oplog, so e.g. an update is never applied before its create.
+    pub fn with_deps(mut self, deps: impl IntoIterator<Item = Uuid>) -> Self {
+        for dep in deps {
+            if !self.deps.contains(&dep) {
+                self.deps.push(dep);
+            }
+        }
+        self
+    }
+
+    /// Declare the payload schema version `data` follows.
+    ///
+    /// Peers that registered upcasters for `table` (see
+    /// [`MergeRegistry::with_upcaster`](crate::crdt::MergeRegistry::with_upcaster))
+    /// upgrade older payloads before applying them.
+    pub fn with_schema_version(mut self, schema_version: u32) -> Self {
+        self.schema_version = schema_version;
+        self
+    }
+}
+
+const MAX_NAME_LEN: usize = 64;
+
+/// Whether `table` is a name [`OplogEntry::validate`] accepts
+pub(crate) fn is_valid_table_name(table: &str) -> bool {
+    !table.is_empty()
+        && table.len() <= MAX_NAME_LEN
+        && !table.starts_with(|c: char| c.is_ascii_digit())
+        && table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !table.to_ascii_lowercase().starts_with("sqlite_")
+}
+
+/// Total order over oplog entries: HLC timestamp, then device ID, then
+/// operation ID.
+///
+/// Two devices can produce the same HLC timestamp; breaking ties by device
+/// and operation ID makes every replica pick the same winner whatever order
+/// operations arrive in. SQL queries order the oplog the same way, with
+/// `ORDER BY timestamp, device_id, id` (UUIDs are stored as lowercase
+/// hyphenated text, which sorts like their bytes).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct OrderKey {
+    /// Raw HLC timestamp
+    pub timestamp: i64,
+    /// Device that created the operation
+    pub device_id: Uuid,
+    /// Operation ID
+    pub id: Uuid,
+}
+
+/// Kind of change an [`OplogEntry`] records.
+///
+/// [`OplogEntry::op_type`] stores the string form. App-defined operations use
+/// [`OpType::Custom`] and are spelled with an `x-` prefix (`"x-archive"`), so
+/// a typo of a built-in type is rejected rather than taken for a custom one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum OpType {
+    /// Insert a whole row
+    Create,
+    /// Write some or all fields of a row
+    Update,
+    /// Remove a row
+    Delete,
+    /// PN-Counter increment (`"increment"`)
+    Increment,
+    /// OR-Set element add (`"add"`)
+    SetAdd,
+    /// OR-Set element remove (`"remove"`)
+    SetRemove,
+    /// Sequence element insert (`"seq_insert"`)
+    SeqInsert,
+    /// Sequence element delete (`"seq_delete"`)
+    SeqDelete,
+    /// RFC 6902 JSON Patch (`"patch"`)
+    Patch,
+    /// RFC 7386 JSON Merge Patch (`"merge-patch"`)
+    MergePatch,
+    /// App-defined operation; holds the name without the `x-` prefix
+    Custom(String),
+}
+
+impl OpType {
+    /// Prefix of custom op types in their string form
+    pub const CUSTOM_PREFIX: &'static str = "x-";
+}
+
+impl fmt::Display for OpType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OpType::Create => "create",
+            OpType::Update => "update",
+            OpType::Delete => "delete",
+            OpType::Increment => "increment",
+            OpType::SetAdd => "add",
+            OpType::SetRemove => "remove",
+            OpType::SeqInsert => "seq_insert",
+            OpType::SeqDelete => "seq_delete",
+            OpType::Patch => "patch",
+            OpType::MergePatch => "merge-patch",
+            OpType::Custom(name) => return write!(f, "{}{}", Self::CUSTOM_PREFIX, name),
+        };
+        f.write_str(name)
+    }
+}
+
+/// Error returned when parsing an [`OpType`] from a string fails
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOpTypeError(String);
+
+impl fmt::Display for ParseOpTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown op type '{}' (custom op types start with '{}')",
+            self.0,
+            OpType::CUSTOM_PREFIX
+        )
+    }
+}
+
+impl std::error::Error for ParseOpTypeError {}
+
+/// Parses the [`Display`](fmt::Display) format.
+///
+/// Custom names must be non-empty and use only lowercase ASCII letters,
+/// digits, `_` and `-`.
+impl FromStr for OpType {
+    type Err = ParseOpTypeError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "create" => OpType::Create,
+            "update" => OpType::Update,
+            "delete" => OpType::Delete,
+            "increment" => OpType::Increment,
+            "add" => OpType::SetAdd,
+            "remove" => OpType::SetRemove,
+            "seq_insert" => OpType::SeqInsert,
+            "seq_delete" => OpType::SeqDelete,
+            "patch" => OpType::Patch,
+            "merge-patch" => OpType::MergePatch,
+            _ => {
+                let name = s
+                    .strip_prefix(Self::CUSTOM_PREFIX)
+                    .filter(|name| {
+                        !name.is_empty()
+                            && s.len() <= MAX_NAME_LEN
+                            && name.chars().all(|c| {
+                                c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-'
+                            })
+                    })
+                    .ok_or_else(|| ParseOpTypeError(s.to_string()))?;
+                OpType::Custom(name.to_string())
+            }
+        })
+    }
+}
+
+impl TryFrom<String> for OpType {
+    type Error = ParseOpTypeError;
+
+    fn try_from(s: String) -> std::result::Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<OpType> for String {
+    fn from(op_type: OpType) -> Self {
+        op_type.to_string()
+    }
 }
 
 /// Peer device in the P2P synchronization network
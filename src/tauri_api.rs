@@ -8,8 +8,10 @@
 
 #[cfg(feature = "tauri-api")]
 mod tauri_commands {
+    use crate::crdt::{Conflict, ConflictResolution};
     use crate::logic::sync_manager::SyncManager;
     use crate::logic::{login_user, register_user};
+    use crate::models::OplogEntry;
     use crate::models::User;
     use chrono::{DateTime, Utc};
     use rusqlite::Connection;
@@ -106,9 +108,8 @@ mod tauri_commands {
             .map_err(|e| e.to_string())?;
         let _user_uuid = Uuid::parse_str(&user_id).map_err(|e| e.to_string())?;
 
-        // Request sync from last known time or epoch
-        let since = sync_manager.get_last_sync_time().unwrap_or_else(Utc::now);
-        sync_manager.request_sync(since).map_err(|e| e.to_string())
+        // Peers reply with the operations missing from our version vector
+        sync_manager.request_sync().map_err(|e| e.to_string())
     }
 
     /// Set device online/offline status
@@ -142,6 +143,44 @@ mod tauri_commands {
             .sync_pending_changes()
             .map_err(|e| e.to_string())
     }
+
+    // ============================================================================
+    // Conflicts
+    // ============================================================================
+
+    /// List unresolved conflicts between concurrent writes
+    #[tauri::command]
+    pub fn ahenk_list_conflicts(
+        sync_manager_state: tauri::State<Arc<Mutex<SyncManager>>>,
+    ) -> Result<Vec<Conflict>, String> {
+        let sync_manager = sync_manager_state
+            .inner()
+            .lock()
+            .map_err(|e| e.to_string())?;
+        sync_manager.list_conflicts().map_err(|e| e.to_string())
+    }
+
+    /// Resolve a conflict by keeping one write or writing a new value
+    ///
+    /// # Arguments
+    /// * `conflict_id` - Conflict UUID
+    /// * `resolution` - `{"kind": "keep_local"}`, `{"kind": "keep_remote"}` or
+    ///   `{"kind": "value", "value": {...}}`
+    #[tauri::command]
+    pub fn ahenk_resolve_conflict(
+        conflict_id: String,
+        resolution: ConflictResolution,
+        sync_manager_state: tauri::State<Arc<Mutex<SyncManager>>>,
+    ) -> Result<OplogEntry, String> {
+        let mut sync_manager = sync_manager_state
+            .inner()
+            .lock()
+            .map_err(|e| e.to_string())?;
+        let conflict_uuid = Uuid::parse_str(&conflict_id).map_err(|e| e.to_string())?;
+        sync_manager
+            .resolve_conflict(conflict_uuid, resolution)
+            .map_err(|e| e.to_string())
+    }
 }
 
 #[cfg(feature = "tauri-api")]
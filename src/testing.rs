@@ -0,0 +1,827 @@
+//! Deterministic multi-replica simulation for convergence tests.
+//!
+//! A [`Simulation`] runs N in-memory replicas, each with its own
+//! [`Connection`], [`DeviceClock`] and [`MergeRegistry`], connected by a
+//! simulated network instead of libp2p. Messages are encoded with
+//! [`encode_sync_message`] and delivered to [`handle_sync_message`] exactly
+//! as a swarm would: like the swarm's gossip topic, every message, replies
+//! included, goes to every other replica, and replicas ignore those addressed
+//! to someone else. Each replica knows the others as peers, so their
+//! acknowledgements move its stable point and compaction removes history.
+//! The network can:
+//! - partition replicas into groups that cannot reach each other
+//! - deliver in-flight messages in random order
+//! - duplicate and drop messages
+//!
+//! Every random choice comes from a [`SimRng`] seeded at construction and
+//! replicas read time from [`ManualClock`]s, so a seed always produces the
+//! same schedule.
+//!
+//! ```rust
+//! use ahenk::testing::{NetworkConfig, Simulation};
+//! use ahenk::LastWriteWins;
+//!
+//! let mut sim = Simulation::with_setup(3, 7, |conn| {
+//!     conn.execute("CREATE TABLE todos (id TEXT PRIMARY KEY, title TEXT)", [])?;
+//!     Ok(ahenk::MergeRegistry::new().with_resolver("todos", LastWriteWins::new()))
+//! })
+//! .unwrap();
+//! sim.set_network(NetworkConfig::faulty());
+//!
+//! sim.partition(&[&[0], &[1, 2]]);
+//! sim.write(0, "todos", "create", &serde_json::json!({"id": "t1", "title": "a"})).unwrap();
+//! sim.write(1, "todos", "create", &serde_json::json!({"id": "t1", "title": "b"})).unwrap();
+//! sim.sync_all().unwrap();
+//! sim.run_until_idle().unwrap();
+//!
+//! sim.settle().unwrap();
+//! sim.assert_converged();
+//! ```
+//!
+//! [`check_convergence`] is the property-testing entry point: it runs random
+//! workloads over many seeds and reports the first seed that does not
+//! converge.
+
+use crate::crdt::{
+    checkpoint, compact_oplog, create_snapshot, local_apply_with, restore_snapshot, DeviceClock,
+    ManualClock, MergeRegistry,
+};
+use crate::db::operations::{get_oplog_entries_since, get_version_vector, initialize_database};
+use crate::error::{AhenkError, Result};
+use crate::logic::build_oplog_entry;
+use crate::logic::sync::{
+    decode_sync_message, encode_sync_message, handle_sync_message, reconcile_request,
+    update_peer_info, SyncMessage,
+};
+use crate::models::OplogEntry;
+use chrono::{Duration, TimeZone, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use uuid::Uuid;
+
+// ============================================================================
+// Randomness
+// ============================================================================
+
+/// Seeded pseudo-random generator (SplitMix64) driving a simulation
+#[derive(Debug, Clone)]
+pub struct SimRng {
+    state: u64,
+}
+
+impl SimRng {
+    /// Create a generator; equal seeds produce equal sequences
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Next 64 random bits
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform integer in `0..n` (`n` must be positive)
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// `true` with probability `p`
+    pub fn chance(&mut self, p: f64) -> bool {
+        // 53 random bits as a float in [0, 1)
+        let sample = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        sample < p
+    }
+}
+
+// ============================================================================
+// Network
+// ============================================================================
+
+/// Faults injected by the simulated network
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NetworkConfig {
+    /// Probability that a delivered message is lost
+    pub loss: f64,
+    /// Probability that a delivered message is delivered again later
+    pub duplication: f64,
+    /// Deliver in-flight messages in random order instead of FIFO
+    pub reorder: bool,
+}
+
+impl NetworkConfig {
+    /// In-order delivery without loss or duplication
+    pub fn reliable() -> Self {
+        Self {
+            loss: 0.0,
+            duplication: 0.0,
+            reorder: false,
+        }
+    }
+
+    /// Reordering with 10% loss and 10% duplication
+    pub fn faulty() -> Self {
+        Self {
+            loss: 0.1,
+            duplication: 0.1,
+            reorder: true,
+        }
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self::reliable()
+    }
+}
+
+/// Message counters of a simulated network
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NetworkStats {
+    /// Messages sent
+    pub sent: usize,
+    /// Messages handed to a replica (duplicates included)
+    pub delivered: usize,
+    /// Messages lost to faults or partitions
+    pub dropped: usize,
+    /// Extra copies queued for redelivery
+    pub duplicated: usize,
+}
+
+/// A message in flight between two replicas
+#[derive(Debug, Clone)]
+struct Envelope {
+    from: usize,
+    to: usize,
+    bytes: Vec<u8>,
+}
+
+// ============================================================================
+// Replicas
+// ============================================================================
+
+/// One simulated device
+pub struct Replica {
+    /// The replica's database
+    pub conn: Connection,
+    /// The replica's device clock, reading time from [`time`](Self::time)
+    pub clock: DeviceClock,
+    /// Resolvers applying operations to the replica's app tables
+    pub registry: MergeRegistry,
+    /// Physical time seen by the replica
+    pub time: ManualClock,
+}
+
+/// N replicas connected by a simulated network
+pub struct Simulation {
+    replicas: Vec<Replica>,
+    in_flight: VecDeque<Envelope>,
+    groups: Vec<usize>,
+    network: NetworkConfig,
+    stats: NetworkStats,
+    rng: SimRng,
+}
+
+impl Simulation {
+    /// Create `replicas` replicas without app tables or resolvers
+    pub fn new(replicas: usize, seed: u64) -> Result<Self> {
+        Self::with_setup(replicas, seed, |_| Ok(MergeRegistry::new()))
+    }
+
+    /// Create `replicas` replicas, running `setup` on each database to create
+    /// the app's tables and build its registry
+    pub fn with_setup(
+        replicas: usize,
+        seed: u64,
+        setup: impl Fn(&Connection) -> Result<MergeRegistry>,
+    ) -> Result<Self> {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let replicas = (0..replicas)
+            .map(|i| {
+                let conn = initialize_database(":memory:")?;
+                let registry = setup(&conn)?;
+                let time = ManualClock::new(start);
+                let device_id = Uuid::from_u64_pair(seed, i as u64 + 1);
+                Ok(Replica {
+                    conn,
+                    clock: DeviceClock::with_clock(device_id, Arc::new(time.clone())),
+                    registry,
+                    time,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        for replica in &replicas {
+            for peer in replicas.iter().filter(|peer| !std::ptr::eq(*peer, replica)) {
+                let device_id = peer.clock.device_id();
+                update_peer_info(&replica.conn, Uuid::nil(), device_id, String::new(), None)?;
+            }
+        }
+
+        Ok(Self {
+            groups: vec![0; replicas.len()],
+            replicas,
+            in_flight: VecDeque::new(),
+            network: NetworkConfig::default(),
+            stats: NetworkStats::default(),
+            rng: SimRng::new(seed),
+        })
+    }
+
+    /// Number of replicas
+    pub fn len(&self) -> usize {
+        self.replicas.len()
+    }
+
+    /// Whether the simulation has no replicas
+    pub fn is_empty(&self) -> bool {
+        self.replicas.is_empty()
+    }
+
+    /// Replica `index`
+    pub fn replica(&self, index: usize) -> &Replica {
+        &self.replicas[index]
+    }
+
+    /// Replica `index`, for direct writes
+    pub fn replica_mut(&mut self, index: usize) -> &mut Replica {
+        &mut self.replicas[index]
+    }
+
+    /// The simulation's random generator, for workloads
+    pub fn rng(&mut self) -> &mut SimRng {
+        &mut self.rng
+    }
+
+    /// Faults injected from now on
+    pub fn set_network(&mut self, network: NetworkConfig) {
+        self.network = network;
+    }
+
+    /// Message counters so far
+    pub fn stats(&self) -> NetworkStats {
+        self.stats
+    }
+
+    /// Messages still in flight
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Split replicas into groups that cannot reach each other. Replicas not
+    /// listed form one more group.
+    pub fn partition(&mut self, groups: &[&[usize]]) {
+        self.groups = vec![groups.len(); self.replicas.len()];
+        for (group, members) in groups.iter().enumerate() {
+            for &replica in members.iter() {
+                self.groups[replica] = group;
+            }
+        }
+    }
+
+    /// Reconnect every replica
+    pub fn heal(&mut self) {
+        self.groups = vec![0; self.replicas.len()];
+    }
+
+    /// Move every replica's physical time forward
+    pub fn advance_time(&mut self, by: Duration) {
+        for replica in &self.replicas {
+            replica.time.advance(by);
+        }
+    }
+
+    /// Build a local operation on replica `index` and apply it with the
+    /// replica's resolvers
+    pub fn write(
+        &mut self,
+        index: usize,
+        table: &str,
+        op_type: &str,
+        data: &serde_json::Value,
+    ) -> Result<OplogEntry> {
+        let replica = &mut self.replicas[index];
+        let op = build_oplog_entry(&replica.conn, &replica.clock, table, op_type, data)?;
+        local_apply_with(&mut replica.conn, &op, &replica.registry)?;
+        Ok(op)
+    }
+
+    /// Send a message from one replica to another
+    ///
+    /// The swarm has no direct messages; prefer [`broadcast`](Self::broadcast).
+    pub fn send(&mut self, from: usize, to: usize, message: &SyncMessage) -> Result<()> {
+        let bytes = encode_sync_message(message)?;
+        self.stats.sent += 1;
+        if self.groups[from] != self.groups[to] {
+            self.stats.dropped += 1;
+            return Ok(());
+        }
+        self.in_flight.push_back(Envelope { from, to, bytes });
+        Ok(())
+    }
+
+    /// Publish a message from one replica to every other replica
+    pub fn broadcast(&mut self, from: usize, message: &SyncMessage) -> Result<()> {
+        for to in (0..self.replicas.len()).filter(|&to| to != from) {
+            self.send(from, to, message)?;
+        }
+        Ok(())
+    }
+
+    /// Have replica `from` ask replica `to` for the operations it is missing
+    pub fn request_sync(&mut self, from: usize, to: usize) -> Result<()> {
+        let message = self.sync_request(from, Some(to))?;
+        self.broadcast(from, &message)
+    }
+
+    /// Have replica `from` start an anti-entropy round with every other replica
+    pub fn reconcile(&mut self, from: usize) -> Result<()> {
+        let replica = &self.replicas[from];
+        let message = reconcile_request(&replica.conn, &replica.clock)?;
+        self.broadcast(from, &message)
+    }
+
+    /// Have every replica request sync from every other replica
+    pub fn sync_all(&mut self) -> Result<()> {
+        for from in 0..self.replicas.len() {
+            let message = self.sync_request(from, None)?;
+            self.broadcast(from, &message)?;
+        }
+        Ok(())
+    }
+
+    /// `RequestSync` from replica `from`, addressed to replica `to` if given
+    fn sync_request(&self, from: usize, to: Option<usize>) -> Result<SyncMessage> {
+        Ok(SyncMessage::RequestSync {
+            user_id: Uuid::nil(),
+            to: to.map(|to| self.replicas[to].clock.device_id()),
+            after: None,
+            version_vector: get_version_vector(&self.replicas[from].conn)?,
+        })
+    }
+
+    /// Compact replica `index`'s oplog below its stable point
+    pub fn compact(&mut self, index: usize) -> Result<()> {
+        let replica = &mut self.replicas[index];
+        let device_id = replica.clock.device_id();
+        compact_oplog(&mut replica.conn, device_id, &replica.registry)?;
+        Ok(())
+    }
+
+    /// Take a checkpoint on replica `index` and check that a new device
+    /// restoring it reaches the same state
+    pub fn checkpoint(&mut self, index: usize) -> Result<()> {
+        let replica = &self.replicas[index];
+        let snapshot = checkpoint(&replica.conn, &replica.registry)?;
+
+        // The new device has the same app tables
+        let mut conn = initialize_database(":memory:")?;
+        for table in replica.registry.tables() {
+            let sql: Option<String> = replica
+                .conn
+                .query_row(
+                    "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?1",
+                    params![table],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if let Some(sql) = sql {
+                conn.execute(&sql, [])?;
+            }
+        }
+        let clock = DeviceClock::with_clock(Uuid::new_v4(), Arc::new(replica.time.clone()));
+        restore_snapshot(&mut conn, &clock, &replica.registry, &snapshot)?;
+
+        let expected = ReplicaState::read(&replica.conn, &replica.registry)?;
+        let restored = ReplicaState::read(&conn, &replica.registry)?;
+        match expected.difference(&restored) {
+            Some(difference) => Err(AhenkError::Validation(format!(
+                "checkpoint of replica {} does not restore: {}",
+                index, difference
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Deliver (or lose) one in-flight message; returns `false` if none was
+    /// in flight
+    pub fn step(&mut self) -> Result<bool> {
+        let next = if self.network.reorder && !self.in_flight.is_empty() {
+            let index = self.rng.below(self.in_flight.len());
+            self.in_flight.remove(index)
+        } else {
+            self.in_flight.pop_front()
+        };
+        let Some(envelope) = next else {
+            return Ok(false);
+        };
+
+        // Partitions also cut off messages sent before they formed
+        if self.groups[envelope.from] != self.groups[envelope.to]
+            || self.rng.chance(self.network.loss)
+        {
+            self.stats.dropped += 1;
+            return Ok(true);
+        }
+        if self.rng.chance(self.network.duplication) {
+            self.stats.duplicated += 1;
+            self.in_flight.push_back(envelope.clone());
+        }
+
+        self.stats.delivered += 1;
+        let message = decode_sync_message(&envelope.bytes)?;
+        let replica = &mut self.replicas[envelope.to];
+        let reply = handle_sync_message(
+            &mut replica.conn,
+            &replica.clock,
+            &replica.registry,
+            message,
+        )?;
+        if let Some(reply) = reply {
+            self.broadcast(envelope.to, &reply)?;
+        }
+        Ok(true)
+    }
+
+    /// Deliver messages until none are in flight
+    pub fn run_until_idle(&mut self) -> Result<()> {
+        while self.step()? {}
+        Ok(())
+    }
+
+    /// Run `steps` random actions: writes (made by `write` on the replica it
+    /// is given), sync requests, anti-entropy rounds, compactions,
+    /// checkpoints, partitions, heals, message deliveries and the passing of
+    /// time
+    pub fn fuzz(
+        &mut self,
+        steps: usize,
+        mut write: impl FnMut(&mut Simulation, usize) -> Result<()>,
+    ) -> Result<()> {
+        let n = self.replicas.len();
+        for _ in 0..steps {
+            let millis = self.rng.below(50) as i64;
+            self.advance_time(Duration::milliseconds(millis));
+
+            match self.rng.below(100) {
+                0..=29 => {
+                    let replica = self.rng.below(n);
+                    write(self, replica)?;
+                }
+                30..=49 if n > 1 => {
+                    let from = self.rng.below(n);
+                    let to = (from + 1 + self.rng.below(n - 1)) % n;
+                    self.request_sync(from, to)?;
+                }
+                50..=52 => {
+                    let from = self.rng.below(n);
+                    self.reconcile(from)?;
+                }
+                53..=55 => {
+                    let groups: Vec<usize> = (0..n).map(|_| self.rng.below(2)).collect();
+                    self.groups = groups;
+                }
+                56..=59 => self.heal(),
+                60..=62 => {
+                    let replica = self.rng.below(n);
+                    self.compact(replica)?;
+                }
+                63..=64 => {
+                    let replica = self.rng.below(n);
+                    self.checkpoint(replica)?;
+                }
+                _ => {
+                    self.step()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Heal the network, drain it and run reliable sync rounds, so every
+    /// operation written so far reaches every replica. The network's fault
+    /// configuration is kept for later steps.
+    ///
+    /// Each round is a version-vector sync between every pair of replicas;
+    /// anti-entropy is not needed to repair anything lost before.
+    pub fn settle(&mut self) -> Result<()> {
+        let network = self.network;
+        self.network = NetworkConfig::reliable();
+        self.heal();
+        self.run_until_idle()?;
+        for _ in 0..2 {
+            self.sync_all()?;
+            self.run_until_idle()?;
+        }
+        self.network = network;
+        Ok(())
+    }
+
+    /// First difference between replica 0 and any other replica, comparing
+    /// the operations each has seen (whether still in its oplog or
+    /// compacted), version vectors and the rows of every table with a
+    /// resolver
+    pub fn divergence(&self) -> Result<Option<String>> {
+        let Some(first) = self.replicas.first() else {
+            return Ok(None);
+        };
+        let expected = ReplicaState::read(&first.conn, &first.registry)?.shared();
+        for (index, replica) in self.replicas.iter().enumerate().skip(1) {
+            let actual = ReplicaState::read(&replica.conn, &replica.registry)?.shared();
+            if let Some(difference) = expected.difference(&actual) {
+                return Ok(Some(format!(
+                    "replica {} differs from replica 0: {}",
+                    index, difference
+                )));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Panic unless every replica has converged (see [`divergence`](Self::divergence))
+    pub fn assert_converged(&self) {
+        match self.divergence() {
+            Ok(None) => {}
+            Ok(Some(difference)) => panic!("replicas did not converge: {}", difference),
+            Err(e) => panic!("failed to read replica state: {}", e),
+        }
+    }
+}
+
+/// What converged replicas must agree on
+#[derive(Debug, PartialEq)]
+struct ReplicaState {
+    oplog: Vec<Uuid>,
+    version_vector: Vec<(Uuid, i64)>,
+    tables: Vec<(String, Vec<String>)>,
+}
+
+impl ReplicaState {
+    fn read(conn: &Connection, registry: &MergeRegistry) -> Result<Self> {
+        // Replicas compact at different stable points, so operations they
+        // removed count as seen
+        let mut oplog: Vec<Uuid> = get_oplog_entries_since(conn, i64::MIN)?
+            .iter()
+            .map(|op| op.id)
+            .collect();
+        let mut stmt = conn.prepare("SELECT id FROM oplog_compacted")?;
+        for id in stmt.query_map([], |row| row.get::<_, String>(0))? {
+            if let Ok(id) = Uuid::parse_str(&id?) {
+                oplog.push(id);
+            }
+        }
+        oplog.sort();
+        let mut version_vector: Vec<(Uuid, i64)> = get_version_vector(conn)?.iter().collect();
+        version_vector.sort();
+
+        // Rows in a canonical order; SELECT order depends on write history
+        let tables = create_snapshot(conn, registry)?
+            .tables
+            .into_iter()
+            .map(|(table, rows)| {
+                let mut rows: Vec<String> = rows
+                    .iter()
+                    .map(|row| serde_json::Value::Object(row.clone()).to_string())
+                    .collect();
+                rows.sort();
+                (table, rows)
+            })
+            .collect();
+
+        Ok(Self {
+            oplog,
+            version_vector,
+            tables,
+        })
+    }
+
+    /// Without the tombstones of compacted deletes, which like the compacted
+    /// operations depend on when each replica compacted
+    fn shared(mut self) -> Self {
+        self.tables.retain(|(table, _)| table != "oplog_tombstones");
+        self
+    }
+
+    fn difference(&self, other: &Self) -> Option<String> {
+        if self.oplog != other.oplog {
+            return Some(format!(
+                "oplog has {} operations, expected {}",
+                other.oplog.len(),
+                self.oplog.len()
+            ));
+        }
+        if self.version_vector != other.version_vector {
+            return Some(format!(
+                "version vector {:?}, expected {:?}",
+                other.version_vector, self.version_vector
+            ));
+        }
+        for ((table, rows), (_, other_rows)) in self.tables.iter().zip(&other.tables) {
+            if rows != other_rows {
+                return Some(format!(
+                    "table '{}' has rows {:?}, expected {:?}",
+                    table, other_rows, rows
+                ));
+            }
+        }
+        (self.tables.len() != other.tables.len()).then(|| "different tables".to_string())
+    }
+}
+
+// ============================================================================
+// Property Testing
+// ============================================================================
+
+/// Parameters of [`check_convergence`]
+#[derive(Debug, Clone)]
+pub struct PropertyConfig {
+    /// Number of seeds to run
+    pub cases: u64,
+    /// First seed; case `i` runs with `seed + i`
+    pub seed: u64,
+    /// Replicas per simulation
+    pub replicas: usize,
+    /// Random actions per simulation (see [`Simulation::fuzz`])
+    pub steps: usize,
+    /// Faults injected while the workload runs
+    pub network: NetworkConfig,
+}
+
+impl Default for PropertyConfig {
+    /// 32 cases of 3 replicas and 200 steps on a faulty network. The first
+    /// seed is read from `AHENK_SIM_SEED` if set, so a reported failure can
+    /// be replayed.
+    fn default() -> Self {
+        Self {
+            cases: 32,
+            seed: std::env::var("AHENK_SIM_SEED")
+                .ok()
+                .and_then(|seed| seed.parse().ok())
+                .unwrap_or(0),
+            replicas: 3,
+            steps: 200,
+            network: NetworkConfig::faulty(),
+        }
+    }
+}
+
+/// Check that random workloads converge.
+///
+/// For every seed, builds a [`Simulation`] with `setup`, runs
+/// [`fuzz`](Simulation::fuzz) with `write` on the configured network, then
+/// [`settle`](Simulation::settle)s it. Panics with the seed of the first case
+/// whose replicas diverge or fail; rerun that case alone with `seed` set to
+/// it and `cases` set to 1.
+pub fn check_convergence<S, W>(config: &PropertyConfig, setup: S, mut write: W)
+where
+    S: Fn(&Connection) -> Result<MergeRegistry>,
+    W: FnMut(&mut Simulation, usize) -> Result<()>,
+{
+    for case in 0..config.cases {
+        let seed = config.seed.wrapping_add(case);
+        let outcome = Simulation::with_setup(config.replicas, seed, &setup).and_then(|mut sim| {
+            sim.set_network(config.network);
+            sim.fuzz(config.steps, &mut write)?;
+            sim.settle()?;
+            sim.divergence()
+        });
+        match outcome {
+            Ok(None) => {}
+            Ok(Some(difference)) => {
+                panic!("seed {}: replicas did not converge: {}", seed, difference)
+            }
+            Err(e) => panic!("seed {}: simulation failed: {}", seed, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{DeleteWins, FieldLww, LastWriteWins};
+    use serde_json::json;
+
+    fn todos(conn: &Connection) -> Result<MergeRegistry> {
+        conn.execute_batch(
+            "CREATE TABLE todos (id TEXT PRIMARY KEY, title TEXT, done INTEGER);
+             CREATE TABLE notes (id TEXT PRIMARY KEY, body TEXT, pinned INTEGER);
+             CREATE TABLE tags (id TEXT PRIMARY KEY, name TEXT)",
+        )?;
+        Ok(MergeRegistry::new()
+            .with_resolver("todos", LastWriteWins::new())
+            .with_resolver("notes", FieldLww::new())
+            .with_resolver("tags", DeleteWins::new()))
+    }
+
+    fn random_write(sim: &mut Simulation, replica: usize) -> Result<()> {
+        let rng = sim.rng();
+        let table = ["todos", "notes", "tags"][rng.below(3)];
+        let id = format!("e{}", rng.below(4));
+        let value = rng.below(100);
+        let (op_type, data) = match (table, rng.below(4)) {
+            (_, 0) => ("delete", json!({"id": id})),
+            ("todos", _) => (
+                "update",
+                json!({"id": id, "title": value, "done": value % 2}),
+            ),
+            ("notes", 1) => ("update", json!({"id": id, "body": value})),
+            ("notes", _) => ("update", json!({"id": id, "pinned": value % 2})),
+            _ => ("create", json!({"id": id, "name": value})),
+        };
+        sim.write(replica, table, op_type, &data)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_partitioned_writes_converge() {
+        let mut sim = Simulation::with_setup(3, 1, todos).unwrap();
+        sim.set_network(NetworkConfig {
+            loss: 0.3,
+            duplication: 0.3,
+            reorder: true,
+        });
+
+        sim.partition(&[&[0], &[1, 2]]);
+        sim.write(0, "todos", "create", &json!({"id": "t1", "title": "a"}))
+            .unwrap();
+        sim.write(1, "todos", "create", &json!({"id": "t1", "title": "b"}))
+            .unwrap();
+        sim.write(2, "tags", "create", &json!({"id": "g1", "name": "x"}))
+            .unwrap();
+        for _ in 0..5 {
+            sim.sync_all().unwrap();
+            sim.run_until_idle().unwrap();
+        }
+        assert!(sim.divergence().unwrap().is_some());
+        assert!(sim.stats().dropped > 0 && sim.stats().duplicated > 0);
+
+        sim.settle().unwrap();
+        sim.assert_converged();
+        let titles: Vec<String> = (0..sim.len())
+            .map(|i| {
+                sim.replica(i)
+                    .conn
+                    .query_row("SELECT title FROM todos WHERE id = 't1'", [], |row| {
+                        row.get(0)
+                    })
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(titles[0], titles[1]);
+        assert_eq!(titles[1], titles[2]);
+    }
+
+    #[test]
+    fn test_acknowledged_history_is_compacted() {
+        let mut sim = Simulation::with_setup(3, 2, todos).unwrap();
+        sim.write(0, "todos", "create", &json!({"id": "t1", "title": "a"}))
+            .unwrap();
+        sim.write(1, "tags", "create", &json!({"id": "g1", "name": "x"}))
+            .unwrap();
+        sim.settle().unwrap();
+        sim.write(2, "todos", "update", &json!({"id": "t1", "title": "b"}))
+            .unwrap();
+        sim.write(2, "tags", "delete", &json!({"id": "g1"}))
+            .unwrap();
+        sim.settle().unwrap();
+
+        for replica in 0..sim.len() {
+            sim.compact(replica).unwrap();
+            sim.checkpoint(replica).unwrap();
+        }
+        let conn = &sim.replica(0).conn;
+        let remaining = get_oplog_entries_since(conn, i64::MIN).unwrap();
+        let tombstones: i64 = conn
+            .query_row("SELECT COUNT(*) FROM oplog_tombstones", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!((remaining.len(), tombstones), (1, 1));
+        sim.assert_converged();
+
+        // A later write to the deleted tag still loses everywhere
+        sim.write(0, "tags", "update", &json!({"id": "g1", "name": "y"}))
+            .unwrap();
+        sim.settle().unwrap();
+        sim.assert_converged();
+        let tags: i64 = sim
+            .replica(1)
+            .conn
+            .query_row("SELECT COUNT(*) FROM tags", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(tags, 0);
+    }
+
+    #[test]
+    fn test_random_workloads_converge() {
+        let config = PropertyConfig {
+            cases: 8,
+            ..PropertyConfig::default()
+        };
+        check_convergence(&config, todos, random_write);
+    }
+}
@@ -153,6 +153,8 @@ fn test_oplog_and_peer_crud_operations() {
             "content": "Sample app data",
             "record_id": Uuid::new_v4().to_string(),
         }),
+        deps: Vec::new(),
+        schema_version: 1,
     };
     operations::create_oplog_entry(&conn, &oplog_entry).expect("Failed to create oplog entry");
 
@@ -277,6 +279,8 @@ fn test_oplog_ordering_by_timestamp() {
             table: "test_table".to_string(),
             op_type: "create".to_string(),
             data: serde_json::json!({"index": i}),
+            deps: Vec::new(),
+            schema_version: 1,
         };
         operations::create_oplog_entry(&conn, &entry).expect("Failed to create oplog entry");
     }
@@ -292,3 +296,69 @@ fn test_oplog_ordering_by_timestamp() {
         assert!(entries[i].timestamp < entries[i + 1].timestamp);
     }
 }
+
+#[test]
+fn test_oplog_pages_follow_total_order() {
+    use ahenk::crdt::VersionVector;
+
+    let conn =
+        operations::initialize_database(":memory:").expect("Failed to create in-memory database");
+
+    // Two devices writing at the same timestamps exercise the device and ID
+    // tie-breakers of the page cursor
+    let phone = Uuid::new_v4();
+    let laptop = Uuid::new_v4();
+    for i in 0..7 {
+        for device_id in [phone, laptop] {
+            let entry = OplogEntry {
+                id: Uuid::new_v4(),
+                device_id,
+                timestamp: 1000 + i / 2,
+                table: "test_table".to_string(),
+                op_type: "create".to_string(),
+                data: serde_json::json!({"index": i}),
+                deps: Vec::new(),
+                schema_version: 1,
+            };
+            operations::create_oplog_entry(&conn, &entry).expect("Failed to create oplog entry");
+        }
+    }
+    let all: Vec<Uuid> = operations::get_oplog_entries_since(&conn, 0)
+        .unwrap()
+        .iter()
+        .map(|e| e.id)
+        .collect();
+    assert_eq!(all.len(), 14);
+
+    let mut paged = Vec::new();
+    let mut cursor = None;
+    loop {
+        let page = operations::get_oplog_page_since(&conn, 0, cursor, 4).unwrap();
+        assert!(page.entries.len() <= 4);
+        paged.extend(page.entries.iter().map(|e| e.id));
+        cursor = page.next;
+        if cursor.is_none() {
+            break;
+        }
+    }
+    assert_eq!(paged, all);
+
+    let iterated: Vec<Uuid> = operations::iter_oplog_since(&conn, 0, 3)
+        .map(|e| e.unwrap().id)
+        .collect();
+    assert_eq!(iterated, all);
+
+    // A peer that has seen the phone's first four writes misses the rest
+    let remote: VersionVector = [(phone, 1001)].into_iter().collect();
+    let missing: Vec<OplogEntry> = operations::iter_oplog_missing(&conn, &remote, 2)
+        .unwrap()
+        .map(|e| e.unwrap())
+        .collect();
+    assert_eq!(missing.len(), 10);
+    assert!(missing
+        .iter()
+        .all(|e| e.device_id == laptop || e.timestamp > 1001));
+    let up_to_date = operations::get_version_vector(&conn).unwrap();
+    let page = operations::get_oplog_page_missing(&conn, &up_to_date, None, 2).unwrap();
+    assert!(page.entries.is_empty() && page.next.is_none());
+}
@@ -239,7 +239,8 @@ fn test_generate_device_id() {
 
 #[test]
 fn test_sync_message_encode_decode() {
-    use ahenk::logic::sync::{SyncMessage, decode_sync_message, encode_sync_message};
+    use ahenk::crdt::VersionVector;
+    use ahenk::logic::sync::{decode_sync_message, encode_sync_message, SyncMessage};
 
     let user_id = Uuid::new_v4();
     let device_id = Uuid::new_v4();
@@ -268,10 +269,12 @@ fn test_sync_message_encode_decode() {
     }
 
     // Test RequestSync message
-    let timestamp = Utc::now().timestamp();
+    let version_vector: VersionVector = [(device_id, 42)].into_iter().collect();
     let request_msg = SyncMessage::RequestSync {
         user_id,
-        since_timestamp: timestamp,
+        to: None,
+        after: None,
+        version_vector: version_vector.clone(),
     };
 
     let encoded = encode_sync_message(&request_msg).unwrap();
@@ -280,16 +283,118 @@ fn test_sync_message_encode_decode() {
     match decoded {
         SyncMessage::RequestSync {
             user_id: uid,
-            since_timestamp: ts,
+            version_vector: vv,
+            ..
         } => {
             assert_eq!(uid, user_id);
-            // Allow for small timestamp differences due to serialization
-            assert!((ts - timestamp).abs() < 2);
+            assert_eq!(vv, version_vector);
         }
         _ => panic!("Expected RequestSync message"),
     }
 }
 
+#[test]
+fn test_request_sync_sends_ops_missing_from_version_vector() {
+    use ahenk::crdt::{DeviceClock, HybridLogicalClock, MergeRegistry, VersionVector};
+    use ahenk::logic::sync::{handle_sync_message, SyncMessage};
+    use ahenk::models::OplogEntry;
+
+    let mut conn = setup_empty_db();
+    let clock = DeviceClock::new(Uuid::new_v4());
+    let fast = Uuid::new_v4();
+    let slow = Uuid::new_v4();
+    let now = Utc::now();
+    let op = |device_id, time| OplogEntry {
+        id: Uuid::new_v4(),
+        device_id,
+        timestamp: HybridLogicalClock::new(time, 0).to_timestamp(),
+        table: "todos".to_string(),
+        op_type: "create".to_string(),
+        data: serde_json::json!({"id": Uuid::new_v4().to_string()}),
+        deps: Vec::new(),
+        schema_version: 1,
+    };
+
+    let fast_seen = op(fast, now - chrono::Duration::minutes(10));
+    let fast_new = op(fast, now - chrono::Duration::minutes(5));
+    // The slow device's clock lags an hour behind, and the requester has
+    // never heard from it
+    let slow_new = op(slow, now - chrono::Duration::hours(1));
+    let entries = [fast_seen.clone(), fast_new.clone(), slow_new.clone()];
+    ahenk::merge(&mut conn, &clock, &entries).unwrap();
+
+    let requester: VersionVector = [(fast, fast_seen.timestamp)].into_iter().collect();
+    let reply = handle_sync_message(
+        &mut conn,
+        &clock,
+        &MergeRegistry::default(),
+        SyncMessage::RequestSync {
+            user_id: Uuid::new_v4(),
+            to: None,
+            after: None,
+            version_vector: requester,
+        },
+    )
+    .unwrap();
+
+    let Some(SyncMessage::SyncData { entries, .. }) = reply else {
+        panic!("Expected SyncData reply");
+    };
+    let ids: Vec<Uuid> = entries.iter().map(|e| e.id).collect();
+    assert_eq!(ids, vec![slow_new.id, fast_new.id]);
+
+    let local = operations::get_version_vector(&conn).unwrap();
+    assert_eq!(local.get(fast), fast_new.timestamp);
+    assert_eq!(local.get(slow), slow_new.timestamp);
+}
+
+#[test]
+fn test_merge_rejects_invalid_remote_ops() {
+    use ahenk::crdt::{DeviceClock, HybridLogicalClock};
+    use ahenk::models::{OpType, OplogEntry};
+    use ahenk::AhenkError;
+
+    assert_eq!("seq_insert".parse(), Ok(OpType::SeqInsert));
+    assert_eq!(
+        "x-archive".parse(),
+        Ok(OpType::Custom("archive".to_string()))
+    );
+    assert_eq!(
+        OpType::Custom("archive".to_string()).to_string(),
+        "x-archive"
+    );
+    assert!("delte".parse::<OpType>().is_err());
+    assert!("x-".parse::<OpType>().is_err());
+
+    let mut conn = setup_empty_db();
+    let clock = DeviceClock::new(Uuid::new_v4());
+    let op = |op_type: &str| OplogEntry {
+        id: Uuid::new_v4(),
+        device_id: Uuid::new_v4(),
+        timestamp: HybridLogicalClock::new(Utc::now(), 0).to_timestamp(),
+        table: "todos".to_string(),
+        op_type: op_type.to_string(),
+        data: serde_json::json!({"id": "t1"}),
+        deps: Vec::new(),
+        schema_version: 1,
+    };
+
+    let typo = op("delte");
+    let err = ahenk::merge(&mut conn, &clock, &[op("create"), typo.clone()]).unwrap_err();
+    assert!(matches!(err, AhenkError::InvalidOperation { op_id, .. } if op_id == typo.id));
+    assert!(ahenk::local_apply(&mut conn, &typo).is_err());
+    assert!(operations::get_oplog_entries_since(&conn, 0)
+        .unwrap()
+        .is_empty());
+    assert!(logic::build_oplog_entry(&conn, &clock, "todos", "delte", &typo.data).is_err());
+
+    ahenk::merge(&mut conn, &clock, &[op("create"), op("x-archive")]).unwrap();
+    assert_eq!(
+        operations::get_oplog_entries_since(&conn, 0).unwrap().len(),
+        2
+    );
+}
+
 #[test]
 fn test_update_peer_info() {
     use ahenk::logic::sync::update_peer_info;
@@ -365,3 +470,246 @@ fn test_sync_prevents_duplicates() {
     // ... test omitted (see git history for original)
 }
 */
+
+#[test]
+fn test_sync_data_is_acknowledged() {
+    use ahenk::crdt::{stable_watermark, DeviceClock, HybridLogicalClock, MergeRegistry};
+    use ahenk::logic::sync::{handle_sync_message, SyncMessage};
+    use ahenk::models::OplogEntry;
+
+    let (mut sender, user_id, _) = setup_db_with_user_and_device();
+    let sender_clock = DeviceClock::new(Uuid::new_v4());
+    let mut receiver = setup_empty_db();
+    let receiver_clock = DeviceClock::new(Uuid::new_v4());
+    let registry = MergeRegistry::default();
+    logic::sync::update_peer_info(
+        &sender,
+        user_id,
+        receiver_clock.device_id(),
+        "peer".to_string(),
+        None,
+    )
+    .unwrap();
+
+    let mut ops = Vec::new();
+    for id in ["t1", "t2"] {
+        let op = logic::build_oplog_entry(
+            &sender,
+            &sender_clock,
+            "todos",
+            "create",
+            &serde_json::json!({"id": id}),
+        )
+        .unwrap();
+        ahenk::local_apply(&mut sender, &op).unwrap();
+        ops.push(op);
+    }
+    let mut acknowledge = |entries: Vec<OplogEntry>| {
+        let reply = handle_sync_message(
+            &mut receiver,
+            &receiver_clock,
+            &registry,
+            SyncMessage::SyncData {
+                user_id,
+                device_id: sender_clock.device_id(),
+                entries,
+                next: None,
+            },
+        )
+        .unwrap();
+        let Some(ack @ SyncMessage::Ack { device_id, to, .. }) = reply else {
+            panic!("Expected Ack reply");
+        };
+        assert_eq!(device_id, receiver_clock.device_id());
+        assert_eq!(to, sender_clock.device_id());
+        ack
+    };
+
+    // Only the second operation arrives; it waits for the first, so the
+    // acknowledgement stops below the first
+    let ack = acknowledge(vec![ops[1].clone()]);
+    handle_sync_message(&mut sender, &sender_clock, &registry, ack).unwrap();
+    assert_eq!(
+        stable_watermark(&sender, sender_clock.device_id()).unwrap(),
+        Some(HybridLogicalClock::from_timestamp(ops[0].timestamp - 1))
+    );
+
+    // An acknowledgement addressed to another device is ignored
+    let SyncMessage::Ack {
+        device_id,
+        version_vector,
+        ..
+    } = acknowledge(vec![ops[0].clone()])
+    else {
+        unreachable!()
+    };
+    let misaddressed = SyncMessage::Ack {
+        device_id,
+        to: Uuid::new_v4(),
+        version_vector: version_vector.clone(),
+    };
+    handle_sync_message(&mut sender, &sender_clock, &registry, misaddressed).unwrap();
+    assert_eq!(
+        stable_watermark(&sender, sender_clock.device_id()).unwrap(),
+        Some(HybridLogicalClock::from_timestamp(ops[0].timestamp - 1))
+    );
+
+    let ack = SyncMessage::Ack {
+        device_id,
+        to: sender_clock.device_id(),
+        version_vector,
+    };
+    handle_sync_message(&mut sender, &sender_clock, &registry, ack).unwrap();
+    assert_eq!(
+        stable_watermark(&sender, sender_clock.device_id()).unwrap(),
+        Some(HybridLogicalClock::from_timestamp(ops[1].timestamp))
+    );
+}
+
+#[test]
+fn test_request_sync_is_paged() {
+    use ahenk::crdt::{DeviceClock, MergeRegistry};
+    use ahenk::logic::sync::{handle_sync_message, SyncMessage, SYNC_PAGE_SIZE};
+
+    let mut phone = setup_empty_db();
+    let mut laptop = setup_empty_db();
+    let phone_clock = DeviceClock::new(Uuid::new_v4());
+    let laptop_clock = DeviceClock::new(Uuid::new_v4());
+    let registry = MergeRegistry::default();
+
+    let count = SYNC_PAGE_SIZE * 2 + 5;
+    for i in 0..count {
+        let op = logic::build_oplog_entry(
+            &phone,
+            &phone_clock,
+            "todos",
+            "create",
+            &serde_json::json!({"id": i}),
+        )
+        .unwrap();
+        ahenk::local_apply(&mut phone, &op).unwrap();
+    }
+
+    // The laptop asks, merges a page and asks the phone for the next one
+    // until the phone's reply says nothing more follows, then acknowledges
+    let mut tablet = setup_empty_db();
+    let tablet_clock = DeviceClock::new(Uuid::new_v4());
+    let mut message = Some(SyncMessage::RequestSync {
+        user_id: Uuid::new_v4(),
+        to: None,
+        after: None,
+        version_vector: operations::get_version_vector(&laptop).unwrap(),
+    });
+    let mut pages = Vec::new();
+    let mut cursor = None;
+    while let Some(msg) = message.take() {
+        if let SyncMessage::SyncData { entries, next, .. } = &msg {
+            if next.is_some() {
+                assert_eq!(*next, entries.last().map(|e| e.order_key()));
+            }
+            pages.push((entries.len(), next.is_some()));
+            cursor = *next;
+        }
+        message = match msg {
+            SyncMessage::RequestSync { to, after, .. } if after.is_some() => {
+                assert_eq!(to, Some(phone_clock.device_id()));
+                assert_eq!(after, cursor);
+                // Follow-ups are for the phone only
+                let ignored =
+                    handle_sync_message(&mut tablet, &tablet_clock, &registry, msg.clone());
+                assert!(ignored.unwrap().is_none());
+                handle_sync_message(&mut phone, &phone_clock, &registry, msg).unwrap()
+            }
+            SyncMessage::RequestSync { .. } => {
+                handle_sync_message(&mut phone, &phone_clock, &registry, msg).unwrap()
+            }
+            SyncMessage::SyncData { .. } => {
+                handle_sync_message(&mut laptop, &laptop_clock, &registry, msg).unwrap()
+            }
+            SyncMessage::Ack { .. } => None,
+            other => panic!("unexpected message {:?}", other),
+        };
+    }
+
+    assert_eq!(
+        pages,
+        vec![(SYNC_PAGE_SIZE, true), (SYNC_PAGE_SIZE, true), (5, false)]
+    );
+    assert_eq!(
+        operations::get_oplog_entries_since(&laptop, 0)
+            .unwrap()
+            .len(),
+        count
+    );
+}
+
+#[test]
+fn test_anti_entropy_repairs_lost_sync_data() {
+    use ahenk::crdt::{DeviceClock, MergeRegistry};
+    use ahenk::logic::sync::{handle_sync_message, reconcile_request, SyncMessage};
+    use std::collections::HashSet;
+
+    let mut phone = setup_empty_db();
+    let mut laptop = setup_empty_db();
+    let phone_clock = DeviceClock::new(Uuid::new_v4());
+    let laptop_clock = DeviceClock::new(Uuid::new_v4());
+    let registry = MergeRegistry::default();
+
+    let mut ops = Vec::new();
+    for i in 0..100 {
+        let mut op = logic::build_oplog_entry(
+            &phone,
+            &phone_clock,
+            "todos",
+            "create",
+            &serde_json::json!({"id": i}),
+        )
+        .unwrap();
+        // As written by a peer predating per-device dependencies
+        op.deps.clear();
+        ahenk::local_apply(&mut phone, &op).unwrap();
+        ops.push(op);
+    }
+
+    // The SyncData carrying ops 40..60 is lost; without dependencies on their
+    // predecessors, later ops advance the laptop's version vector past them,
+    // so a RequestSync cannot recover them
+    ahenk::merge(&mut laptop, &laptop_clock, &ops[..40]).unwrap();
+    ahenk::merge(&mut laptop, &laptop_clock, &ops[60..]).unwrap();
+    let version_vector = operations::get_version_vector(&laptop).unwrap();
+    let reply = handle_sync_message(
+        &mut phone,
+        &phone_clock,
+        &registry,
+        SyncMessage::RequestSync {
+            user_id: Uuid::new_v4(),
+            to: None,
+            after: None,
+            version_vector,
+        },
+    )
+    .unwrap();
+    assert!(matches!(reply, Some(SyncMessage::SyncData { entries, .. }) if entries.is_empty()));
+
+    let mut message = Some(reconcile_request(&laptop, &laptop_clock).unwrap());
+    let mut turn = 0;
+    while let Some(msg) = message.take() {
+        message = if turn % 2 == 0 {
+            handle_sync_message(&mut phone, &phone_clock, &registry, msg).unwrap()
+        } else {
+            handle_sync_message(&mut laptop, &laptop_clock, &registry, msg).unwrap()
+        };
+        turn += 1;
+        assert!(turn < 10, "reconciliation did not terminate");
+    }
+
+    let ids = |conn: &Connection| -> HashSet<Uuid> {
+        operations::get_oplog_entries_since(conn, 0)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.id)
+            .collect()
+    };
+    assert_eq!(ids(&laptop).len(), 100);
+    assert_eq!(ids(&laptop), ids(&phone));
+}
@@ -9,8 +9,10 @@
 use ahenk::db::migrations::{apply_migrations, get_current_version, get_migration_history};
 use ahenk::db::operations::{create_user, get_user};
 use ahenk::models::User;
+use ahenk::{DeviceClock, HybridLogicalClock, ManualClock};
 use chrono::Utc;
 use rusqlite::Connection;
+use std::sync::Arc;
 use uuid::Uuid;
 
 #[test]
@@ -23,7 +25,7 @@ fn test_fresh_database_migration() {
 
     // Verify schema version
     let version = get_current_version(&conn).unwrap();
-    assert_eq!(version, 1, "Fresh database should be at version 1");
+    assert_eq!(version, 20, "Fresh database should be at version 20");
 
     // Verify core tables exist by checking sqlite_master
     let table_count: i32 = conn
@@ -34,8 +36,12 @@ fn test_fresh_database_migration() {
         )
         .unwrap();
 
-    // We should have: users, devices, oplog, peers, schema_version = 5 tables
-    assert_eq!(table_count, 5, "Should have 5 tables in core sync schema");
+    // users, devices, oplog, peers, device_clock, oplog_quarantine,
+    // document_fields, document_tombstones, peer_acks, version_vector,
+    // oplog_pending, oplog_compacted, snapshots, undo_stack, conflicts,
+    // tracked_tables, change_capture_state, oplog_groups, oplog_tombstones,
+    // oplog_heads, schema_version
+    assert_eq!(table_count, 21, "Should have 21 tables in core sync schema");
 }
 
 #[test]
@@ -86,7 +92,7 @@ fn test_idempotent_migrations() {
             |row| row.get(0),
         )
         .unwrap();
-    assert_eq!(table_count, 5);
+    assert_eq!(table_count, 21);
 }
 
 #[test]
@@ -179,11 +185,27 @@ fn test_all_required_core_tables_exist() {
     apply_migrations(&conn).unwrap();
 
     let required_tables = vec![
-        "users",          // User authentication
-        "devices",        // Device management
-        "oplog",          // CRDT operation log
-        "peers",          // P2P peer tracking
-        "schema_version", // Migration tracking
+        "users",                // User authentication
+        "devices",              // Device management
+        "oplog",                // CRDT operation log
+        "peers",                // P2P peer tracking
+        "device_clock",         // Persistent HLC state
+        "oplog_quarantine",     // Rejected remote operations
+        "document_fields",      // Per-field merge clocks
+        "document_tombstones",  // Field-level merge deletes
+        "peer_acks",            // Compaction watermarks
+        "version_vector",       // Delta sync state
+        "oplog_pending",        // Causal delivery buffer
+        "oplog_compacted",      // Compacted operation IDs
+        "snapshots",            // Bootstrap checkpoints
+        "undo_stack",           // Per-device undo/redo
+        "conflicts",            // Concurrent write log
+        "tracked_tables",       // Change capture registrations
+        "change_capture_state", // Capture suppression flag
+        "oplog_groups",         // Tracked transaction groups
+        "oplog_tombstones",     // Compacted deletes
+        "oplog_heads",          // Latest ops on tracked entities
+        "schema_version",       // Migration tracking
     ];
 
     for table in required_tables {
@@ -287,4 +309,94 @@ fn test_oplog_table_structure() {
     assert!(columns.contains(&"table_name".to_string()));
     assert!(columns.contains(&"op_type".to_string()));
     assert!(columns.contains(&"data".to_string()));
+    assert!(columns.contains(&"deps".to_string()));
+    assert!(columns.contains(&"schema_version".to_string()));
+    assert!(columns.contains(&"entity_id".to_string()));
+
+    // Indexes for paginated and per-device/per-table/per-entity reads
+    let mut stmt = conn.prepare("PRAGMA index_list(oplog)").unwrap();
+    let indexes: Vec<String> = stmt
+        .query_map([], |row| row.get(1))
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    for index in [
+        "idx_oplog_order",
+        "idx_oplog_device",
+        "idx_oplog_table",
+        "idx_oplog_entity",
+    ] {
+        assert!(indexes.contains(&index.to_string()), "missing {}", index);
+    }
+}
+
+/// A version 1 database built by hand, with one operation of `device_id` on
+/// task `t1` stored with a legacy-encoded HLC
+fn legacy_database(device_id: Uuid, legacy: i64) -> Connection {
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute_batch(include_str!("../src/db/migrations/001_initial_schema.sql"))
+        .unwrap();
+    conn.execute_batch(
+        "CREATE TABLE schema_version (
+            version INTEGER PRIMARY KEY,
+            applied_at TEXT NOT NULL,
+            description TEXT NOT NULL
+        );
+        INSERT INTO schema_version VALUES (1, '2024-10-22T00:00:00Z', 'Initial schema');",
+    )
+    .unwrap();
+    conn.execute(
+        "INSERT INTO oplog (id, device_id, timestamp, table_name, op_type, data)
+         VALUES (?1, ?2, ?3, 'tasks', 'create', '{\"id\": \"t1\"}')",
+        rusqlite::params![Uuid::new_v4().to_string(), device_id.to_string(), legacy],
+    )
+    .unwrap();
+    conn
+}
+
+#[test]
+fn test_legacy_hlc_timestamps_are_reencoded() {
+    let written_at = Utc::now() - chrono::Duration::days(3);
+    let legacy = ((written_at.timestamp_micros() as u64) << 16 | 5) as i64;
+    let conn = legacy_database(Uuid::new_v4(), legacy);
+
+    apply_migrations(&conn).unwrap();
+
+    let timestamp: i64 = conn
+        .query_row("SELECT timestamp FROM oplog", [], |row| row.get(0))
+        .unwrap();
+    let hlc = HybridLogicalClock::from_timestamp(timestamp);
+
+    assert_eq!(hlc.physical_time(), written_at.timestamp_millis() as u64);
+    assert_eq!(hlc.counter(), 5);
+}
+
+#[test]
+fn test_upgraded_device_clock_resumes_from_the_oplog() {
+    let device_id = Uuid::new_v4();
+    let written_at = Utc::now() - chrono::Duration::days(3);
+    let legacy = ((written_at.timestamp_micros() as u64) << 16) as i64;
+    let conn = legacy_database(device_id, legacy);
+
+    apply_migrations(&conn).unwrap();
+
+    // The wall clock was set back a day after the operation was written
+    let rolled_back = ManualClock::new(written_at - chrono::Duration::days(1));
+    let clock = DeviceClock::with_clock(device_id, Arc::new(rolled_back));
+    let written: i64 = conn
+        .query_row("SELECT timestamp FROM oplog", [], |row| row.get(0))
+        .unwrap();
+    assert!(clock.tick(&conn).unwrap() > HybridLogicalClock::from_timestamp(written));
+}
+
+#[test]
+fn test_upgraded_oplog_is_indexed_by_entity() {
+    let conn = legacy_database(Uuid::new_v4(), 1 << 16);
+
+    apply_migrations(&conn).unwrap();
+
+    let entity_id: String = conn
+        .query_row("SELECT entity_id FROM oplog", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(entity_id, "t1");
 }